@@ -0,0 +1,131 @@
+//! Departures of observed daily values from the climatological baseline.
+//!
+//! The baseline is the smoothed normal of each day of year (see [`crate::normals`]);
+//! percentile ranks compare an observation with the unsmoothed baseline-period
+//! values recorded on the same day of year.
+
+use chrono::NaiveDate;
+use std::collections::HashMap;
+
+use crate::models::{AnomalySummary, DailyAnomaly, ElementAnomaly};
+use crate::normals::{self, NormalsTable, DAYS_IN_CYCLE, NORMAL_ELEMENTS};
+use crate::observations::{self, Element, Observation, Units};
+
+/// Baseline-period values per element and day of year, sorted ascending.
+pub struct Climatology {
+    values: HashMap<Element, Vec<Vec<f64>>>,
+}
+
+impl Climatology {
+    pub fn from_days<'a, I>(days: I) -> Climatology
+    where
+        I: IntoIterator<Item = &'a (NaiveDate, Vec<Observation>)>,
+    {
+        let mut values: HashMap<Element, Vec<Vec<f64>>> = NORMAL_ELEMENTS
+            .iter()
+            .map(|e| (e.clone(), vec![Vec::new(); DAYS_IN_CYCLE]))
+            .collect();
+
+        for (date, day_observations) in days {
+            let index = normals::day_of_year(*date) as usize - 1;
+            for (element, by_day) in values.iter_mut() {
+                if let Some(value) = observations::value_of(day_observations, element) {
+                    by_day[index].push(value);
+                }
+            }
+        }
+
+        for by_day in values.values_mut() {
+            by_day.iter_mut().for_each(|v| v.sort_by(f64::total_cmp));
+        }
+
+        Climatology { values }
+    }
+
+    /// Percentile rank (0-100) of `value` among the baseline values of the same
+    /// day of year, counting ties as half.
+    pub fn percentile_rank(&self, element: &Element, date: NaiveDate, value: f64) -> Option<f64> {
+        let sample = &self.values.get(element)?[normals::day_of_year(date) as usize - 1];
+        if sample.is_empty() {
+            return None;
+        }
+        let below = sample.partition_point(|v| *v < value);
+        let equal = sample[below..].partition_point(|v| *v <= value);
+        Some((below as f64 + equal as f64 / 2.0) / sample.len() as f64 * 100.0)
+    }
+}
+
+/// Running totals of one element over the requested range, in raw units.
+#[derive(Default)]
+struct Totals {
+    days: u32,
+    observed: f64,
+    normal: f64,
+    departure: f64,
+}
+
+/// Compare each day's observations with the baseline.
+///
+/// `observed` must be in date order; values are returned converted to `units`.
+pub fn departures(
+    observed: &[(NaiveDate, Vec<Observation>)],
+    baseline: &NormalsTable,
+    climatology: &Climatology,
+    units: Units,
+) -> (Vec<DailyAnomaly>, AnomalySummary) {
+    let mut totals: HashMap<Element, Totals> = HashMap::new();
+    let mut days_with_data = 0;
+
+    let days = observed
+        .iter()
+        .map(|(date, day_observations)| {
+            let mut anomaly = |element: &Element| -> Option<ElementAnomaly> {
+                let value = observations::value_of(day_observations, element)?;
+                let normal = baseline.value(element, *date)?;
+                let totals = totals.entry(element.clone()).or_default();
+                totals.days += 1;
+                totals.observed += value;
+                totals.normal += normal;
+                totals.departure += value - normal;
+                Some(ElementAnomaly {
+                    observed: element.convert(value, units),
+                    normal: element.convert(normal, units),
+                    departure: element.convert_delta(value - normal, units),
+                    cumulative_departure: element.convert_delta(totals.departure, units),
+                    percentile_rank: climatology.percentile_rank(element, *date, value),
+                })
+            };
+            let day = DailyAnomaly {
+                date: *date,
+                tmax: anomaly(&Element::Tmax),
+                tmin: anomaly(&Element::Tmin),
+                prcp: anomaly(&Element::Prcp),
+            };
+            if day.tmax.is_some() || day.tmin.is_some() || day.prcp.is_some() {
+                days_with_data += 1;
+            }
+            day
+        })
+        .collect();
+
+    let mean_departure = |element: &Element| {
+        totals
+            .get(element)
+            .map(|t| element.convert_delta(t.departure / t.days as f64, units))
+    };
+    let tmax_departure = mean_departure(&Element::Tmax);
+    let tmin_departure = mean_departure(&Element::Tmin);
+    let prcp = totals.get(&Element::Prcp);
+
+    let summary = AnomalySummary {
+        days_with_data,
+        tmax_departure,
+        tmin_departure,
+        mean_temperature_departure: tmax_departure.zip(tmin_departure).map(|(max, min)| (max + min) / 2.0),
+        prcp_observed: prcp.map(|t| Element::Prcp.convert(t.observed, units)),
+        prcp_normal: prcp.map(|t| Element::Prcp.convert(t.normal, units)),
+        prcp_percent_of_normal: prcp.and_then(|t| (t.normal > 0.0).then(|| t.observed / t.normal * 100.0)),
+    };
+
+    (days, summary)
+}
@@ -0,0 +1,43 @@
+//! Recently looked-up API keys, so requests do not each query `api_keys`.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::models::ApiKey;
+
+/// Keys found by hash, each kept for `ttl` after its lookup. Unknown keys are
+/// not kept, so a key created elsewhere works at once.
+pub struct KeyCache {
+    ttl: Duration,
+    keys: Mutex<HashMap<String, (Instant, ApiKey)>>,
+}
+
+impl KeyCache {
+    pub fn new(ttl: Duration) -> Self {
+        KeyCache {
+            ttl,
+            keys: Mutex::default(),
+        }
+    }
+
+    /// The key stored under `hash`, unless it was looked up too long ago.
+    pub fn get(&self, hash: &str) -> Option<ApiKey> {
+        let keys = self.keys.lock().unwrap_or_else(|e| e.into_inner());
+        keys.get(hash)
+            .filter(|(found_at, _)| found_at.elapsed() < self.ttl)
+            .map(|(_, key)| key.clone())
+    }
+
+    pub fn insert(&self, hash: String, key: ApiKey) {
+        let mut keys = self.keys.lock().unwrap_or_else(|e| e.into_inner());
+        keys.retain(|_, (found_at, _)| found_at.elapsed() < self.ttl);
+        keys.insert(hash, (Instant::now(), key));
+    }
+
+    /// Drop the key named `name`, e.g. once it is revoked.
+    pub fn forget(&self, name: &str) {
+        let mut keys = self.keys.lock().unwrap_or_else(|e| e.into_inner());
+        keys.retain(|_, (_, key)| key.name != name);
+    }
+}
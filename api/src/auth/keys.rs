@@ -0,0 +1,62 @@
+//! The `api_keys` table.
+
+use super::Role;
+use crate::models::{ApiKey, NewApiKey};
+
+const COLUMNS: &str = "name, role, prefix, rate_limit, created_at";
+
+fn from_row(row: &tokio_postgres::Row) -> ApiKey {
+    let role: &str = row.get(1);
+    let rate_limit: Option<i32> = row.get(3);
+    ApiKey {
+        name: row.get(0),
+        // The table only admits known roles; anything else gets the least access
+        role: Role::parse(role).unwrap_or(Role::Read),
+        prefix: row.get(2),
+        rate_limit: rate_limit.map(|limit| limit as u32),
+        created_at: row.get(4),
+    }
+}
+
+pub async fn list(client: &tokio_postgres::Client) -> Result<Vec<ApiKey>, tokio_postgres::Error> {
+    let rows = client
+        .query(&format!("SELECT {} FROM api_keys ORDER BY name", COLUMNS), &[])
+        .await?;
+    Ok(rows.iter().map(from_row).collect())
+}
+
+/// The key with the SHA-256 hash `hash`.
+pub async fn find(client: &tokio_postgres::Client, hash: &str) -> Result<Option<ApiKey>, tokio_postgres::Error> {
+    let row = client
+        .query_opt(&format!("SELECT {} FROM api_keys WHERE key_hash = $1", COLUMNS), &[&hash])
+        .await?;
+    Ok(row.as_ref().map(from_row))
+}
+
+/// Store a key by its hash; returns `None` when the name is taken.
+pub async fn insert(
+    client: &tokio_postgres::Client,
+    key: &NewApiKey,
+    prefix: &str,
+    hash: &str,
+) -> Result<Option<ApiKey>, tokio_postgres::Error> {
+    let rate_limit = key.rate_limit.map(|limit| limit.min(i32::MAX as u32) as i32);
+    let row = client
+        .query_opt(
+            &format!(
+                "INSERT INTO api_keys (name, key_hash, prefix, role, rate_limit)
+                 VALUES ($1, $2, $3, $4, $5)
+                 ON CONFLICT (name) DO NOTHING
+                 RETURNING {}",
+                COLUMNS
+            ),
+            &[&key.name, &hash, &prefix, &key.role.as_str(), &rate_limit],
+        )
+        .await?;
+    Ok(row.as_ref().map(from_row))
+}
+
+pub async fn delete(client: &tokio_postgres::Client, name: &str) -> Result<bool, tokio_postgres::Error> {
+    let deleted = client.execute("DELETE FROM api_keys WHERE name = $1", &[&name]).await?;
+    Ok(deleted > 0)
+}
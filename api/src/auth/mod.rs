@@ -0,0 +1,285 @@
+//! API key authentication and per-key rate limits.
+//!
+//! Clients send a key as `Authorization: Bearer <key>` or `X-API-Key: <key>`.
+//! Keys are random, so only their SHA-256 hash is stored (in `api_keys`), and
+//! each has a [`Role`]: `read` keys call the GET data endpoints, `admin` keys
+//! everything, including the registry, normals computation and key management.
+//! Every key draws from its own token bucket, refilled at the key's rate or the
+//! server default. Keys found are remembered for [`KEY_CACHE_TTL`]; revoking
+//! one over HTTP forgets it at once, while a key revoked with `api keys revoke`
+//! is accepted by running servers until its entry expires.
+//!
+//! The first admin key is made with `api keys create`, which writes to the
+//! database directly.
+
+mod key_cache;
+pub mod keys;
+mod rate_limit;
+
+use anyhow::{bail, Result};
+use axum::{
+    extract::{Path, Request, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use clap::{Args, Subcommand, ValueEnum};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use std::time::Duration;
+use utoipa::ToSchema;
+
+use crate::config::AuthConfig;
+use crate::db::DbPool;
+use crate::error::ApiError;
+use crate::models::{CreatedApiKey, NewApiKey};
+use crate::repository::{self, ClimateRepository, PostgresRepository};
+use key_cache::KeyCache;
+use rate_limit::RateLimiter;
+
+/// Start of every key, so leaked keys are easy to recognise.
+const KEY_PREFIX: &str = "ck_";
+
+/// Random bytes in a key.
+const KEY_BYTES: usize = 24;
+
+/// Characters of a key kept in the clear to tell keys apart.
+const SHOWN_PREFIX_LEN: usize = KEY_PREFIX.len() + 8;
+
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+/// How long a key found in `api_keys` is trusted without looking it up again.
+pub const KEY_CACHE_TTL: Duration = Duration::from_secs(30);
+
+/// What a key may call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, ToSchema, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// The GET data endpoints
+    Read,
+    /// Every endpoint
+    Admin,
+}
+
+impl Role {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Role::Read => "read",
+            Role::Admin => "admin",
+        }
+    }
+
+    pub fn parse(role: &str) -> Option<Role> {
+        match role {
+            "read" => Some(Role::Read),
+            "admin" => Some(Role::Admin),
+            _ => None,
+        }
+    }
+}
+
+/// A new random key.
+pub fn generate_key() -> String {
+    let mut bytes = [0u8; KEY_BYTES];
+    getrandom::fill(&mut bytes).expect("the operating system provides random bytes");
+    let random: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    format!("{}{}", KEY_PREFIX, random)
+}
+
+/// The hash a key is stored and looked up by.
+pub fn hash_key(key: &str) -> String {
+    Sha256::digest(key.as_bytes()).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Generate and store a key; returns `None` when the name is taken.
+pub async fn create_key<R: ClimateRepository>(
+    repo: &R,
+    new_key: &NewApiKey,
+) -> repository::Result<Option<CreatedApiKey>> {
+    let key = generate_key();
+    let stored = repo
+        .insert_api_key(new_key, &key[..SHOWN_PREFIX_LEN], &hash_key(&key))
+        .await?;
+    Ok(stored.map(|details| CreatedApiKey { key, details }))
+}
+
+/// State of [`authenticate`]: where keys are looked up, the keys found
+/// recently, their buckets, and the role the routes behind it require.
+#[derive(Clone)]
+pub struct Auth<R> {
+    repo: R,
+    keys: Arc<KeyCache>,
+    limiter: Arc<RateLimiter>,
+    enabled: bool,
+    rate_limit: u32,
+    role: Role,
+}
+
+impl<R: ClimateRepository> Auth<R> {
+    pub fn new(repo: R, config: &AuthConfig) -> Self {
+        Auth {
+            repo,
+            keys: Arc::new(KeyCache::new(KEY_CACHE_TTL)),
+            limiter: Arc::new(RateLimiter::new(config.rate_limit_burst)),
+            enabled: !config.disabled,
+            rate_limit: config.rate_limit,
+            role: Role::Read,
+        }
+    }
+
+    /// The same keys and buckets, admitting only keys of at least `role`.
+    pub fn require(&self, role: Role) -> Self {
+        Auth { role, ..self.clone() }
+    }
+}
+
+/// Middleware admitting requests with a key of the required role and
+/// tokens left in its bucket.
+pub async fn authenticate<R: ClimateRepository>(State(auth): State<Auth<R>>, request: Request, next: Next) -> Response {
+    if !auth.enabled {
+        return next.run(request).await;
+    }
+    let Some(key) = presented_key(request.headers()) else {
+        return unauthorized("missing_api_key", "An API key is required; send it as Authorization: Bearer <key>");
+    };
+
+    let hash = hash_key(key);
+    let found = match auth.keys.get(&hash) {
+        Some(found) => Some(found),
+        None => match auth.repo.find_api_key(&hash).await {
+            Ok(found) => {
+                if let Some(found) = &found {
+                    auth.keys.insert(hash, found.clone());
+                }
+                found
+            }
+            Err(e) => {
+                tracing::error!("Failed to look up API key: {}", e);
+                return ApiError::from(e).into_response();
+            }
+        },
+    };
+    let Some(found) = found else {
+        return unauthorized("invalid_api_key", "The API key is not valid");
+    };
+    if found.role < auth.role {
+        return ApiError::new(
+            StatusCode::FORBIDDEN,
+            "forbidden",
+            format!("This endpoint requires a key with the {} role", auth.role.as_str()),
+        )
+        .into_response();
+    }
+
+    let rate_limit = found.rate_limit.unwrap_or(auth.rate_limit);
+    if let Err(wait) = auth.limiter.acquire(&found.name, rate_limit) {
+        let mut response = ApiError::new(
+            StatusCode::TOO_MANY_REQUESTS,
+            "rate_limited",
+            format!("Rate limit of {} requests per minute exceeded", rate_limit),
+        )
+        .into_response();
+        let seconds = wait.as_secs_f64().ceil().max(1.0) as u64;
+        response.headers_mut().insert(header::RETRY_AFTER, HeaderValue::from(seconds));
+        return response;
+    }
+
+    next.run(request).await
+}
+
+/// Middleware forgetting the key named in the path once it is deleted, so it
+/// is refused from the next request on.
+pub async fn forget_on_revoke<R: ClimateRepository>(
+    State(auth): State<Auth<R>>,
+    Path(name): Path<String>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let response = next.run(request).await;
+    if response.status().is_success() {
+        auth.keys.forget(&name);
+    }
+    response
+}
+
+/// The key of `Authorization: Bearer` or, failing that, `X-API-Key`.
+fn presented_key(headers: &HeaderMap) -> Option<&str> {
+    let bearer = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split_once(' '))
+        .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("bearer"))
+        .map(|(_, key)| key.trim());
+    bearer
+        .or_else(|| headers.get(API_KEY_HEADER).and_then(|value| value.to_str().ok()))
+        .filter(|key| !key.is_empty())
+}
+
+fn unauthorized(code: &'static str, detail: &str) -> Response {
+    let mut response = ApiError::new(StatusCode::UNAUTHORIZED, code, detail).into_response();
+    response
+        .headers_mut()
+        .insert(header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+    response
+}
+
+#[derive(Debug, Args)]
+pub struct KeysArgs {
+    #[command(subcommand)]
+    command: KeysCommand,
+}
+
+#[derive(Debug, Subcommand)]
+enum KeysCommand {
+    /// Create a key and print it; it cannot be shown again
+    Create {
+        /// Unique name of the key, e.g. the client using it
+        name: String,
+        #[arg(long, value_enum, default_value_t = Role::Read)]
+        role: Role,
+        /// Requests per minute, instead of the server default
+        #[arg(long)]
+        rate_limit: Option<u32>,
+    },
+    /// List the keys, without the keys themselves
+    List,
+    /// Delete a key
+    Revoke { name: String },
+}
+
+pub async fn run(args: KeysArgs, pool: DbPool) -> Result<()> {
+    let repo = PostgresRepository::new(pool);
+    match args.command {
+        KeysCommand::Create { name, role, rate_limit } => {
+            if rate_limit == Some(0) {
+                bail!("--rate-limit must be greater than 0");
+            }
+            let new_key = NewApiKey { name, role, rate_limit };
+            match create_key(&repo, &new_key).await? {
+                Some(created) => println!("{}", created.key),
+                None => bail!("a key named {} already exists", new_key.name),
+            }
+        }
+        KeysCommand::List => {
+            for key in repo.list_api_keys().await? {
+                let rate_limit = key.rate_limit.map_or("default".to_string(), |limit| format!("{}/min", limit));
+                println!(
+                    "{}\t{}\t{}...\t{}\t{}",
+                    key.name,
+                    key.role.as_str(),
+                    key.prefix,
+                    rate_limit,
+                    key.created_at.format("%Y-%m-%d %H:%M:%S UTC")
+                );
+            }
+        }
+        KeysCommand::Revoke { name } => {
+            if !repo.delete_api_key(&name).await? {
+                bail!("no key named {}", name);
+            }
+            tracing::info!("Revoked key {}", name);
+        }
+    }
+    Ok(())
+}
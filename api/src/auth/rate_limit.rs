@@ -0,0 +1,47 @@
+//! Token buckets limiting the request rate of each API key.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// One bucket per key, refilled continuously at the key's rate and holding at
+/// most `burst` requests (or a minute's worth, when less).
+pub struct RateLimiter {
+    burst: u32,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(burst: u32) -> Self {
+        RateLimiter {
+            burst,
+            buckets: Mutex::default(),
+        }
+    }
+
+    /// Take a request from the bucket of `key`, refilled at `per_minute`.
+    /// When it is empty, returns how long until the next request is allowed.
+    pub fn acquire(&self, key: &str, per_minute: u32) -> Result<(), Duration> {
+        let per_second = f64::from(per_minute.max(1)) / 60.0;
+        let capacity = f64::from(self.burst.clamp(1, per_minute.max(1)));
+        let now = Instant::now();
+
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        let bucket = buckets.entry(key.to_string()).or_insert(Bucket { tokens: capacity, updated: now });
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * per_second).min(capacity);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / per_second))
+        }
+    }
+}
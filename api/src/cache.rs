@@ -0,0 +1,369 @@
+//! In-memory cache of aggregate responses.
+//!
+//! Historical aggregates rarely change, so the responses of the aggregate
+//! endpoints are kept and served again until the data behind them changes.
+//! Entries are keyed by the path, the parsed query parameters (so parameter
+//! order, encoding and spelled-out defaults do not matter), the negotiated
+//! [`Format`] and the current UTC date, which defaults such as `as_of` depend
+//! on. They expire after a TTL, and the least recently used ones are evicted
+//! beyond a size limit.
+//!
+//! `api ingest` notifies [`CHANGES_CHANNEL`] with every location it writes;
+//! the server [`listen`]s on it and drops the entries of that location, along
+//! with those not tied to a location (`get_locations`). Writes through the
+//! API, such as computing normals or editing the location registry, clear the
+//! cache.
+//!
+//! Cached responses carry an `ETag` and a `Last-Modified` (when they were
+//! computed), and [`conditional`] answers matching conditional GETs with 304.
+
+use axum::{
+    body::{to_bytes, Body, Bytes},
+    extract::{FromRequestParts, Request, State},
+    http::{header, request::Parts, HeaderMap, HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, SubsecRound, Utc};
+use futures_util::StreamExt;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tokio_postgres::AsyncMessage;
+
+use crate::db::TlsConnector;
+use crate::config::CacheConfig;
+use crate::error::ApiError;
+use crate::export::Format;
+use crate::extract::Query;
+
+/// Postgres notification channel `api ingest` announces written locations on.
+pub const CHANGES_CHANNEL: &str = "daily_changed";
+
+/// Wait before reconnecting the notification listener.
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Part of the cache a single response may take; larger ones are not stored.
+const MAX_ENTRY_FRACTION: usize = 8;
+
+/// Responses shared by every request handler; cloning is cheap.
+#[derive(Clone)]
+pub struct ResponseCache {
+    inner: Arc<Mutex<Inner>>,
+    max_bytes: usize,
+    ttl: Duration,
+}
+
+#[derive(Default)]
+struct Inner {
+    entries: HashMap<String, Entry>,
+    /// Keys by last use, least recent first
+    recency: BTreeMap<u64, String>,
+    clock: u64,
+    bytes: usize,
+    /// Bumped by every invalidation, so responses computed before one are not stored
+    generation: u64,
+}
+
+struct Entry {
+    headers: HeaderMap,
+    body: Bytes,
+    location: Option<String>,
+    stored: Instant,
+    used: u64,
+    size: usize,
+}
+
+impl Inner {
+    fn remove(&mut self, key: &str) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.recency.remove(&entry.used);
+            self.bytes -= entry.size;
+        }
+    }
+
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+}
+
+impl ResponseCache {
+    pub fn new(max_bytes: usize, ttl: Duration) -> Self {
+        ResponseCache {
+            inner: Arc::default(),
+            max_bytes,
+            ttl,
+        }
+    }
+
+    pub fn from_config(config: &CacheConfig) -> Self {
+        ResponseCache::new(config.size_mb * 1024 * 1024, Duration::from_secs(config.ttl_secs))
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.max_bytes > 0
+    }
+
+    /// Drop the responses computed from the rows of `location`, and those not
+    /// tied to a location.
+    pub fn invalidate(&self, location: &str) {
+        let mut inner = self.lock();
+        inner.generation += 1;
+        let stale: Vec<String> = inner
+            .entries
+            .iter()
+            .filter(|(_, entry)| entry.location.as_deref().is_none_or(|l| l == location))
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in stale {
+            inner.remove(&key);
+        }
+    }
+
+    pub fn clear(&self) {
+        let mut inner = self.lock();
+        inner.generation += 1;
+        inner.entries.clear();
+        inner.recency.clear();
+        inner.bytes = 0;
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Inner> {
+        // Every update leaves the maps consistent, so a panic elsewhere does not matter
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn get(&self, key: &str) -> Option<Response> {
+        let mut inner = self.lock();
+        let entry = inner.entries.get(key)?;
+        if entry.stored.elapsed() >= self.ttl {
+            inner.remove(key);
+            return None;
+        }
+        let previous = entry.used;
+        let used = inner.tick();
+        inner.recency.remove(&previous);
+        inner.recency.insert(used, key.to_string());
+        let entry = inner.entries.get_mut(key)?;
+        entry.used = used;
+
+        let mut response = Response::new(Body::from(entry.body.clone()));
+        *response.headers_mut() = entry.headers.clone();
+        Some(response)
+    }
+
+    fn generation(&self) -> u64 {
+        self.lock().generation
+    }
+
+    /// Store a response unless the cache was invalidated since `generation`.
+    fn insert(&self, key: String, location: Option<String>, generation: u64, headers: HeaderMap, body: Bytes) {
+        let size = key.len() + body.len();
+        if size > self.max_bytes / MAX_ENTRY_FRACTION {
+            return;
+        }
+        let mut inner = self.lock();
+        if inner.generation != generation {
+            return;
+        }
+        inner.remove(&key);
+        while inner.bytes + size > self.max_bytes {
+            let Some((_, oldest)) = inner.recency.pop_first() else { break };
+            inner.remove(&oldest);
+        }
+        let used = inner.tick();
+        inner.recency.insert(used, key.clone());
+        inner.bytes += size;
+        inner.entries.insert(
+            key,
+            Entry {
+                headers,
+                body,
+                location,
+                stored: Instant::now(),
+                used,
+                size,
+            },
+        );
+    }
+}
+
+/// Middleware serving the GET (and HEAD) requests of an endpoint taking `P` from the
+/// cache, and storing its successful responses.
+///
+/// Requests whose parameters do not parse are passed on, for the handler to
+/// reject.
+pub async fn responses<P>(State(cache): State<ResponseCache>, request: Request, next: Next) -> Response
+where
+    P: DeserializeOwned + Serialize,
+{
+    if !matches!(*request.method(), Method::GET | Method::HEAD) {
+        return next.run(request).await;
+    }
+    let (mut parts, body) = request.into_parts();
+    let Some((key, location)) = cache_key::<P>(&mut parts).await else {
+        return next.run(Request::from_parts(parts, body)).await;
+    };
+    if let Some(response) = cache.get(&key) {
+        return response;
+    }
+
+    let generation = cache.generation();
+    let response = next.run(Request::from_parts(parts, body)).await;
+    if response.status() != StatusCode::OK {
+        return response;
+    }
+    let (mut parts, body) = response.into_parts();
+    let body = match to_bytes(body, usize::MAX).await {
+        Ok(body) => body,
+        Err(e) => {
+            tracing::error!("Failed to read response to cache: {}", e);
+            return ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "export_failed", "The response could not be encoded")
+                .into_response();
+        }
+    };
+
+    let digest = Sha256::digest(&body);
+    let etag: String = digest[..16].iter().map(|b| format!("{:02x}", b)).collect();
+    let modified = Utc::now().trunc_subsecs(0);
+    let headers = &mut parts.headers;
+    headers.insert(header::ETAG, HeaderValue::try_from(format!("\"{}\"", etag)).expect("hex is a valid header"));
+    headers.insert(
+        header::LAST_MODIFIED,
+        HeaderValue::try_from(modified.format("%a, %d %b %Y %H:%M:%S GMT").to_string()).expect("dates are valid headers"),
+    );
+    // Stored by clients, but revalidated before each use
+    headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+
+    cache.insert(key, location, generation, parts.headers.clone(), body.clone());
+    Response::from_parts(parts, Body::from(body))
+}
+
+/// The normalized key of a request and the location it reads, or `None` when
+/// its parameters are invalid.
+async fn cache_key<P>(parts: &mut Parts) -> Option<(String, Option<String>)>
+where
+    P: DeserializeOwned + Serialize,
+{
+    let Query(params) = Query::<P>::try_from_uri(&parts.uri).ok()?;
+    let format = Format::from_request_parts(parts, &()).await.ok()?;
+    let params = serde_json::to_value(params).ok()?;
+    let location = params.get("location").and_then(Value::as_str).map(str::to_string);
+    let key = format!(
+        "{} {} {} {}",
+        parts.uri.path(),
+        format.extension(),
+        Utc::now().date_naive(),
+        params
+    );
+    Some((key, location))
+}
+
+/// Middleware clearing the cache after a successful write.
+pub async fn clear_on_write(State(cache): State<ResponseCache>, request: Request, next: Next) -> Response {
+    let safe = matches!(*request.method(), Method::GET | Method::HEAD);
+    let response = next.run(request).await;
+    if !safe && response.status().is_success() {
+        cache.clear();
+    }
+    response
+}
+
+/// Middleware answering conditional GETs with 304 Not Modified when the
+/// response has not changed: its `ETag` matches `If-None-Match` or, without
+/// one, its `Last-Modified` is not after `If-Modified-Since`.
+pub async fn conditional(request: Request, next: Next) -> Response {
+    let safe = matches!(*request.method(), Method::GET | Method::HEAD);
+    let if_none_match = request.headers().get(header::IF_NONE_MATCH).cloned();
+    let if_modified_since = request.headers().get(header::IF_MODIFIED_SINCE).cloned();
+    let response = next.run(request).await;
+    if !safe || response.status() != StatusCode::OK {
+        return response;
+    }
+
+    let headers = response.headers();
+    let unchanged = match (if_none_match, headers.get(header::ETAG)) {
+        (Some(tags), Some(etag)) => etag_matches(&tags, etag),
+        (Some(_), None) => false,
+        (None, _) => match (if_modified_since, headers.get(header::LAST_MODIFIED)) {
+            (Some(since), Some(modified)) => http_date(&since)
+                .zip(http_date(modified))
+                .is_some_and(|(since, modified)| modified <= since),
+            _ => false,
+        },
+    };
+    if !unchanged {
+        return response;
+    }
+
+    let mut not_modified = StatusCode::NOT_MODIFIED.into_response();
+    for name in [header::ETAG, header::LAST_MODIFIED, header::CACHE_CONTROL, header::VARY] {
+        if let Some(value) = headers.get(&name) {
+            not_modified.headers_mut().insert(name, value.clone());
+        }
+    }
+    not_modified
+}
+
+/// Weak comparison of an `If-None-Match` list with an entity tag.
+fn etag_matches(tags: &HeaderValue, etag: &HeaderValue) -> bool {
+    let (Ok(tags), Ok(etag)) = (tags.to_str(), etag.to_str()) else {
+        return false;
+    };
+    let opaque = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+    tags.split(',').any(|tag| tag.trim() == "*" || opaque(tag) == opaque(etag))
+}
+
+fn http_date(value: &HeaderValue) -> Option<DateTime<Utc>> {
+    let value = value.to_str().ok()?;
+    DateTime::parse_from_rfc2822(value).ok().map(|date| date.to_utc())
+}
+
+/// Drop the entries of the locations announced on [`CHANGES_CHANNEL`], for as
+/// long as the server runs, reconnecting when the connection is lost.
+pub async fn listen(config: tokio_postgres::Config, tls: TlsConnector, cache: ResponseCache) {
+    loop {
+        match listen_once(&config, &tls, &cache).await {
+            Ok(()) => tracing::warn!("Change notification connection closed"),
+            Err(e) => tracing::warn!("Change notification connection failed: {}", e),
+        }
+        tokio::time::sleep(RECONNECT_DELAY).await;
+    }
+}
+
+async fn listen_once(
+    config: &tokio_postgres::Config,
+    tls: &TlsConnector,
+    cache: &ResponseCache,
+) -> Result<(), tokio_postgres::Error> {
+    let (client, mut connection) = config.connect(tls.clone()).await?;
+
+    // Notifications only arrive while the connection is polled
+    let (sender, mut locations) = mpsc::unbounded_channel();
+    let connection = tokio::spawn(async move {
+        let mut messages = futures_util::stream::poll_fn(move |cx| connection.poll_message(cx));
+        while let Some(message) = messages.next().await {
+            if let AsyncMessage::Notification(notification) = message? {
+                let _ = sender.send(notification.payload().to_string());
+            }
+        }
+        Ok::<_, tokio_postgres::Error>(())
+    });
+
+    client.batch_execute(&format!("LISTEN {}", CHANGES_CHANNEL)).await?;
+    // Rows written while nobody listened may have changed any response
+    cache.clear();
+    tracing::info!("Listening for data changes on {}", CHANGES_CHANNEL);
+
+    while let Some(location) = locations.recv().await {
+        tracing::debug!("Data of {} changed, dropping its cached responses", location);
+        cache.invalidate(&location);
+    }
+    drop(client);
+    connection.await.unwrap_or(Ok(()))
+}
@@ -0,0 +1,444 @@
+//! Settings of the server and the commands.
+//!
+//! Each setting has a default, which the TOML file (`--config`, `API_CONFIG`,
+//! or `api.toml` when present) overrides, then its environment variable, then
+//! its command-line flag. The result is checked as a whole before anything
+//! starts, so every problem is reported at once with the setting it concerns.
+
+use anyhow::{bail, Context, Result};
+use clap::{Args, Subcommand, ValueEnum};
+use serde::{Deserialize, Serialize, Serializer};
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::{Path, PathBuf};
+
+use crate::db;
+
+/// Read when `--config` is not given, if it exists.
+const DEFAULT_CONFIG_FILE: &str = "api.toml";
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub database: DatabaseConfig,
+    pub server: ServerConfig,
+    pub cache: CacheConfig,
+    pub auth: AuthConfig,
+    pub log: LogConfig,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    /// Required by every command using the database
+    pub host: Option<String>,
+    pub port: u16,
+    pub name: String,
+    pub user: Option<String>,
+    #[serde(serialize_with = "redact")]
+    pub password: Option<String>,
+    /// Seconds a statement may run before Postgres cancels it; the server
+    /// defaults to `server.request_timeout_secs`, other commands to none
+    pub statement_timeout_secs: Option<u64>,
+    pub pool: PoolConfig,
+    pub tls: TlsConfig,
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        DatabaseConfig {
+            host: None,
+            port: 5432,
+            name: "mcpdb".to_string(),
+            user: None,
+            password: None,
+            statement_timeout_secs: None,
+            pool: PoolConfig::default(),
+            tls: TlsConfig::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PoolConfig {
+    /// Most connections open at once
+    pub max_size: usize,
+    /// Seconds a request waits for a free connection before failing with 503
+    pub wait_timeout_secs: u64,
+    /// Seconds allowed to open a connection
+    pub connect_timeout_secs: u64,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        PoolConfig {
+            max_size: 16,
+            wait_timeout_secs: 5,
+            connect_timeout_secs: 10,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsConfig {
+    pub mode: SslMode,
+    /// PEM bundle of the certificate authorities to trust
+    pub ca_file: Option<PathBuf>,
+    /// PEM client certificate, with `key_file`
+    pub cert_file: Option<PathBuf>,
+    pub key_file: Option<PathBuf>,
+}
+
+/// Whether and how connections to Postgres use TLS, as libpq's `sslmode`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum SslMode {
+    /// Never
+    #[default]
+    Disable,
+    /// When the server supports it
+    Prefer,
+    /// Always, without checking the server's certificate
+    Require,
+    /// Always, checking the certificate and that it names the host
+    VerifyFull,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub host: IpAddr,
+    pub port: u16,
+    /// Origins whose pages may call the API from a browser; `*` for any
+    pub cors_origins: Vec<String>,
+    /// Seconds a request may take to respond before failing with 504
+    pub request_timeout_secs: u64,
+    /// Seconds the requests in flight are given to finish on shutdown
+    pub shutdown_timeout_secs: u64,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            host: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            port: 3000,
+            cors_origins: vec!["*".to_string()],
+            request_timeout_secs: 30,
+            shutdown_timeout_secs: 30,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheConfig {
+    /// Memory for cached responses in MiB; 0 disables the cache
+    pub size_mb: usize,
+    /// Seconds a cached response is served before it is computed again
+    pub ttl_secs: u64,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfig {
+            size_mb: 64,
+            ttl_secs: 3600,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    /// Serve every route without an API key (for local development)
+    pub disabled: bool,
+    /// Requests per minute of keys without a rate limit of their own
+    pub rate_limit: u32,
+    /// Requests a key may send at once before being held to its rate
+    pub rate_limit_burst: u32,
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        AuthConfig {
+            disabled: false,
+            rate_limit: 600,
+            rate_limit_burst: 60,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    pub format: LogFormat,
+    /// `tracing` filter directives, e.g. `api=debug,tower_http=info`
+    pub filter: String,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        LogConfig {
+            format: LogFormat::Text,
+            filter: "api=debug,tower_http=debug".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Human-readable lines
+    #[default]
+    Text,
+    /// One JSON object per event
+    Json,
+}
+
+fn redact<S: Serializer>(secret: &Option<String>, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+    match secret {
+        Some(_) => serializer.serialize_str("********"),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// Flags overriding the configuration file, each with its environment variable.
+#[derive(Debug, Clone, Args)]
+pub struct ConfigArgs {
+    /// TOML configuration file [default: api.toml, if present]
+    #[arg(long = "config", env = "API_CONFIG", global = true)]
+    pub file: Option<PathBuf>,
+
+    #[arg(long, env = "DB_HOST", global = true, help_heading = "Database")]
+    db_host: Option<String>,
+    #[arg(long, env = "DB_PORT", global = true, help_heading = "Database")]
+    db_port: Option<u16>,
+    #[arg(long, env = "DB_NAME", global = true, help_heading = "Database")]
+    db_name: Option<String>,
+    #[arg(long, env = "DB_USER", global = true, help_heading = "Database")]
+    db_user: Option<String>,
+    #[arg(long, env = "DB_PASSWORD", hide_env_values = true, global = true, help_heading = "Database")]
+    db_password: Option<String>,
+    /// Most connections open at once
+    #[arg(long, env = "DB_POOL_SIZE", global = true, help_heading = "Database")]
+    db_pool_size: Option<usize>,
+    /// Seconds a request waits for a free connection
+    #[arg(long, env = "DB_POOL_TIMEOUT_SECS", global = true, help_heading = "Database")]
+    db_pool_timeout: Option<u64>,
+    /// Seconds allowed to open a connection
+    #[arg(long, env = "DB_CONNECT_TIMEOUT_SECS", global = true, help_heading = "Database")]
+    db_connect_timeout: Option<u64>,
+    /// Seconds a statement may run [default: the request timeout when serving]
+    #[arg(long, env = "DB_STATEMENT_TIMEOUT_SECS", global = true, help_heading = "Database")]
+    db_statement_timeout: Option<u64>,
+    #[arg(long, env = "DB_SSLMODE", value_enum, global = true, help_heading = "Database")]
+    db_sslmode: Option<SslMode>,
+    /// PEM bundle of the certificate authorities to trust
+    #[arg(long, env = "DB_SSL_ROOT_CERT", global = true, help_heading = "Database")]
+    db_ssl_root_cert: Option<PathBuf>,
+    /// PEM client certificate
+    #[arg(long, env = "DB_SSL_CERT", global = true, help_heading = "Database")]
+    db_ssl_cert: Option<PathBuf>,
+    /// PEM key of the client certificate
+    #[arg(long, env = "DB_SSL_KEY", global = true, help_heading = "Database")]
+    db_ssl_key: Option<PathBuf>,
+
+    /// Address to listen on
+    #[arg(long, env = "SERVER_HOST", global = true, help_heading = "Server")]
+    host: Option<IpAddr>,
+    #[arg(long, env = "SERVER_PORT", global = true, help_heading = "Server")]
+    port: Option<u16>,
+    /// Origins allowed to call the API from a browser, comma-separated; `*` for any
+    #[arg(long = "cors-origin", env = "CORS_ORIGINS", value_delimiter = ',', global = true, help_heading = "Server")]
+    cors_origins: Option<Vec<String>>,
+    /// Seconds a request may take to respond
+    #[arg(long, env = "REQUEST_TIMEOUT_SECS", global = true, help_heading = "Server")]
+    request_timeout: Option<u64>,
+    /// Seconds the requests in flight are given to finish on shutdown
+    #[arg(long, env = "SHUTDOWN_TIMEOUT_SECS", global = true, help_heading = "Server")]
+    shutdown_timeout: Option<u64>,
+
+    /// Memory for cached responses in MiB; 0 disables the cache
+    #[arg(long = "cache-size", env = "CACHE_SIZE_MB", global = true, help_heading = "Server")]
+    cache_size: Option<usize>,
+    /// Seconds a cached response is served before it is computed again
+    #[arg(long = "cache-ttl", env = "CACHE_TTL_SECS", global = true, help_heading = "Server")]
+    cache_ttl: Option<u64>,
+
+    /// Serve every route without an API key (for local development)
+    #[arg(
+        long,
+        env = "DISABLE_AUTH",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        global = true,
+        help_heading = "Server"
+    )]
+    disable_auth: Option<bool>,
+    /// Requests per minute of keys without a rate limit of their own
+    #[arg(long, env = "RATE_LIMIT", global = true, help_heading = "Server")]
+    rate_limit: Option<u32>,
+    /// Requests a key may send at once before being held to its rate
+    #[arg(long, env = "RATE_LIMIT_BURST", global = true, help_heading = "Server")]
+    rate_limit_burst: Option<u32>,
+
+    #[arg(long, env = "LOG_FORMAT", value_enum, global = true, help_heading = "Logging")]
+    log_format: Option<LogFormat>,
+    /// `tracing` filter directives, e.g. `api=debug,tower_http=info`
+    #[arg(long, env = "RUST_LOG", global = true, help_heading = "Logging")]
+    log_filter: Option<String>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ConfigCommand {
+    /// Validate the configuration and print it with every layer applied
+    Check,
+}
+
+impl Config {
+    /// Read the configuration file, apply the environment and flags, and validate.
+    pub fn load(args: &ConfigArgs) -> Result<Config> {
+        let mut config = match &args.file {
+            Some(path) => Config::read(path)?,
+            None if Path::new(DEFAULT_CONFIG_FILE).exists() => Config::read(Path::new(DEFAULT_CONFIG_FILE))?,
+            None => Config::default(),
+        };
+        config.apply(args.clone());
+
+        let problems = config.problems();
+        if !problems.is_empty() {
+            bail!("invalid configuration:\n  - {}", problems.join("\n  - "));
+        }
+        Ok(config)
+    }
+
+    fn read(path: &Path) -> Result<Config> {
+        let text = std::fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+        toml::from_str(&text).with_context(|| format!("invalid configuration file {}", path.display()))
+    }
+
+    fn apply(&mut self, args: ConfigArgs) {
+        fn set<T>(setting: &mut T, value: Option<T>) {
+            if let Some(value) = value {
+                *setting = value;
+            }
+        }
+        let database = &mut self.database;
+        set(&mut database.host, args.db_host.map(Some));
+        set(&mut database.port, args.db_port);
+        set(&mut database.name, args.db_name);
+        set(&mut database.user, args.db_user.map(Some));
+        set(&mut database.password, args.db_password.map(Some));
+        set(&mut database.pool.max_size, args.db_pool_size);
+        set(&mut database.pool.wait_timeout_secs, args.db_pool_timeout);
+        set(&mut database.pool.connect_timeout_secs, args.db_connect_timeout);
+        set(&mut database.statement_timeout_secs, args.db_statement_timeout.map(Some));
+        set(&mut database.tls.mode, args.db_sslmode);
+        set(&mut database.tls.ca_file, args.db_ssl_root_cert.map(Some));
+        set(&mut database.tls.cert_file, args.db_ssl_cert.map(Some));
+        set(&mut database.tls.key_file, args.db_ssl_key.map(Some));
+        set(&mut self.server.host, args.host);
+        set(&mut self.server.port, args.port);
+        set(&mut self.server.cors_origins, args.cors_origins);
+        set(&mut self.server.request_timeout_secs, args.request_timeout);
+        set(&mut self.server.shutdown_timeout_secs, args.shutdown_timeout);
+        set(&mut self.cache.size_mb, args.cache_size);
+        set(&mut self.cache.ttl_secs, args.cache_ttl);
+        set(&mut self.auth.disabled, args.disable_auth);
+        set(&mut self.auth.rate_limit, args.rate_limit);
+        set(&mut self.auth.rate_limit_burst, args.rate_limit_burst);
+        set(&mut self.log.format, args.log_format);
+        set(&mut self.log.filter, args.log_filter);
+    }
+
+    /// Everything wrong with the settings, each naming the setting and where to set it.
+    fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        let database = &self.database;
+        if database.host.as_deref().is_none_or(str::is_empty) {
+            problems.push("database.host: not set; give it in the configuration file, DB_HOST or --db-host".to_string());
+        }
+        if database.name.is_empty() {
+            problems.push("database.name: must not be empty".to_string());
+        }
+        if database.pool.max_size == 0 {
+            problems.push("database.pool.max_size: must be at least 1".to_string());
+        }
+        if database.pool.wait_timeout_secs == 0 {
+            problems.push("database.pool.wait_timeout_secs: must be at least 1".to_string());
+        }
+        if database.pool.connect_timeout_secs == 0 {
+            problems.push("database.pool.connect_timeout_secs: must be at least 1".to_string());
+        }
+        if database.statement_timeout_secs == Some(0) {
+            problems.push("database.statement_timeout_secs: must be at least 1".to_string());
+        }
+
+        let tls = &database.tls;
+        let tls_problems = problems.len();
+        for (setting, path) in [("ca_file", &tls.ca_file), ("cert_file", &tls.cert_file), ("key_file", &tls.key_file)] {
+            if let Some(path) = path.as_ref().filter(|path| !path.is_file()) {
+                problems.push(format!("database.tls.{}: {} is not a readable file", setting, path.display()));
+            }
+        }
+        if tls.cert_file.is_some() != tls.key_file.is_some() {
+            problems.push("database.tls: cert_file and key_file must be given together".to_string());
+        }
+        // The files are there; check they hold what they should
+        if problems.len() == tls_problems && tls.mode != SslMode::Disable {
+            if let Err(e) = db::tls::connector(tls) {
+                problems.push(format!("{:#}", e));
+            }
+        }
+
+        for origin in &self.server.cors_origins {
+            if origin != "*" && !is_origin(origin) {
+                problems.push(format!(
+                    "server.cors_origins: {:?} is not an origin such as https://example.com (scheme and host, no path)",
+                    origin
+                ));
+            }
+        }
+        if self.server.cors_origins.len() > 1 && self.server.cors_origins.iter().any(|origin| origin == "*") {
+            problems.push("server.cors_origins: * allows every origin and cannot be combined with others".to_string());
+        }
+        if self.server.request_timeout_secs == 0 {
+            problems.push("server.request_timeout_secs: must be at least 1".to_string());
+        }
+
+        if self.auth.rate_limit == 0 {
+            problems.push("auth.rate_limit: must be at least 1 request per minute".to_string());
+        }
+        if self.auth.rate_limit_burst == 0 {
+            problems.push("auth.rate_limit_burst: must be at least 1".to_string());
+        }
+        if let Err(e) = tracing_subscriber::EnvFilter::try_new(&self.log.filter) {
+            problems.push(format!("log.filter: {}", e));
+        }
+        problems
+    }
+}
+
+/// Whether `origin` is `scheme://host[:port]`, as browsers send in `Origin`.
+fn is_origin(origin: &str) -> bool {
+    let Some((scheme, authority)) = origin.split_once("://") else {
+        return false;
+    };
+    matches!(scheme, "http" | "https")
+        && !authority.is_empty()
+        && !authority.contains(['/', '?', '#', ' '])
+        && axum::http::HeaderValue::from_str(origin).is_ok()
+}
+
+/// `config check`: the settings as loaded, with secrets hidden.
+pub fn run(command: ConfigCommand, config: &Config) -> Result<()> {
+    match command {
+        ConfigCommand::Check => {
+            print!("{}", toml::to_string(config)?);
+            eprintln!("Configuration is valid");
+        }
+    }
+    Ok(())
+}
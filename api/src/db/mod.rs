@@ -0,0 +1,62 @@
+use anyhow::{Context, Result};
+use deadpool_postgres::{Config, ManagerConfig, Pool, PoolConfig, RecyclingMethod, Runtime, SslMode as PoolSslMode, Timeouts};
+use std::time::Duration;
+
+use crate::config::{DatabaseConfig, SslMode};
+
+pub mod tls;
+
+pub use tls::TlsConnector;
+
+pub type DbPool = Pool;
+
+/// A pool of connections to the database of `config`; connections are opened
+/// when first needed.
+pub fn create_pool(config: &DatabaseConfig) -> Result<DbPool> {
+    pool_config(config)
+        .create_pool(Some(Runtime::Tokio1), tls_connector(config)?)
+        .context("creating the database pool")
+}
+
+/// How connections to the database of `config` negotiate TLS, see [`tls`].
+pub fn tls_connector(config: &DatabaseConfig) -> Result<TlsConnector> {
+    tls::connector(&config.tls).context("setting up TLS to the database")
+}
+
+/// Settings of a single connection outside the pool, e.g. to receive notifications.
+pub fn connection_config(config: &DatabaseConfig) -> Result<tokio_postgres::Config> {
+    pool_config(config).get_pg_config().context("invalid database settings")
+}
+
+fn pool_config(config: &DatabaseConfig) -> Config {
+    let mut cfg = Config::new();
+    cfg.host = config.host.clone();
+    cfg.port = Some(config.port);
+    cfg.dbname = Some(config.name.clone());
+    cfg.user = config.user.clone();
+    cfg.password = config.password.clone();
+    cfg.connect_timeout = Some(Duration::from_secs(config.pool.connect_timeout_secs));
+    cfg.options = config.statement_timeout_secs.map(|secs| format!("-c statement_timeout={}s", secs));
+    // Certificates are checked by the connector; Postgres only needs to know
+    // whether to fall back to plain connections
+    cfg.ssl_mode = Some(match config.tls.mode {
+        SslMode::Disable => PoolSslMode::Disable,
+        SslMode::Prefer => PoolSslMode::Prefer,
+        SslMode::Require | SslMode::VerifyFull => PoolSslMode::Require,
+    });
+
+    cfg.manager = Some(ManagerConfig {
+        recycling_method: RecyclingMethod::Fast,
+    });
+    cfg.pool = Some(PoolConfig {
+        max_size: config.pool.max_size,
+        timeouts: Timeouts {
+            wait: Some(Duration::from_secs(config.pool.wait_timeout_secs)),
+            create: Some(Duration::from_secs(config.pool.connect_timeout_secs)),
+            recycle: Some(Duration::from_secs(config.pool.connect_timeout_secs)),
+        },
+        ..PoolConfig::default()
+    });
+
+    cfg
+}
@@ -0,0 +1,178 @@
+//! TLS for connections to Postgres, with rustls.
+//!
+//! Certificates are checked as libpq's `sslmode` does: `prefer` and `require`
+//! encrypt without checking the server's certificate, unless a CA bundle is
+//! given, when its chain must lead to one of the bundle's authorities (libpq's
+//! behaviour with a root certificate); `verify-full` also checks that the
+//! certificate names the host, trusting the system's authorities when no
+//! bundle is given.
+
+use anyhow::{bail, Context, Result};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::client::WebPkiServerVerifier;
+use rustls::crypto::{ring, CryptoProvider};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::{CertificateError, ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme};
+use std::path::Path;
+use std::sync::Arc;
+use tokio_postgres_rustls::MakeRustlsConnect;
+
+use crate::config::{SslMode, TlsConfig};
+
+/// What the pool and other connections negotiate TLS with; unused with `disable`.
+pub type TlsConnector = MakeRustlsConnect;
+
+/// A connector for the mode, CA bundle and client certificate of `config`.
+pub fn connector(config: &TlsConfig) -> Result<TlsConnector> {
+    let provider = Arc::new(ring::default_provider());
+    let verifier: Arc<dyn ServerCertVerifier> = match (config.mode, &config.ca_file) {
+        (SslMode::Disable, _) | (SslMode::Prefer | SslMode::Require, None) => {
+            Arc::new(AnyCertificate(provider.clone()))
+        }
+        (SslMode::Prefer | SslMode::Require, Some(ca_file)) => {
+            Arc::new(AnyHostname(webpki_verifier(authorities(ca_file)?, &provider)?))
+        }
+        (SslMode::VerifyFull, Some(ca_file)) => webpki_verifier(authorities(ca_file)?, &provider)?,
+        (SslMode::VerifyFull, None) => webpki_verifier(system_authorities()?, &provider)?,
+    };
+
+    let builder = ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .context("setting up TLS")?
+        .dangerous()
+        .with_custom_certificate_verifier(verifier);
+    let client_config = match (&config.cert_file, &config.key_file) {
+        (Some(cert_file), Some(key_file)) => {
+            let key = PrivateKeyDer::from_pem_file(key_file)
+                .with_context(|| format!("database.tls.key_file: no private key in {}", key_file.display()))?;
+            builder
+                .with_client_auth_cert(certificates("cert_file", cert_file)?, key)
+                .context("database.tls: cert_file and key_file do not match")?
+        }
+        _ => builder.with_no_client_auth(),
+    };
+    Ok(MakeRustlsConnect::new(client_config))
+}
+
+/// The certificates of the PEM file given as `database.tls.<setting>`.
+fn certificates(setting: &str, path: &Path) -> Result<Vec<CertificateDer<'static>>> {
+    let certificates = CertificateDer::pem_file_iter(path)
+        .and_then(|certificates| certificates.collect::<Result<Vec<_>, _>>())
+        .with_context(|| format!("database.tls.{}: cannot read {}", setting, path.display()))?;
+    if certificates.is_empty() {
+        bail!("database.tls.{}: {} holds no PEM certificates", setting, path.display());
+    }
+    Ok(certificates)
+}
+
+fn authorities(ca_file: &Path) -> Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    for certificate in certificates("ca_file", ca_file)? {
+        roots
+            .add(certificate)
+            .with_context(|| format!("database.tls.ca_file: invalid certificate in {}", ca_file.display()))?;
+    }
+    Ok(roots)
+}
+
+fn system_authorities() -> Result<RootCertStore> {
+    let found = rustls_native_certs::load_native_certs();
+    let mut roots = RootCertStore::empty();
+    let (added, _) = roots.add_parsable_certificates(found.certs);
+    if added == 0 {
+        bail!(
+            "database.tls.mode: verify-full found no system certificate authorities; give the server's in database.tls.ca_file"
+        );
+    }
+    Ok(roots)
+}
+
+fn webpki_verifier(roots: RootCertStore, provider: &Arc<CryptoProvider>) -> Result<Arc<WebPkiServerVerifier>> {
+    WebPkiServerVerifier::builder_with_provider(Arc::new(roots), provider.clone())
+        .build()
+        .context("setting up certificate verification")
+}
+
+/// Accepts any certificate, still checking that the server holds its key.
+#[derive(Debug)]
+struct AnyCertificate(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for AnyCertificate {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+/// Checks the certificate chain, whatever host the certificate names.
+#[derive(Debug)]
+struct AnyHostname(Arc<WebPkiServerVerifier>);
+
+impl ServerCertVerifier for AnyHostname {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        match self.0.verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now) {
+            Err(rustls::Error::InvalidCertificate(
+                CertificateError::NotValidForName | CertificateError::NotValidForNameContext { .. },
+            )) => Ok(ServerCertVerified::assertion()),
+            result => result,
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.0.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.0.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.supported_verify_schemes()
+    }
+}
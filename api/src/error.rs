@@ -0,0 +1,184 @@
+//! Error responses.
+//!
+//! Every failure, from a handler or an extractor, is an [`ApiError`] and is
+//! returned as an RFC 7807 `application/problem+json` document:
+//!
+//! ```json
+//! {
+//!   "type": "about:blank",
+//!   "title": "Bad Request",
+//!   "status": 400,
+//!   "detail": "Month must be between 1 and 12",
+//!   "code": "invalid_parameter",
+//!   "parameter": "month",
+//!   "request_id": "6f1c9a52-3c1e-4a8e-9d1b-2f0e8c4b7a10"
+//! }
+//! ```
+//!
+//! `code` is stable and meant for clients to branch on; `detail` is for
+//! people and may change.
+
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderValue, StatusCode, Uri},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+use std::time::Duration;
+use tower_http::request_id::RequestId;
+use utoipa::ToSchema;
+
+use crate::repository::RepositoryError;
+
+pub const PROBLEM_JSON: &str = "application/problem+json";
+
+tokio::task_local! {
+    /// ID of the request being handled, see [`scope_request_id`].
+    static REQUEST_ID: String;
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ApiError {
+    pub status: StatusCode,
+    /// Machine-readable error code, e.g. `invalid_parameter`
+    pub code: &'static str,
+    pub detail: String,
+    /// Query parameter, path segment or body field at fault
+    pub parameter: Option<String>,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, code: &'static str, detail: impl Into<String>) -> Self {
+        ApiError {
+            status,
+            code,
+            detail: detail.into(),
+            parameter: None,
+        }
+    }
+
+    pub fn with_parameter(mut self, parameter: impl Into<String>) -> Self {
+        self.parameter = Some(parameter.into());
+        self
+    }
+
+    /// A parameter has a value that is malformed, out of range or
+    /// inconsistent with another parameter.
+    pub fn invalid_parameter(parameter: &str, detail: impl Into<String>) -> Self {
+        ApiError::new(StatusCode::BAD_REQUEST, "invalid_parameter", detail).with_parameter(parameter)
+    }
+
+    /// A required parameter, or one of a set of alternatives, is missing.
+    pub fn missing_parameter(parameter: &str, detail: impl Into<String>) -> Self {
+        ApiError::new(StatusCode::BAD_REQUEST, "missing_parameter", detail).with_parameter(parameter)
+    }
+
+    pub fn not_found(code: &'static str, detail: impl Into<String>) -> Self {
+        ApiError::new(StatusCode::NOT_FOUND, code, detail)
+    }
+
+    pub fn conflict(code: &'static str, detail: impl Into<String>) -> Self {
+        ApiError::new(StatusCode::CONFLICT, code, detail)
+    }
+}
+
+/// Repository failures are reported without their cause, which is logged by
+/// the caller instead.
+impl From<RepositoryError> for ApiError {
+    fn from(e: RepositoryError) -> Self {
+        match e {
+            RepositoryError::Connection(_) => {
+                ApiError::new(StatusCode::SERVICE_UNAVAILABLE, "database_unavailable", "Database connection error")
+            }
+            RepositoryError::Query(_) => {
+                ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "database_error", "Database query error")
+            }
+            RepositoryError::Timeout(_) => {
+                ApiError::new(StatusCode::GATEWAY_TIMEOUT, "timeout", "The database query took too long")
+            }
+        }
+    }
+}
+
+/// RFC 7807 problem details, the body of every error response.
+#[derive(Serialize, ToSchema)]
+pub struct Problem {
+    /// Always `about:blank`; `code` tells problems apart
+    #[serde(rename = "type")]
+    #[schema(example = "about:blank")]
+    kind: &'static str,
+    /// Reason phrase of the status
+    #[schema(example = "Bad Request")]
+    title: &'static str,
+    #[schema(example = 400)]
+    status: u16,
+    /// Explanation for people; may change between releases
+    #[schema(example = "Month must be between 1 and 12")]
+    detail: String,
+    /// Stable machine-readable error code
+    #[schema(example = "invalid_parameter")]
+    code: &'static str,
+    /// Query parameter, path segment or body field at fault
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(example = "month")]
+    parameter: Option<String>,
+    /// Value of the `x-request-id` response header
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_id: Option<String>,
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let problem = Problem {
+            kind: "about:blank",
+            title: self.status.canonical_reason().unwrap_or("Error"),
+            status: self.status.as_u16(),
+            detail: self.detail,
+            code: self.code,
+            parameter: self.parameter,
+            request_id: REQUEST_ID.try_with(Clone::clone).ok().filter(|id| !id.is_empty()),
+        };
+        let mut response = (self.status, Json(problem)).into_response();
+        response
+            .headers_mut()
+            .insert(header::CONTENT_TYPE, HeaderValue::from_static(PROBLEM_JSON));
+        response
+    }
+}
+
+/// Middleware making the ID assigned by `SetRequestIdLayer` available to the
+/// [`ApiError`]s rendered while the request is handled.
+pub async fn scope_request_id(request: Request, next: Next) -> Response {
+    let id = request
+        .extensions()
+        .get::<RequestId>()
+        .and_then(|id| id.header_value().to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+    REQUEST_ID.scope(id, next.run(request)).await
+}
+
+/// Middleware failing requests that have not responded within `limit` with
+/// 504; their handler is dropped, which cancels its database query.
+pub async fn timeout(State(limit): State<Duration>, request: Request, next: Next) -> Response {
+    match tokio::time::timeout(limit, next.run(request)).await {
+        Ok(response) => response,
+        Err(_) => {
+            tracing::warn!("Request timed out after {} s", limit.as_secs());
+            let detail = format!("The request did not complete within {} s", limit.as_secs());
+            ApiError::new(StatusCode::GATEWAY_TIMEOUT, "timeout", detail).into_response()
+        }
+    }
+}
+
+/// Fallback for paths without a route.
+pub async fn route_not_found(uri: Uri) -> ApiError {
+    ApiError::not_found("route_not_found", format!("No route for {}", uri.path()))
+}
+
+/// Fallback for routes called with an unsupported method.
+pub async fn method_not_allowed() -> ApiError {
+    ApiError::new(StatusCode::METHOD_NOT_ALLOWED, "method_not_allowed", "Method not allowed for this route")
+}
@@ -0,0 +1,495 @@
+//! CSV, NDJSON and Parquet responses of the data endpoints.
+//!
+//! The format is chosen by the `format` query parameter or, without it, by
+//! the `Accept` header; JSON stays the default. Tabular formats flatten a
+//! response into rows of fixed [`Column`]s (see [`tables`]), so the same
+//! columns come back whichever format is asked for. Daily observations are
+//! encoded while they are read from the database cursor ([`stream`]); the
+//! other endpoints return small aggregates and are encoded in one go.
+
+pub mod tables;
+
+use arrow_array::builder::{BooleanBuilder, Date32Builder, Float64Builder, Int64Builder, StringBuilder};
+use arrow_array::types::Date32Type;
+use arrow_array::{ArrayRef, RecordBatch};
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
+use axum::{
+    async_trait,
+    body::{Body, Bytes},
+    extract::FromRequestParts,
+    http::{header, request::Parts, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use chrono::NaiveDate;
+use futures_util::{Stream, StreamExt};
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+use serde::ser::SerializeMap;
+use serde::{Deserialize, Serialize, Serializer};
+use std::io;
+use std::sync::Arc;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::error::ApiError;
+use crate::extract::{Json, Query};
+
+/// Encoded bytes buffered before they are sent as one body chunk.
+const CHUNK_BYTES: usize = 64 * 1024;
+/// Rows per Parquet row group; bounds the memory held while streaming.
+const PARQUET_ROW_GROUP_ROWS: usize = 64 * 1024;
+
+/// Representation of a data endpoint's response.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    /// The JSON document of the endpoint
+    #[default]
+    Json,
+    /// Comma-separated values with a header row
+    Csv,
+    /// One JSON object per row and line
+    Ndjson,
+    /// Apache Parquet file, Snappy-compressed
+    Parquet,
+}
+
+impl Format {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Format::Json => "application/json",
+            Format::Csv => "text/csv; charset=utf-8",
+            Format::Ndjson => "application/x-ndjson",
+            Format::Parquet => "application/vnd.apache.parquet",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Json => "json",
+            Format::Csv => "csv",
+            Format::Ndjson => "ndjson",
+            Format::Parquet => "parquet",
+        }
+    }
+
+    /// The format served for a media range of an `Accept` header.
+    fn from_media_range(range: &str) -> Option<Format> {
+        match range {
+            "application/json" | "application/*" | "*/*" => Some(Format::Json),
+            "text/csv" | "text/*" => Some(Format::Csv),
+            "application/x-ndjson" | "application/ndjson" => Some(Format::Ndjson),
+            "application/vnd.apache.parquet" | "application/x-parquet" => Some(Format::Parquet),
+            _ => None,
+        }
+    }
+
+    /// The acceptable format with the highest quality in an `Accept` header,
+    /// the first listed among equals.
+    fn negotiate(accept: &str) -> Option<Format> {
+        let mut best: Option<(Format, f32)> = None;
+        for item in accept.split(',') {
+            let mut parts = item.split(';').map(str::trim);
+            let range = parts.next().unwrap_or_default().to_ascii_lowercase();
+            let quality = parts
+                .filter_map(|p| p.strip_prefix("q="))
+                .find_map(|q| q.parse::<f32>().ok())
+                .unwrap_or(1.0);
+            if let Some(format) = Format::from_media_range(&range) {
+                if quality > 0.0 && best.is_none_or(|(_, q)| quality > q) {
+                    best = Some((format, quality));
+                }
+            }
+        }
+        best.map(|(format, _)| format)
+    }
+}
+
+/// The `format` query parameter of the data endpoints.
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct FormatParams {
+    /// Response format, overriding the `Accept` header (default `json`)
+    pub format: Option<Format>,
+}
+
+/// The format requested by `format`, or else by `Accept`.
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Format {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let Query(params) = Query::<FormatParams>::try_from_uri(&parts.uri)?;
+        if let Some(format) = params.format {
+            return Ok(format);
+        }
+        let Some(accept) = parts.headers.get(header::ACCEPT) else {
+            return Ok(Format::Json);
+        };
+        accept.to_str().ok().and_then(Format::negotiate).ok_or_else(|| {
+            ApiError::new(
+                StatusCode::NOT_ACCEPTABLE,
+                "not_acceptable",
+                "Acceptable types are application/json, text/csv, application/x-ndjson and application/vnd.apache.parquet",
+            )
+        })
+    }
+}
+
+/// Value type of a [`Column`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnType {
+    Text,
+    Integer,
+    Float,
+    Boolean,
+    Date,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Column {
+    pub name: &'static str,
+    pub kind: ColumnType,
+}
+
+impl Column {
+    pub const fn new(name: &'static str, kind: ColumnType) -> Self {
+        Column { name, kind }
+    }
+}
+
+/// A value of a row; [`Cell::Null`] when absent.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Cell {
+    Null,
+    Text(String),
+    Integer(i64),
+    Float(f64),
+    Boolean(bool),
+    Date(NaiveDate),
+}
+
+impl Serialize for Cell {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Cell::Null => serializer.serialize_none(),
+            Cell::Text(s) => serializer.serialize_str(s),
+            Cell::Integer(i) => serializer.serialize_i64(*i),
+            Cell::Float(f) => serializer.serialize_f64(*f),
+            Cell::Boolean(b) => serializer.serialize_bool(*b),
+            Cell::Date(d) => d.serialize(serializer),
+        }
+    }
+}
+
+/// A row as a JSON object, keys in column order.
+struct JsonRow<'a>(&'a [Column], &'a [Cell]);
+
+impl Serialize for JsonRow<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for (column, cell) in self.0.iter().zip(self.1) {
+            map.serialize_entry(column.name, cell)?;
+        }
+        map.end()
+    }
+}
+
+impl Cell {
+    fn as_csv(&self) -> String {
+        match self {
+            Cell::Null => String::new(),
+            Cell::Text(s) => s.clone(),
+            Cell::Integer(i) => i.to_string(),
+            Cell::Float(f) => f.to_string(),
+            Cell::Boolean(b) => b.to_string(),
+            Cell::Date(d) => d.to_string(),
+        }
+    }
+}
+
+macro_rules! cell_from {
+    ($($type:ty => |$v:ident| $cell:expr;)*) => {$(
+        impl From<$type> for Cell {
+            fn from($v: $type) -> Cell {
+                $cell
+            }
+        }
+    )*};
+}
+
+cell_from! {
+    String => |v| Cell::Text(v);
+    &str => |v| Cell::Text(v.to_string());
+    char => |v| Cell::Text(v.to_string());
+    i32 => |v| Cell::Integer(v.into());
+    u32 => |v| Cell::Integer(v.into());
+    u64 => |v| Cell::Integer(v as i64);
+    f64 => |v| Cell::Float(v);
+    bool => |v| Cell::Boolean(v);
+    NaiveDate => |v| Cell::Date(v);
+}
+
+impl<T: Into<Cell>> From<Option<T>> for Cell {
+    fn from(value: Option<T>) -> Cell {
+        value.map_or(Cell::Null, Into::into)
+    }
+}
+
+/// A response that also exports as a table.
+pub trait Tabular: Serialize {
+    /// Columns of every row, in order
+    const COLUMNS: &'static [Column];
+    /// Name of the exported file, without extension
+    const NAME: &'static str;
+
+    fn into_rows(self) -> Vec<Vec<Cell>>;
+}
+
+/// Encodes rows of fixed columns into one of the tabular formats.
+pub struct Encoder {
+    columns: &'static [Column],
+    sink: Sink,
+}
+
+enum Sink {
+    Csv(csv::Writer<Vec<u8>>),
+    Ndjson(Vec<u8>),
+    Parquet {
+        writer: ArrowWriter<Vec<u8>>,
+        schema: SchemaRef,
+        /// Rows not yet written as a record batch
+        rows: Vec<Vec<Cell>>,
+    },
+}
+
+impl Encoder {
+    /// Start a table of `columns`; fails for [`Format::Json`], which is not tabular.
+    pub fn new(format: Format, columns: &'static [Column]) -> io::Result<Self> {
+        let sink = match format {
+            Format::Json => return Err(io::Error::other("JSON is not a tabular format")),
+            Format::Csv => {
+                let mut writer = csv::Writer::from_writer(Vec::new());
+                writer.write_record(columns.iter().map(|c| c.name))?;
+                Sink::Csv(writer)
+            }
+            Format::Ndjson => Sink::Ndjson(Vec::new()),
+            Format::Parquet => {
+                let schema = Arc::new(arrow_schema(columns));
+                let properties = WriterProperties::builder()
+                    .set_compression(Compression::SNAPPY)
+                    .set_max_row_group_size(PARQUET_ROW_GROUP_ROWS)
+                    .build();
+                let writer = ArrowWriter::try_new(Vec::new(), schema.clone(), Some(properties)).map_err(io::Error::other)?;
+                Sink::Parquet { writer, schema, rows: Vec::new() }
+            }
+        };
+        Ok(Encoder { columns, sink })
+    }
+
+    pub fn push(&mut self, row: Vec<Cell>) -> io::Result<()> {
+        match &mut self.sink {
+            Sink::Csv(writer) => writer.write_record(row.iter().map(Cell::as_csv))?,
+            Sink::Ndjson(buffer) => {
+                serde_json::to_writer(&mut *buffer, &JsonRow(self.columns, &row))?;
+                buffer.push(b'\n');
+            }
+            Sink::Parquet { writer, schema, rows } => {
+                rows.push(row);
+                if rows.len() >= PARQUET_ROW_GROUP_ROWS {
+                    let batch = record_batch(schema, self.columns, std::mem::take(rows))?;
+                    writer.write(&batch).map_err(io::Error::other)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Bytes encoded so far and not yet taken.
+    pub fn pending(&self) -> usize {
+        match &self.sink {
+            Sink::Csv(writer) => writer.get_ref().len(),
+            Sink::Ndjson(buffer) => buffer.len(),
+            Sink::Parquet { writer, .. } => writer.inner().len(),
+        }
+    }
+
+    /// Take the bytes encoded so far.
+    pub fn take(&mut self) -> io::Result<Bytes> {
+        let bytes = match &mut self.sink {
+            Sink::Csv(writer) => std::mem::replace(writer, csv::Writer::from_writer(Vec::new()))
+                .into_inner()
+                .map_err(|e| e.into_error())?,
+            Sink::Ndjson(buffer) => std::mem::take(buffer),
+            Sink::Parquet { writer, .. } => std::mem::take(writer.inner_mut()),
+        };
+        Ok(Bytes::from(bytes))
+    }
+
+    /// Complete the table and take the remaining bytes.
+    pub fn finish(mut self) -> io::Result<Bytes> {
+        if let Sink::Parquet { writer, schema, rows } = &mut self.sink {
+            if !rows.is_empty() {
+                let batch = record_batch(schema, self.columns, std::mem::take(rows))?;
+                writer.write(&batch).map_err(io::Error::other)?;
+            }
+            writer.finish().map_err(io::Error::other)?;
+        }
+        self.take()
+    }
+}
+
+fn arrow_schema(columns: &[Column]) -> Schema {
+    let fields: Vec<Field> = columns
+        .iter()
+        .map(|column| {
+            let data_type = match column.kind {
+                ColumnType::Text => DataType::Utf8,
+                ColumnType::Integer => DataType::Int64,
+                ColumnType::Float => DataType::Float64,
+                ColumnType::Boolean => DataType::Boolean,
+                ColumnType::Date => DataType::Date32,
+            };
+            Field::new(column.name, data_type, true)
+        })
+        .collect();
+    Schema::new(fields)
+}
+
+/// Rows transposed into Arrow columns; a cell of the wrong type is written as null.
+fn record_batch(schema: &SchemaRef, columns: &[Column], rows: Vec<Vec<Cell>>) -> io::Result<RecordBatch> {
+    let arrays: Vec<ArrayRef> = columns
+        .iter()
+        .enumerate()
+        .map(|(i, column)| {
+            let cells = rows.iter().map(move |row| row.get(i).unwrap_or(&Cell::Null));
+            let array: ArrayRef = match column.kind {
+                ColumnType::Text => {
+                    let mut builder = StringBuilder::new();
+                    cells.for_each(|c| builder.append_option(if let Cell::Text(s) = c { Some(s) } else { None }));
+                    Arc::new(builder.finish())
+                }
+                ColumnType::Integer => {
+                    let mut builder = Int64Builder::new();
+                    cells.for_each(|c| builder.append_option(if let Cell::Integer(i) = c { Some(*i) } else { None }));
+                    Arc::new(builder.finish())
+                }
+                ColumnType::Float => {
+                    let mut builder = Float64Builder::new();
+                    cells.for_each(|c| {
+                        builder.append_option(match c {
+                            Cell::Float(f) => Some(*f),
+                            Cell::Integer(i) => Some(*i as f64),
+                            _ => None,
+                        })
+                    });
+                    Arc::new(builder.finish())
+                }
+                ColumnType::Boolean => {
+                    let mut builder = BooleanBuilder::new();
+                    cells.for_each(|c| builder.append_option(if let Cell::Boolean(b) = c { Some(*b) } else { None }));
+                    Arc::new(builder.finish())
+                }
+                ColumnType::Date => {
+                    let mut builder = Date32Builder::new();
+                    cells.for_each(|c| {
+                        builder.append_option(if let Cell::Date(d) = c { Some(Date32Type::from_naive_date(*d)) } else { None })
+                    });
+                    Arc::new(builder.finish())
+                }
+            };
+            array
+        })
+        .collect();
+    RecordBatch::try_new(schema.clone(), arrays).map_err(io::Error::other)
+}
+
+/// Headers of a response in `format`; tabular formats download as `name.<ext>`.
+fn headers(format: Format, name: &str) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert(header::CONTENT_TYPE, HeaderValue::from_static(format.content_type()));
+    headers.insert(header::VARY, HeaderValue::from_static("accept"));
+    if format != Format::Json {
+        let disposition = format!("attachment; filename=\"{}.{}\"", name, format.extension());
+        if let Ok(value) = HeaderValue::from_str(&disposition) {
+            headers.insert(header::CONTENT_DISPOSITION, value);
+        }
+    }
+    headers
+}
+
+/// A response in the format the client asked for.
+#[derive(Debug)]
+pub struct Export<T> {
+    format: Format,
+    value: T,
+}
+
+impl<T: Tabular> Export<T> {
+    pub fn new(format: Format, value: T) -> Self {
+        Export { format, value }
+    }
+}
+
+impl<T: Tabular> IntoResponse for Export<T> {
+    fn into_response(self) -> Response {
+        if self.format == Format::Json {
+            return (headers(Format::Json, T::NAME), Json(self.value)).into_response();
+        }
+        let encoded = Encoder::new(self.format, T::COLUMNS).and_then(|mut encoder| {
+            for row in self.value.into_rows() {
+                encoder.push(row)?;
+            }
+            encoder.finish()
+        });
+        match encoded {
+            Ok(body) => (headers(self.format, T::NAME), body).into_response(),
+            Err(e) => {
+                tracing::error!("Failed to encode {} as {:?}: {}", T::NAME, self.format, e);
+                ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "export_failed", "The response could not be encoded")
+                    .into_response()
+            }
+        }
+    }
+}
+
+/// JSON response of an endpoint that also exports as a table.
+pub fn json<T: Serialize>(value: T) -> Response {
+    (headers(Format::Json, ""), Json(value)).into_response()
+}
+
+/// Stream `rows` as a table named `name`, sending a chunk whenever
+/// [`CHUNK_BYTES`] are encoded.
+///
+/// The status is sent before the first row is read, so a failure midway
+/// aborts the body instead; callers check what they can beforehand.
+pub fn stream<S>(format: Format, name: &str, columns: &'static [Column], rows: S) -> Result<Response, ApiError>
+where
+    S: Stream<Item = io::Result<Vec<Cell>>> + Send + 'static,
+{
+    let encoder = Encoder::new(format, columns).map_err(|e| {
+        tracing::error!("Failed to start {} export: {}", name, e);
+        ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "export_failed", "The response could not be encoded")
+    })?;
+
+    let chunks = futures_util::stream::unfold(Some((Box::pin(rows), encoder)), |state| async move {
+        let (mut rows, mut encoder) = state?;
+        loop {
+            match rows.next().await {
+                Some(Ok(row)) => {
+                    if let Err(e) = encoder.push(row) {
+                        return Some((Err(e), None));
+                    }
+                    if encoder.pending() >= CHUNK_BYTES {
+                        let chunk = encoder.take();
+                        return Some((chunk, Some((rows, encoder))));
+                    }
+                }
+                Some(Err(e)) => return Some((Err(e), None)),
+                None => return Some((encoder.finish(), None)),
+            }
+        }
+    });
+
+    Ok((headers(format, name), Body::from_stream(chunks)).into_response())
+}
@@ -0,0 +1,219 @@
+//! Rows and columns of each data endpoint in the tabular formats.
+//!
+//! Tables are long rather than wide wherever the JSON nests per element, so
+//! their columns do not depend on the data. Summaries computed from the rows
+//! (temperature statistics, anomaly totals) are only part of the JSON.
+
+use chrono::NaiveDate;
+
+use super::{Cell, Column, ColumnType, Tabular};
+use crate::models::{
+    AnomalyResponse, ElementAnomaly, Location, NearestStationsResponse, NormalsResponse, PrecipitationResponse,
+    TemperatureResponse, YearlyPrecipitationResponse,
+};
+use crate::observations::{Observation, Units};
+
+use ColumnType::{Boolean, Date, Float, Integer, Text};
+
+impl Tabular for Vec<Location> {
+    const NAME: &'static str = "locations";
+    const COLUMNS: &'static [Column] = &[
+        Column::new("location", Text),
+        Column::new("registered", Boolean),
+        Column::new("display_name", Text),
+        Column::new("latitude", Float),
+        Column::new("longitude", Float),
+        Column::new("elevation", Float),
+        Column::new("country", Text),
+        Column::new("timezone", Text),
+        // Space-separated
+        Column::new("station_ids", Text),
+        Column::new("distance_km", Float),
+    ];
+
+    fn into_rows(self) -> Vec<Vec<Cell>> {
+        self.into_iter()
+            .map(|l| {
+                vec![
+                    l.location.into(),
+                    l.registered.into(),
+                    l.display_name.into(),
+                    l.latitude.into(),
+                    l.longitude.into(),
+                    l.elevation.into(),
+                    l.country.into(),
+                    l.timezone.into(),
+                    l.station_ids.join(" ").into(),
+                    l.distance_km.into(),
+                ]
+            })
+            .collect()
+    }
+}
+
+/// One row per element and year.
+impl Tabular for TemperatureResponse {
+    const NAME: &'static str = "temperature";
+    const COLUMNS: &'static [Column] = &[
+        Column::new("element", Text),
+        Column::new("year", Integer),
+        Column::new("value", Float),
+    ];
+
+    fn into_rows(self) -> Vec<Vec<Cell>> {
+        [("TMAX", self.tmax), ("TMIN", self.tmin), ("TAVG", self.tavg)]
+            .into_iter()
+            .flat_map(|(element, summary)| summary.into_iter().flat_map(|s| s.values).map(move |v| (element, v)))
+            .map(|(element, v)| vec![element.into(), v.year.into(), v.value.into()])
+            .collect()
+    }
+}
+
+impl Tabular for PrecipitationResponse {
+    const NAME: &'static str = "monthly_precipitation";
+    const COLUMNS: &'static [Column] = &[
+        Column::new("year", Integer),
+        Column::new("month", Integer),
+        Column::new("precipitation", Float),
+    ];
+
+    fn into_rows(self) -> Vec<Vec<Cell>> {
+        let mut years: Vec<(i32, f64)> = self.precipitation_by_year.into_iter().collect();
+        years.sort_by_key(|(year, _)| *year);
+        years
+            .into_iter()
+            .map(|(year, total)| vec![year.into(), self.month.into(), total.into()])
+            .collect()
+    }
+}
+
+impl Tabular for YearlyPrecipitationResponse {
+    const NAME: &'static str = "yearly_precipitation";
+    const COLUMNS: &'static [Column] = &[Column::new("year", Integer), Column::new("precipitation", Float)];
+
+    fn into_rows(self) -> Vec<Vec<Cell>> {
+        let mut years: Vec<(i32, f64)> = self.yearly_precipitation.into_iter().collect();
+        years.sort_by_key(|(year, _)| *year);
+        years.into_iter().map(|(year, total)| vec![year.into(), total.into()]).collect()
+    }
+}
+
+/// Name of the daily observations table.
+pub const DAILY_OBSERVATIONS: &str = "daily_observations";
+
+/// One row per observation, see [`observation_row`].
+pub const DAILY_OBSERVATION_COLUMNS: &[Column] = &[
+    Column::new("date", Date),
+    Column::new("station_id", Text),
+    Column::new("element", Text),
+    Column::new("value", Float),
+    Column::new("unit", Text),
+    Column::new("mflag", Text),
+    Column::new("qflag", Text),
+    Column::new("sflag", Text),
+];
+
+/// A stored observation converted to `units`, as a row of [`DAILY_OBSERVATION_COLUMNS`].
+pub fn observation_row(date: NaiveDate, station_id: &str, observation: &Observation, units: Units) -> Vec<Cell> {
+    let element = &observation.element;
+    vec![
+        date.into(),
+        station_id.into(),
+        element.code().into(),
+        element.convert(observation.value, units).into(),
+        element.unit_label(units).into(),
+        observation.mflag.into(),
+        observation.qflag.into(),
+        observation.sflag.into(),
+    ]
+}
+
+impl Tabular for NormalsResponse {
+    const NAME: &'static str = "normals";
+    const COLUMNS: &'static [Column] = &[
+        Column::new("date", Date),
+        Column::new("day_of_year", Integer),
+        Column::new("tmax", Float),
+        Column::new("tmin", Float),
+        Column::new("prcp", Float),
+    ];
+
+    fn into_rows(self) -> Vec<Vec<Cell>> {
+        self.normals
+            .into_iter()
+            .map(|n| vec![n.date.into(), n.day_of_year.into(), n.tmax.into(), n.tmin.into(), n.prcp.into()])
+            .collect()
+    }
+}
+
+/// One row per date and element observed.
+impl Tabular for AnomalyResponse {
+    const NAME: &'static str = "anomalies";
+    const COLUMNS: &'static [Column] = &[
+        Column::new("date", Date),
+        Column::new("element", Text),
+        Column::new("observed", Float),
+        Column::new("normal", Float),
+        Column::new("departure", Float),
+        Column::new("cumulative_departure", Float),
+        Column::new("percentile_rank", Float),
+    ];
+
+    fn into_rows(self) -> Vec<Vec<Cell>> {
+        let mut rows = Vec::new();
+        for day in self.days {
+            let elements: [(&str, Option<ElementAnomaly>); 3] = [("TMAX", day.tmax), ("TMIN", day.tmin), ("PRCP", day.prcp)];
+            for (element, anomaly) in elements {
+                if let Some(a) = anomaly {
+                    rows.push(vec![
+                        day.date.into(),
+                        element.into(),
+                        a.observed.into(),
+                        a.normal.into(),
+                        a.departure.into(),
+                        a.cumulative_departure.into(),
+                        a.percentile_rank.into(),
+                    ]);
+                }
+            }
+        }
+        rows
+    }
+}
+
+/// The periods of record per element are only part of the JSON.
+impl Tabular for NearestStationsResponse {
+    const NAME: &'static str = "nearest_stations";
+    const COLUMNS: &'static [Column] = &[
+        Column::new("station_id", Text),
+        Column::new("name", Text),
+        Column::new("latitude", Float),
+        Column::new("longitude", Float),
+        Column::new("elevation", Float),
+        Column::new("state", Text),
+        Column::new("wmo_id", Text),
+        Column::new("distance_km", Float),
+        Column::new("coverage", Float),
+        Column::new("effective_distance_km", Float),
+    ];
+
+    fn into_rows(self) -> Vec<Vec<Cell>> {
+        self.stations
+            .into_iter()
+            .map(|s| {
+                vec![
+                    s.station_id.into(),
+                    s.name.into(),
+                    s.latitude.into(),
+                    s.longitude.into(),
+                    s.elevation.into(),
+                    s.state.into(),
+                    s.wmo_id.into(),
+                    s.distance_km.into(),
+                    s.coverage.into(),
+                    s.effective_distance_km.into(),
+                ]
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,139 @@
+//! Request extractors that reject with [`ApiError`].
+//!
+//! Drop-in replacements for axum's `Query`, `Json` and `Path`, whose
+//! rejections are plain text. Deserialization errors name the offending
+//! parameter or body field.
+
+use axum::{
+    async_trait,
+    body::Bytes,
+    extract::{FromRequest, FromRequestParts, Request},
+    http::{header, request::Parts, HeaderMap, StatusCode, Uri},
+    response::{IntoResponse, Response},
+};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::error::ApiError;
+
+/// Query string deserialized into `T`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Query<T>(pub T);
+
+impl<T: DeserializeOwned> Query<T> {
+    pub fn try_from_uri(uri: &Uri) -> Result<Self, ApiError> {
+        let query = uri.query().unwrap_or_default();
+        let deserializer = serde_urlencoded::Deserializer::new(form_urlencoded::parse(query.as_bytes()));
+        serde_path_to_error::deserialize(deserializer)
+            .map(Query)
+            .map_err(|e| field_error(StatusCode::BAD_REQUEST, e.path(), &e.inner().to_string()))
+    }
+}
+
+#[async_trait]
+impl<T, S> FromRequestParts<S> for Query<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Self::try_from_uri(&parts.uri)
+    }
+}
+
+/// JSON request body deserialized into `T`; as a response, `T` serialized
+/// as JSON.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Json<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequest<S> for Json<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request(request: Request, state: &S) -> Result<Self, Self::Rejection> {
+        if !is_json(request.headers()) {
+            return Err(ApiError::new(
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                "unsupported_media_type",
+                "Expected a request body with Content-Type: application/json",
+            ));
+        }
+        let body = Bytes::from_request(request, state)
+            .await
+            .map_err(|e| ApiError::new(e.status(), "invalid_body", e.body_text()))?;
+
+        let deserializer = &mut serde_json::Deserializer::from_slice(&body);
+        serde_path_to_error::deserialize(deserializer).map(Json).map_err(|e| {
+            let inner = e.inner();
+            let position = format!(" at line {} column {}", inner.line(), inner.column());
+            let message = inner.to_string();
+            let message = message.strip_suffix(&position).unwrap_or(&message);
+            match inner.classify() {
+                // Well-formed JSON that does not fit the expected fields
+                serde_json::error::Category::Data => field_error(StatusCode::UNPROCESSABLE_ENTITY, e.path(), message),
+                _ => ApiError::new(StatusCode::BAD_REQUEST, "invalid_body", format!("Malformed JSON body: {}", message)),
+            }
+        })
+    }
+}
+
+impl<T: Serialize> IntoResponse for Json<T> {
+    fn into_response(self) -> Response {
+        axum::Json(self.0).into_response()
+    }
+}
+
+/// Path parameters deserialized into `T`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Path<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequestParts<S> for Path<T>
+where
+    T: DeserializeOwned + Send,
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        axum::extract::Path::<T>::from_request_parts(parts, state)
+            .await
+            .map(|axum::extract::Path(value)| Path(value))
+            .map_err(|e| ApiError::new(e.status(), "invalid_path", e.body_text()))
+    }
+}
+
+/// `application/json` or any `+json` media type.
+fn is_json(headers: &HeaderMap) -> bool {
+    let Some(content_type) = headers.get(header::CONTENT_TYPE).and_then(|v| v.to_str().ok()) else {
+        return false;
+    };
+    let essence = content_type.split(';').next().unwrap_or_default().trim().to_ascii_lowercase();
+    essence == "application/json" || (essence.starts_with("application/") && essence.ends_with("+json"))
+}
+
+/// Map a deserialization error at `path` to an error naming the field.
+///
+/// Missing fields are reported by serde at the enclosing level, so their
+/// name is taken from the message instead.
+fn field_error(status: StatusCode, path: &serde_path_to_error::Path, message: &str) -> ApiError {
+    if let Some(field) = message
+        .strip_prefix("missing field `")
+        .and_then(|rest| rest.strip_suffix('`'))
+    {
+        let error = ApiError::new(status, "missing_parameter", format!("Missing required parameter: {}", field));
+        return error.with_parameter(field);
+    }
+
+    let parameter = path.to_string();
+    if parameter == "." || parameter.contains('?') {
+        return ApiError::new(status, "invalid_parameter", message.to_string());
+    }
+    ApiError::new(status, "invalid_parameter", format!("Invalid value for {}: {}", parameter, message))
+        .with_parameter(parameter)
+}
@@ -1,236 +1,265 @@
-use axum::{
-    extract::{Query, State},
-    http::StatusCode,
-    Json,
-};
-use chrono::{Datelike, Utc};
-use serde_json::Value;
-use crate::{db::DbPool, models::{Location, TemperatureRequest, TemperatureResponse, PrecipitationRequest, PrecipitationResponse, YearlyPrecipitationRequest, YearlyPrecipitationResponse}};
-
-pub async fn get_locations(
-    State(pool): State<DbPool>,
-) -> Result<Json<Vec<Location>>, (StatusCode, String)> {
-    let client = pool
-        .get()
-        .await
-        .map_err(|e| {
-            tracing::error!("Failed to get database connection: {}", e);
-            (StatusCode::INTERNAL_SERVER_ERROR, "Database connection error".to_string())
-        })?;
+use axum::{extract::State, http::StatusCode, response::Response};
+use chrono::{Datelike, NaiveDate, Utc};
+use futures_util::StreamExt;
+use std::collections::BTreeMap;
+use crate::anomalies::{self, Climatology};
+use crate::auth;
+use crate::error::ApiError;
+use crate::export::{self, tables, Cell, Export, Format, FormatParams};
+use crate::openapi::{BadRequest, Conflict, NotFound, UnprocessableEntity, UnsupportedMediaType};
+use crate::extract::{Json, Path, Query};
+use crate::normals::{self, NormalsTable, NORMAL_ELEMENTS};
+use crate::observations::{self, Element};
+use crate::stations;
+use crate::stats::{Summary, YearValue};
+use crate::repository::{self, ClimateRepository, RepositoryError};
+use crate::models::{ApiKey, CreatedApiKey, NewApiKey, Location, LocationsRequest, LocationDetails, NewLocation, TemperatureRequest, TemperatureResponse, PrecipitationRequest, PrecipitationResponse, YearlyPrecipitationRequest, YearlyPrecipitationResponse, DailyObservationsRequest, DailyObservationsResponse, DailyObservation, ObservationFlags, YearRange, ComputeNormalsRequest, ComputeNormalsResponse, ElementNormalsSummary, NormalsRequest, NormalsResponse, DailyNormal, AnomalyRequest, AnomalyResponse, BaselineSource, NearestStationsRequest, NearestStationsResponse, NearestStation};
+
+const DEFAULT_PERCENTILES: [f64; 4] = [10.0, 25.0, 75.0, 90.0];
+const MAX_ANOMALY_DAYS: i64 = 3660;
+const DEFAULT_OBSERVATION_LIMIT: u32 = 366;
+const MAX_OBSERVATION_LIMIT: u32 = 10_000;
+const DEFAULT_STATION_LIMIT: u32 = 10;
+const MAX_STATION_LIMIT: u32 = 100;
+const DEFAULT_STATION_DISTANCE_KM: f64 = 100.0;
+const MAX_STATION_DISTANCE_KM: f64 = 1000.0;
+
+/// Log a repository failure and answer with a generic database error.
+fn internal_error(context: &'static str) -> impl Fn(RepositoryError) -> ApiError {
+    move |e| {
+        tracing::error!("{}: {}", context, e);
+        ApiError::from(e)
+    }
+}
+
+/// Resolve the inclusive year range for an aggregate request.
+///
+/// `as_of` (default: today, UTC) only supplies defaults, so a pinned `as_of`
+/// makes results reproducible across a year boundary:
+/// - `start_year` and `end_year` given: used as is
+/// - only `start_year`: ends `samples - 1` years later, or at the `as_of` year
+/// - otherwise: ends at `end_year` (default: the `as_of` year) and spans `samples` years
+fn resolve_year_range(
+    samples: Option<u32>,
+    start_year: Option<i32>,
+    end_year: Option<i32>,
+    as_of: Option<NaiveDate>,
+) -> Result<YearRange, ApiError> {
+    if samples == Some(0) {
+        return Err(ApiError::invalid_parameter("samples", "Samples must be greater than 0"));
+    }
+    let as_of = as_of.unwrap_or_else(|| Utc::now().date_naive());
+    let samples = samples.map(|s| s as i32);
+
+    let (start_year, end_year) = match (start_year, end_year, samples) {
+        (Some(start), Some(end), _) => (start, end),
+        (Some(start), None, Some(samples)) => (start, start + samples - 1),
+        (Some(start), None, None) => (start, as_of.year()),
+        (None, end, Some(samples)) => {
+            let end = end.unwrap_or_else(|| as_of.year());
+            (end - samples + 1, end)
+        }
+        (None, _, None) => {
+            return Err(ApiError::missing_parameter("samples", "Either samples or start_year must be provided"));
+        }
+    };
+
+    if start_year > end_year {
+        return Err(ApiError::invalid_parameter("start_year", "start_year must not be after end_year"));
+    }
 
-    let rows = client
-        .query("SELECT DISTINCT location FROM daily WHERE location IS NOT NULL ORDER BY location", &[])
+    Ok(YearRange { start_year, end_year, as_of })
+}
+
+/// List locations
+///
+/// Registered locations first, then any other names found in the daily table,
+/// which are marked `"registered": false` and have no coordinates.
+#[utoipa::path(
+    get,
+    path = "/get_locations",
+    tag = "locations",
+    params(LocationsRequest, FormatParams),
+    responses(
+        (status = 200, description = "All locations", body = Vec<Location>),
+        (status = 400, response = BadRequest),
+    )
+)]
+pub async fn get_locations<R: ClimateRepository>(
+    Query(params): Query<LocationsRequest>,
+    format: Format,
+    State(repo): State<R>,
+) -> Result<Export<Vec<Location>>, ApiError> {
+    let point = match (params.latitude, params.longitude) {
+        (Some(latitude), Some(longitude)) => {
+            validate_coordinates(latitude, longitude)?;
+            Some((latitude, longitude))
+        }
+        (None, None) => None,
+        _ => return Err(ApiError::missing_parameter(if params.latitude.is_none() { "latitude" } else { "longitude" }, "latitude and longitude must be given together")),
+    };
+
+    let mut locations = repo
+        .list_locations()
         .await
-        .map_err(|e| {
-            tracing::error!("Failed to query locations: {}", e);
-            (StatusCode::INTERNAL_SERVER_ERROR, "Database query error".to_string())
-        })?;
-
-    let locations: Vec<Location> = rows
-        .iter()
-        .map(|row| Location {
-            location: row.get(0),
-        })
-        .collect();
+        .map_err(internal_error("Failed to query locations"))?;
 
-    Ok(Json(locations))
+    // Closest registered locations first; unregistered ones keep their order at the end
+    if let Some((latitude, longitude)) = point {
+        for location in &mut locations {
+            location.distance_km = location
+                .latitude
+                .zip(location.longitude)
+                .map(|(lat, lon)| stations::distance_km(latitude, longitude, lat, lon));
+        }
+        locations.sort_by(|a, b| match (a.distance_km, b.distance_km) {
+            (Some(a), Some(b)) => a.total_cmp(&b),
+            (Some(_), None) => std::cmp::Ordering::Less,
+            (None, Some(_)) => std::cmp::Ordering::Greater,
+            (None, None) => std::cmp::Ordering::Equal,
+        });
+    }
+
+    Ok(Export::new(format, locations))
+}
+
+fn validate_coordinates(latitude: f64, longitude: f64) -> Result<(), ApiError> {
+    if !(-90.0..=90.0).contains(&latitude) {
+        return Err(ApiError::invalid_parameter("latitude", "Latitude must be between -90 and 90"));
+    }
+    if !(-180.0..=180.0).contains(&longitude) {
+        return Err(ApiError::invalid_parameter("longitude", "Longitude must be between -180 and 180"));
+    }
+    Ok(())
 }
 
-pub async fn get_average_temp_by_date(
+/// Temperature statistics for a calendar day
+///
+/// Statistics for a day and month across a range of years, computed separately
+/// for TMAX, TMIN and TAVG.
+#[utoipa::path(
+    get,
+    path = "/get_average_temp_by_date",
+    tag = "observations",
+    params(TemperatureRequest, FormatParams),
+    responses(
+        (status = 200, description = "Temperature statistics", body = TemperatureResponse),
+        (status = 400, response = BadRequest),
+        (status = 404, response = NotFound),
+    )
+)]
+pub async fn get_average_temp_by_date<R: ClimateRepository>(
     Query(params): Query<TemperatureRequest>,
-    State(pool): State<DbPool>,
-) -> Result<Json<TemperatureResponse>, (StatusCode, String)> {
+    format: Format,
+    State(repo): State<R>,
+) -> Result<Export<TemperatureResponse>, ApiError> {
     // Validate input parameters
     if params.month == 0 || params.month > 12 {
-        return Err((StatusCode::BAD_REQUEST, "Month must be between 1 and 12".to_string()));
+        return Err(ApiError::invalid_parameter("month", "Month must be between 1 and 12"));
     }
     if params.day == 0 || params.day > 31 {
-        return Err((StatusCode::BAD_REQUEST, "Day must be between 1 and 31".to_string()));
-    }
-    if params.samples == 0 {
-        return Err((StatusCode::BAD_REQUEST, "Samples must be greater than 0".to_string()));
+        return Err(ApiError::invalid_parameter("day", "Day must be between 1 and 31"));
     }
+    let percentiles = match params.percentiles.as_deref() {
+        Some(list) => parse_percentile_list(list)?,
+        None => DEFAULT_PERCENTILES.to_vec(),
+    };
+    let range = resolve_year_range(params.samples, params.start_year, params.end_year, params.as_of)?;
 
-    let client = pool
-        .get()
-        .await
-        .map_err(|e| {
-            tracing::error!("Failed to get database connection: {}", e);
-            (StatusCode::INTERNAL_SERVER_ERROR, "Database connection error".to_string())
-        })?;
-
-    // Get current year and calculate target years (current year + 1, +2, etc.)
-    let current_year = Utc::now().year();
-    let start_year = current_year - params.samples as i32;
-    let end_year = current_year;
-
-    // Query for temperature data for the specified day/month across multiple years
-    let query = "
-        SELECT EXTRACT(YEAR FROM date)::INTEGER as year, data::TEXT
-        FROM daily 
-        WHERE EXTRACT(MONTH FROM date) = $1::INTEGER 
-        AND EXTRACT(DAY FROM date) = $2::INTEGER
-        AND EXTRACT(YEAR FROM date) BETWEEN $3::INTEGER AND $4::INTEGER
-        AND location = $5
-        AND data IS NOT NULL
-        ORDER BY year
-    ";
     tracing::debug!(
-        "Temperature query parameters - month: {}, day: {}, samples: {}, location: {}, start_year: {}, end_year: {}",
-        params.month, params.day, params.samples, params.location, start_year, end_year
+        "Temperature query parameters - month: {}, day: {}, location: {}, start_year: {}, end_year: {}",
+        params.month, params.day, params.location, range.start_year, range.end_year
     );
-    let rows = client
-        .query(query, &[&(params.month as i32), &(params.day as i32), &start_year, &end_year, &params.location])
+    let rows = repo
+        .day_temperatures(&params.location, params.month, params.day, range.start_year, range.end_year, params.qc)
         .await
-        .map_err(|e| {
-            tracing::error!("Failed to query temperature data: {}", e);
-            (StatusCode::INTERNAL_SERVER_ERROR, "Database query error".to_string())
-        })?;
+        .map_err(internal_error("Failed to query temperature data"))?;
 
     if rows.is_empty() {
-        return Err((StatusCode::NOT_FOUND, "No temperature data found for the specified date range".to_string()));
+        return Err(ApiError::not_found("no_data", "No temperature data found for the specified date range"));
     }
 
-    let mut temperatures = Vec::new();
-    let mut years_included = Vec::new();
+    // TMAX, TMIN and TAVG are summarised independently; TAVG is only what the
+    // station reported, never derived from TMIN/TMAX.
+    let elements = [Element::Tmax, Element::Tmin, Element::Tavg];
+    let mut samples: [Vec<YearValue>; 3] = Default::default();
+    let mut years_with_data = std::collections::HashSet::new();
+    let mut values_dropped = 0;
 
     for row in rows {
-        let year: i32 = row.get(0);
-        let data_str: String = row.get(1);
-        let data: Value = serde_json::from_str(&data_str).unwrap_or_default();
-        
-        years_included.push(year as i32);
-        // Extract temperature fields from array format
-        let mut temp_data = std::collections::HashMap::new();
-        
-        if let Some(data_array) = data.as_array() {
-            for item in data_array {
-            if let Some(obj) = item.as_object() {
-                for (key, value) in obj {
-                if key == "TAVG" || key == "TMAX" || key == "TMIN" {
-                    temp_data.insert(key.clone(), value.clone());
-                }
-                }
-            }
-            }
-        }
-        
-        let data = serde_json::Value::Object(serde_json::Map::from_iter(temp_data));
-        // Try to get TAVG first, otherwise calculate average of TMIN and TMAX
-        if let Some(tavg) = data.get("TAVG") {
-            if let Some(temp_val) = tavg.as_f64() {
-                temperatures.push(temp_val);
-            } else if let Some(temp_str) = tavg.as_str() {
-                if let Ok(temp_val) = temp_str.parse::<f64>() {
-                    temperatures.push(temp_val);
-                }
-            }
-        } else {
-            // Calculate average from TMIN and TMAX
-            let tmin = data.get("TMIN").and_then(|v| {
-                v.as_f64().or_else(|| v.as_str().and_then(|s| s.parse::<f64>().ok()))
-            });
-            let tmax = data.get("TMAX").and_then(|v| {
-                v.as_f64().or_else(|| v.as_str().and_then(|s| s.parse::<f64>().ok()))
-            });
-
-            if let (Some(min), Some(max)) = (tmin, tmax) {
-                temperatures.push((min + max) / 2.0);
+        for (element, (sample, value)) in elements.iter().zip(samples.iter_mut().zip([row.tmax, row.tmin, row.tavg])) {
+            if let Some(value) = value {
+                sample.push(YearValue { year: row.year, value: element.convert(value, params.units) });
+                years_with_data.insert(row.year);
             }
         }
+        values_dropped += row.values_dropped;
     }
 
-    if temperatures.is_empty() {
-        return Err((StatusCode::NOT_FOUND, "No valid temperature data found".to_string()));
+    if years_with_data.is_empty() {
+        return Err(ApiError::not_found("no_data", "No valid temperature data found"));
     }
 
-    let average_temperature = temperatures.iter().sum::<f64>() / temperatures.len() as f64;
+    let [tmax, tmin, tavg] = samples.map(|sample| Summary::from_values(sample, &percentiles));
 
     let response = TemperatureResponse {
         day: params.day,
         month: params.month,
-        samples_requested: params.samples,
-        samples_found: temperatures.len() as u32,
-        average_temperature,
-        temperatures,
+        start_year: range.start_year,
+        end_year: range.end_year,
+        as_of: range.as_of,
+        samples_requested: range.years(),
+        samples_found: years_with_data.len() as u32,
+        units: params.units,
+        temperature_unit: Element::Tavg.unit_label(params.units).to_string(),
+        qc: params.qc,
+        values_dropped,
+        percentiles,
+        tmax,
+        tmin,
+        tavg,
     };
 
-    Ok(Json(response))
+    Ok(Export::new(format, response))
 }
 
-pub async fn get_total_precipitation_by_month(
+/// Monthly precipitation totals
+///
+/// Total precipitation of a month in each year of a range.
+#[utoipa::path(
+    get,
+    path = "/get_total_precipitation_by_month",
+    tag = "observations",
+    params(PrecipitationRequest, FormatParams),
+    responses(
+        (status = 200, description = "Precipitation per year", body = PrecipitationResponse),
+        (status = 400, response = BadRequest),
+    )
+)]
+pub async fn get_total_precipitation_by_month<R: ClimateRepository>(
     Query(params): Query<PrecipitationRequest>,
-    State(pool): State<DbPool>,
-) -> Result<Json<PrecipitationResponse>, (StatusCode, String)> {
+    format: Format,
+    State(repo): State<R>,
+) -> Result<Export<PrecipitationResponse>, ApiError> {
     // Validate input parameters
     if params.month == 0 || params.month > 12 {
-        return Err((StatusCode::BAD_REQUEST, "Month must be between 1 and 12".to_string()));
-    }
-    if params.samples == 0 {
-        return Err((StatusCode::BAD_REQUEST, "Samples must be greater than 0".to_string()));
+        return Err(ApiError::invalid_parameter("month", "Month must be between 1 and 12"));
     }
+    let range = resolve_year_range(params.samples, params.start_year, params.end_year, params.as_of)?;
 
-    let client = pool
-        .get()
-        .await
-        .map_err(|e| {
-            tracing::error!("Failed to get database connection: {}", e);
-            (StatusCode::INTERNAL_SERVER_ERROR, "Database connection error".to_string())
-        })?;
-
-    // Get current year and calculate target years (current year, current year - 1, etc.)
-    let current_year = Utc::now().year();
-    let start_year = current_year - params.samples as i32 + 1;
-    let end_year = current_year;
-
-    // Query for precipitation data for the specified month across multiple years
-    let query = "
-        SELECT EXTRACT(YEAR FROM date)::INTEGER as year, data::TEXT
-        FROM daily 
-        WHERE EXTRACT(MONTH FROM date) = $1::INTEGER
-        AND EXTRACT(YEAR FROM date) BETWEEN $2::INTEGER AND $3::INTEGER
-        AND location = $4
-        AND data IS NOT NULL
-        ORDER BY year
-    ";
-
-    let rows = client
-        .query(query, &[&(params.month as i32), &start_year, &end_year, &params.location])
+    let rows = repo
+        .precipitation_totals(&params.location, Some(params.month), range.start_year, range.end_year, params.qc)
         .await
-        .map_err(|e| {
-            tracing::error!("Failed to query precipitation data: {}", e);
-            (StatusCode::INTERNAL_SERVER_ERROR, "Database query error".to_string())
-        })?;
+        .map_err(internal_error("Failed to query precipitation data"))?;
 
     let mut precipitation_by_year = std::collections::HashMap::new();
     let mut years_with_data = std::collections::HashSet::new();
+    let mut values_dropped = 0;
 
     for row in rows {
-        let year: i32 = row.get(0);
-        let year_int = year as i32;
-        let data_str: String = row.get(1);
-        let data: Value = serde_json::from_str(&data_str).unwrap_or_default();
-        years_with_data.insert(year_int);
-
-        // Get PRCP value, default to 0.0 if not present
-        // Find the PRCP value from the data array
-        let prcp_value = if let Some(data_array) = data.as_array() {
-            data_array.iter()
-                .find_map(|item| item.get("PRCP"))
-                .and_then(|v| {
-                    v.as_f64().or_else(|| v.as_str().and_then(|s| s.parse::<f64>().ok()))
-                })
-                .unwrap_or(0.0)
-        } else {
-            // Fallback to original logic if data is not an array
-            data.get("PRCP")
-                .and_then(|v| {
-                    v.as_f64().or_else(|| v.as_str().and_then(|s| s.parse::<f64>().ok()))
-                })
-                .unwrap_or(0.0)
-        };
-
-        *precipitation_by_year.entry(year_int).or_insert(0.0) += prcp_value;
+        years_with_data.insert(row.year);
+        precipitation_by_year.insert(row.year, Element::Prcp.convert(row.total, params.units));
+        values_dropped += row.values_dropped;
     }
 
     let mut years_included: Vec<i32> = years_with_data.into_iter().collect();
@@ -240,99 +269,835 @@ pub async fn get_total_precipitation_by_month(
 
     let response = PrecipitationResponse {
         month: params.month,
-        samples_requested: params.samples,
+        start_year: range.start_year,
+        end_year: range.end_year,
+        as_of: range.as_of,
+        samples_requested: range.years(),
         samples_found: years_included.len() as u32,
+        units: params.units,
+        precipitation_unit: Element::Prcp.unit_label(params.units).to_string(),
+        qc: params.qc,
+        values_dropped,
         precipitation_by_year,
     };
 
-    Ok(Json(response))
+    Ok(Export::new(format, response))
 }
 
-pub async fn get_yearly_precipitation(
+/// Yearly precipitation totals
+///
+/// Total precipitation of each year of a range.
+#[utoipa::path(
+    get,
+    path = "/get_yearly_precipitation",
+    tag = "observations",
+    params(YearlyPrecipitationRequest, FormatParams),
+    responses(
+        (status = 200, description = "Precipitation per year", body = YearlyPrecipitationResponse),
+        (status = 400, response = BadRequest),
+        (status = 404, response = NotFound),
+    )
+)]
+pub async fn get_yearly_precipitation<R: ClimateRepository>(
     Query(params): Query<YearlyPrecipitationRequest>,
-    State(pool): State<DbPool>,
-) -> Result<Json<YearlyPrecipitationResponse>, (StatusCode, String)> {
+    format: Format,
+    State(repo): State<R>,
+) -> Result<Export<YearlyPrecipitationResponse>, ApiError> {
     // Validate input parameters
-    if params.samples == 0 {
-        return Err((StatusCode::BAD_REQUEST, "Samples must be greater than 0".to_string()));
-    }
-
-    let client = pool
-        .get()
-        .await
-        .map_err(|e| {
-            tracing::error!("Failed to get database connection: {}", e);
-            (StatusCode::INTERNAL_SERVER_ERROR, "Database connection error".to_string())
-        })?;
-
-    // Get current year and calculate target years going back
-    let current_year = Utc::now().year();
-    let start_year = current_year - params.samples as i32 + 1;
-    let end_year = current_year;
-
-    // Query for all precipitation data across the specified years
-    let query = "
-        SELECT EXTRACT(YEAR FROM date)::INTEGER as year, data::TEXT
-        FROM daily 
-        WHERE EXTRACT(YEAR FROM date) BETWEEN $1::INTEGER AND $2::INTEGER
-        AND location = $3
-        AND data IS NOT NULL
-        ORDER BY year
-    ";
+    let range = resolve_year_range(params.samples, params.start_year, params.end_year, params.as_of)?;
 
     tracing::debug!(
-        "Yearly precipitation query parameters - samples: {}, location: {}, start_year: {}, end_year: {}",
-        params.samples, params.location, start_year, end_year
+        "Yearly precipitation query parameters - location: {}, start_year: {}, end_year: {}",
+        params.location, range.start_year, range.end_year
     );
 
-    let rows = client
-        .query(query, &[&start_year, &end_year, &params.location])
+    // Days without a (QC-passing) PRCP value contribute nothing
+    let rows = repo
+        .precipitation_totals(&params.location, None, range.start_year, range.end_year, params.qc)
         .await
-        .map_err(|e| {
-            tracing::error!("Failed to query yearly precipitation data: {}", e);
-            (StatusCode::INTERNAL_SERVER_ERROR, "Database query error".to_string())
-        })?;
+        .map_err(internal_error("Failed to query yearly precipitation data"))?;
 
     let mut yearly_precipitation = std::collections::HashMap::new();
-    let mut years_with_data = std::collections::HashSet::new();
+    let mut values_dropped = 0;
 
     for row in rows {
-        let year: i32 = row.get(0);
-        let data_str: String = row.get(1);
-        let data: Value = serde_json::from_str(&data_str).unwrap_or_default();
-        
-        years_with_data.insert(year);
-
-        // Get PRCP value from the data, handling both array and object formats
-        let prcp_value = if let Some(data_array) = data.as_array() {
-            data_array.iter()
-                .find_map(|item| item.get("PRCP"))
-                .and_then(|v| {
-                    v.as_f64().or_else(|| v.as_str().and_then(|s| s.parse::<f64>().ok()))
-                })
-                .unwrap_or(0.0)
-        } else {
-            // Fallback to original logic if data is not an array
-            data.get("PRCP")
-                .and_then(|v| {
-                    v.as_f64().or_else(|| v.as_str().and_then(|s| s.parse::<f64>().ok()))
-                })
-                .unwrap_or(0.0)
-        };
-
-        // Sum up precipitation for the entire year
-        *yearly_precipitation.entry(year).or_insert(0.0) += prcp_value;
+        yearly_precipitation.insert(row.year, Element::Prcp.convert(row.total, params.units));
+        values_dropped += row.values_dropped;
     }
 
     if yearly_precipitation.is_empty() {
-        return Err((StatusCode::NOT_FOUND, "No precipitation data found for the specified years".to_string()));
+        return Err(ApiError::not_found("no_data", "No precipitation data found for the specified years"));
     }
 
     let response = YearlyPrecipitationResponse {
-        samples: params.samples,
+        start_year: range.start_year,
+        end_year: range.end_year,
+        as_of: range.as_of,
+        samples: range.years(),
         samples_found: yearly_precipitation.len() as u32,
+        units: params.units,
+        precipitation_unit: Element::Prcp.unit_label(params.units).to_string(),
+        qc: params.qc,
+        values_dropped,
         yearly_precipitation,
     };
 
+    Ok(Export::new(format, response))
+}
+
+/// Parse a comma-separated percentile list (`10, 90`) into values within 0-100.
+fn parse_percentile_list(percentiles: &str) -> Result<Vec<f64>, ApiError> {
+    let mut values = Vec::new();
+    for item in percentiles.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        match item.parse::<f64>() {
+            Ok(p) if (0.0..=100.0).contains(&p) => values.push(p),
+            _ => return Err(ApiError::invalid_parameter("percentiles", format!("Invalid percentile: {} (must be between 0 and 100)", item))),
+        }
+    }
+    values.sort_by(f64::total_cmp);
+    values.dedup();
+    Ok(values)
+}
+
+/// Parse a comma-separated element list (`TMAX, tmin`) into elements.
+fn parse_element_list(elements: &str) -> Result<Vec<Element>, ApiError> {
+    let mut codes = Vec::new();
+    for code in elements.split(',').map(str::trim).filter(|c| !c.is_empty()) {
+        if !code.chars().all(|c| c.is_ascii_alphanumeric()) || code.len() > 8 {
+            return Err(ApiError::invalid_parameter("elements", format!("Invalid element code: {}", code)));
+        }
+        let element = Element::from_code(code);
+        if !codes.contains(&element) {
+            codes.push(element);
+        }
+    }
+    if codes.is_empty() {
+        return Err(ApiError::invalid_parameter("elements", "Elements must list at least one element code"));
+    }
+    Ok(codes)
+}
+
+/// Daily observations
+///
+/// The decoded per-day observations of a location between two dates, one entry
+/// per station and day. In the tabular formats every observation is a row and
+/// all matching rows are returned unless `limit` is given.
+#[utoipa::path(
+    get,
+    path = "/get_daily_observations",
+    tag = "observations",
+    params(DailyObservationsRequest, FormatParams),
+    responses(
+        (status = 200, description = "A page of observations", body = DailyObservationsResponse),
+        (status = 400, response = BadRequest),
+    )
+)]
+pub async fn get_daily_observations<R: ClimateRepository>(
+    Query(params): Query<DailyObservationsRequest>,
+    format: Format,
+    State(repo): State<R>,
+) -> Result<Response, ApiError> {
+    // Validate input parameters
+    if params.start_date > params.end_date {
+        return Err(ApiError::invalid_parameter("start_date", "start_date must not be after end_date"));
+    }
+    let offset = params.offset.unwrap_or(0);
+    let elements = params.elements.as_deref().map(parse_element_list).transpose()?;
+    if format != Format::Json {
+        if params.limit == Some(0) {
+            return Err(ApiError::invalid_parameter("limit", "Limit must be at least 1"));
+        }
+        return export_daily_observations(repo, params, elements, offset, format).await;
+    }
+    let limit = params.limit.unwrap_or(DEFAULT_OBSERVATION_LIMIT);
+    if limit == 0 || limit > MAX_OBSERVATION_LIMIT {
+        return Err(ApiError::invalid_parameter("limit", format!("Limit must be between 1 and {}", MAX_OBSERVATION_LIMIT)));
+    }
+    let element_codes: Option<Vec<String>> = elements
+        .as_ref()
+        .map(|list| list.iter().map(|e| e.code().to_string()).collect());
+
+    tracing::debug!(
+        "Daily observations query parameters - location: {}, start_date: {}, end_date: {}, elements: {:?}, limit: {}, offset: {}",
+        params.location, params.start_date, params.end_date, element_codes, limit, offset
+    );
+
+    let page = repo
+        .daily_observations(&params.location, params.start_date, params.end_date, elements.as_deref(), limit, offset)
+        .await
+        .map_err(internal_error("Failed to query daily observations"))?;
+
+    let mut element_units = BTreeMap::new();
+    let mut values_dropped = 0;
+    let observations = page
+        .days
+        .into_iter()
+        .map(|day| {
+            let (selected, dropped) = observations::select(day.observations, elements.as_deref(), params.qc);
+            values_dropped += dropped;
+
+            let mut values = BTreeMap::new();
+            let mut flags = BTreeMap::new();
+            for observation in selected {
+                let code = observation.element.code().to_string();
+                element_units
+                    .entry(code.clone())
+                    .or_insert_with(|| observation.element.unit_label(params.units).to_string());
+                if observation.has_flags() {
+                    flags.insert(code.clone(), ObservationFlags::from(&observation));
+                }
+                values.insert(code, observation.element.convert(observation.value, params.units));
+            }
+            DailyObservation {
+                date: day.date,
+                station_id: Some(day.station_id),
+                values,
+                flags,
+            }
+        })
+        .collect();
+
+    let response = DailyObservationsResponse {
+        location: params.location,
+        start_date: params.start_date,
+        end_date: params.end_date,
+        elements: element_codes,
+        limit,
+        offset,
+        total: page.total,
+        units: params.units,
+        element_units,
+        qc: params.qc,
+        values_dropped,
+        rows_corrupt: page.rows_corrupt,
+        observations,
+    };
+
+    Ok(export::json(response))
+}
+
+/// Stream the observations of a daily observations request as a table, one
+/// row per observation, straight from the repository's cursor.
+async fn export_daily_observations<R: ClimateRepository>(
+    repo: R,
+    params: DailyObservationsRequest,
+    elements: Option<Vec<Element>>,
+    offset: u32,
+    format: Format,
+) -> Result<Response, ApiError> {
+    let mut days = repo
+        .stream_daily_observations(&params.location, params.start_date, params.end_date, elements.as_deref(), params.limit, offset)
+        .await
+        .map_err(internal_error("Failed to query daily observations"))?;
+
+    // A query failing on the first rows still gets an error response; later
+    // failures can only cut the body short
+    let first = days
+        .next()
+        .await
+        .transpose()
+        .map_err(internal_error("Failed to query daily observations"))?;
+
+    let (units, qc) = (params.units, params.qc);
+    let rows = futures_util::stream::iter(first.map(Ok)).chain(days).flat_map(move |day| {
+        let rows: Vec<std::io::Result<Vec<Cell>>> = match day {
+            Ok(day) => {
+                let (selected, _) = observations::select(day.observations, elements.as_deref(), qc);
+                selected
+                    .iter()
+                    .map(|o| Ok(tables::observation_row(day.date, &day.station_id, o, units)))
+                    .collect()
+            }
+            Err(e) => {
+                tracing::error!("Failed to read daily observations: {}", e);
+                vec![Err(std::io::Error::other(e))]
+            }
+        };
+        futures_util::stream::iter(rows)
+    });
+    export::stream(format, tables::DAILY_OBSERVATIONS, tables::DAILY_OBSERVATION_COLUMNS, rows)
+}
+
+/// Resolve a normals base period, defaulting to 1991-2020.
+fn resolve_base_period(start_year: Option<i32>, end_year: Option<i32>) -> Result<(i32, i32), ApiError> {
+    let start_year = start_year.unwrap_or(normals::DEFAULT_BASE_START_YEAR);
+    let end_year = end_year.unwrap_or(normals::DEFAULT_BASE_END_YEAR);
+    if start_year > end_year {
+        return Err(ApiError::invalid_parameter("base_start_year", "base_start_year must not be after base_end_year"));
+    }
+    Ok((start_year, end_year))
+}
+
+/// Compute and store normals
+///
+/// Computes daily climate normals of TMAX, TMIN and PRCP over a base period and
+/// stores them, replacing those of the same location and base period.
+#[utoipa::path(
+    post,
+    path = "/compute_normals",
+    tag = "normals",
+    params(ComputeNormalsRequest),
+    responses(
+        (status = 200, description = "Normals computed and stored", body = ComputeNormalsResponse),
+        (status = 400, response = BadRequest),
+        (status = 404, response = NotFound),
+    )
+)]
+pub async fn compute_normals<R: ClimateRepository>(
+    Query(params): Query<ComputeNormalsRequest>,
+    State(repo): State<R>,
+) -> Result<Json<ComputeNormalsResponse>, ApiError> {
+    let (base_start_year, base_end_year) = resolve_base_period(params.base_start_year, params.base_end_year)?;
+
+    let base = repo
+        .base_sums(&params.location, base_start_year, base_end_year, params.qc)
+        .await
+        .map_err(internal_error("Failed to query normals base period"))?;
+    let values_dropped = base.values_dropped;
+
+    let computed = normals::smooth(base.sums, params.method);
+    if computed.is_empty() {
+        return Err(ApiError::not_found(
+            "insufficient_data",
+            format!(
+                "Not enough data to compute normals (at least {} years of TMAX, TMIN or PRCP are required)",
+                normals::MIN_YEARS
+            ),
+        ));
+    }
+
+    repo.store_normals(&params.location, base_start_year, base_end_year, params.qc, params.method, &computed)
+        .await
+        .map_err(internal_error("Failed to store normals"))?;
+
+    tracing::info!(
+        "Computed {} normals for {} ({}-{})",
+        params.method.as_str(), params.location, base_start_year, base_end_year
+    );
+
+    let response = ComputeNormalsResponse {
+        location: params.location,
+        base_start_year,
+        base_end_year,
+        method: params.method,
+        qc: params.qc,
+        values_dropped,
+        elements: computed
+            .iter()
+            .map(|n| ElementNormalsSummary {
+                element: n.element.code().to_string(),
+                years: n.years,
+                days_with_data: n.sample_counts.iter().filter(|c| **c > 0).count() as u32,
+            })
+            .collect(),
+    };
+
     Ok(Json(response))
 }
+
+/// Stored normals
+///
+/// The normals stored by `POST /compute_normals` for a single date or every
+/// date of a year.
+#[utoipa::path(
+    get,
+    path = "/get_normals",
+    tag = "normals",
+    params(NormalsRequest, FormatParams),
+    responses(
+        (status = 200, description = "Daily normals", body = NormalsResponse),
+        (status = 400, response = BadRequest),
+        (status = 404, response = NotFound),
+    )
+)]
+pub async fn get_normals<R: ClimateRepository>(
+    Query(params): Query<NormalsRequest>,
+    format: Format,
+    State(repo): State<R>,
+) -> Result<Export<NormalsResponse>, ApiError> {
+    let (base_start_year, base_end_year) = resolve_base_period(params.base_start_year, params.base_end_year)?;
+    let dates: Vec<NaiveDate> = match (params.date, params.year) {
+        (Some(date), None) => vec![date],
+        (None, Some(year)) => {
+            let first = NaiveDate::from_ymd_opt(year, 1, 1)
+                .ok_or_else(|| ApiError::invalid_parameter("year", format!("Invalid year: {}", year)))?;
+            first.iter_days().take_while(|d| d.year() == year).collect()
+        }
+        (Some(_), Some(_)) => {
+            return Err(ApiError::invalid_parameter("date", "Exactly one of date or year must be provided"));
+        }
+        (None, None) => return Err(ApiError::missing_parameter("date", "Exactly one of date or year must be provided")),
+    };
+
+    let table = repo
+        .load_normals(&params.location, base_start_year, base_end_year, params.qc)
+        .await
+        .map_err(internal_error("Failed to query normals"))?;
+
+    if table.is_empty() {
+        return Err(ApiError::not_found(
+            "normals_not_found",
+            format!(
+                "No normals stored for {} ({}-{}, qc={}); compute them with POST /compute_normals",
+                params.location, base_start_year, base_end_year, params.qc.as_str()
+            ),
+        ));
+    }
+
+    let units = params.units;
+    let normal = |element: &Element, date: NaiveDate| table.value(element, date).map(|v| element.convert(v, units));
+    let normals = dates
+        .into_iter()
+        .map(|date| DailyNormal {
+            date,
+            day_of_year: normals::day_of_year(date),
+            tmax: normal(&Element::Tmax, date),
+            tmin: normal(&Element::Tmin, date),
+            prcp: normal(&Element::Prcp, date),
+        })
+        .collect();
+
+    let response = NormalsResponse {
+        location: params.location,
+        base_start_year,
+        base_end_year,
+        method: table.smoothing,
+        qc: params.qc,
+        units,
+        element_units: NORMAL_ELEMENTS
+            .iter()
+            .map(|e| (e.code().to_string(), e.unit_label(units).to_string()))
+            .collect(),
+        normals,
+    };
+
+    Ok(Export::new(format, response))
+}
+
+/// Departures from normal
+///
+/// Each day's departure from the climatological baseline for TMAX, TMIN and
+/// PRCP, with cumulative departures, percentile ranks and a summary.
+#[utoipa::path(
+    get,
+    path = "/get_anomalies",
+    tag = "normals",
+    params(AnomalyRequest, FormatParams),
+    responses(
+        (status = 200, description = "Daily departures and their summary", body = AnomalyResponse),
+        (status = 400, response = BadRequest),
+        (status = 404, response = NotFound),
+    )
+)]
+pub async fn get_anomalies<R: ClimateRepository>(
+    Query(params): Query<AnomalyRequest>,
+    format: Format,
+    State(repo): State<R>,
+) -> Result<Export<AnomalyResponse>, ApiError> {
+    // Validate input parameters
+    if params.start_date > params.end_date {
+        return Err(ApiError::invalid_parameter("start_date", "start_date must not be after end_date"));
+    }
+    if (params.end_date - params.start_date).num_days() >= MAX_ANOMALY_DAYS {
+        return Err(ApiError::invalid_parameter("end_date", format!("Date range must not exceed {} days", MAX_ANOMALY_DAYS)));
+    }
+    let (base_start_year, base_end_year) = resolve_base_period(params.base_start_year, params.base_end_year)?;
+
+    let observed = repo
+        .element_days(&params.location, params.start_date, params.end_date, params.qc)
+        .await
+        .map_err(internal_error("Failed to query observations for anomalies"))?;
+    if observed.days.is_empty() {
+        return Err(ApiError::not_found("no_data", "No observations found for the specified date range"));
+    }
+    let values_dropped = observed.values_dropped;
+
+    let (base_start, base_end) = repository::year_span(base_start_year, base_end_year);
+    let base_days = repo
+        .element_days(&params.location, base_start, base_end, params.qc)
+        .await
+        .map_err(internal_error("Failed to query anomaly baseline period"))?
+        .days;
+    let climatology = Climatology::from_days(&base_days);
+
+    // Prefer the normals stored for the same QC policy; otherwise derive them from
+    // the baseline rows we already loaded for the percentile ranks.
+    let stored = repo
+        .load_normals(&params.location, base_start_year, base_end_year, params.qc)
+        .await
+        .map_err(internal_error("Failed to query normals"))?;
+    let (baseline, source) = if stored.is_empty() {
+        let smoothing = normals::Smoothing::default();
+        let computed = normals::compute(base_days, smoothing);
+        (NormalsTable::from_computed(smoothing, &computed), BaselineSource::Computed)
+    } else {
+        (stored, BaselineSource::Stored)
+    };
+    if baseline.is_empty() {
+        return Err(ApiError::not_found(
+            "insufficient_data",
+            format!(
+                "Not enough data in {}-{} to build a baseline (at least {} years are required)",
+                base_start_year, base_end_year, normals::MIN_YEARS
+            ),
+        ));
+    }
+
+    let (days, summary) = anomalies::departures(&observed.days, &baseline, &climatology, params.units);
+
+    let response = AnomalyResponse {
+        location: params.location,
+        start_date: params.start_date,
+        end_date: params.end_date,
+        base_start_year,
+        base_end_year,
+        baseline: source,
+        method: baseline.smoothing,
+        units: params.units,
+        temperature_unit: Element::Tmax.unit_label(params.units).to_string(),
+        precipitation_unit: Element::Prcp.unit_label(params.units).to_string(),
+        qc: params.qc,
+        values_dropped,
+        summary,
+        days,
+    };
+
+    Ok(Export::new(format, response))
+}
+
+/// Nearest stations
+///
+/// Stations of the catalogue closest to a point, ranked by distance and data
+/// coverage.
+#[utoipa::path(
+    get,
+    path = "/get_nearest_stations",
+    tag = "stations",
+    params(NearestStationsRequest, FormatParams),
+    responses(
+        (status = 200, description = "Stations by effective distance", body = NearestStationsResponse),
+        (status = 400, response = BadRequest),
+    )
+)]
+pub async fn get_nearest_stations<R: ClimateRepository>(
+    Query(params): Query<NearestStationsRequest>,
+    format: Format,
+    State(repo): State<R>,
+) -> Result<Export<NearestStationsResponse>, ApiError> {
+    // Validate input parameters
+    validate_coordinates(params.latitude, params.longitude)?;
+    let limit = params.limit.unwrap_or(DEFAULT_STATION_LIMIT);
+    if limit == 0 || limit > MAX_STATION_LIMIT {
+        return Err(ApiError::invalid_parameter("limit", format!("Limit must be between 1 and {}", MAX_STATION_LIMIT)));
+    }
+    let max_distance_km = params.max_distance_km.unwrap_or(DEFAULT_STATION_DISTANCE_KM);
+    if !(max_distance_km > 0.0 && max_distance_km <= MAX_STATION_DISTANCE_KM) {
+        return Err(ApiError::invalid_parameter(
+            "max_distance_km",
+            format!("max_distance_km must be greater than 0 and at most {}", MAX_STATION_DISTANCE_KM),
+        ));
+    }
+    let elements = match params.elements.as_deref() {
+        Some(list) => parse_element_list(list)?,
+        None => NORMAL_ELEMENTS.to_vec(),
+    };
+    let start_year = params.start_year.unwrap_or(normals::DEFAULT_BASE_START_YEAR);
+    let end_year = params.end_year.unwrap_or_else(|| Utc::now().year());
+    if start_year > end_year {
+        return Err(ApiError::invalid_parameter("start_year", "start_year must not be after end_year"));
+    }
+
+    let candidates = repo
+        .stations_within(params.latitude, params.longitude, max_distance_km)
+        .await
+        .map_err(internal_error("Failed to query stations"))?;
+
+    let mut nearest: Vec<NearestStation> = candidates
+        .into_iter()
+        .filter_map(|station| {
+            let distance_km = stations::distance_km(params.latitude, params.longitude, station.latitude, station.longitude);
+            if distance_km > max_distance_km {
+                return None;
+            }
+            let coverage = stations::coverage(&station.elements, &elements, start_year, end_year);
+            Some(NearestStation {
+                station_id: station.station_id,
+                name: station.name,
+                latitude: station.latitude,
+                longitude: station.longitude,
+                elevation: station.elevation,
+                state: station.state,
+                wmo_id: station.wmo_id,
+                distance_km,
+                coverage,
+                effective_distance_km: (coverage > 0.0).then(|| distance_km / coverage),
+                elements: station.elements,
+            })
+        })
+        .collect();
+
+    // Stations with coverage first, closest effective distance first
+    nearest.sort_by(|a, b| match (a.effective_distance_km, b.effective_distance_km) {
+        (Some(a), Some(b)) => a.total_cmp(&b),
+        (Some(_), None) => std::cmp::Ordering::Less,
+        (None, Some(_)) => std::cmp::Ordering::Greater,
+        (None, None) => a.distance_km.total_cmp(&b.distance_km),
+    });
+    nearest.truncate(limit as usize);
+
+    let response = NearestStationsResponse {
+        latitude: params.latitude,
+        longitude: params.longitude,
+        max_distance_km,
+        elements,
+        start_year,
+        end_year,
+        stations: nearest,
+    };
+
+    Ok(Export::new(format, response))
+}
+
+/// Check the registry fields of a location, normalising the country code.
+fn validate_location_details(mut details: LocationDetails) -> Result<LocationDetails, ApiError> {
+    if details.display_name.trim().is_empty() {
+        return Err(ApiError::invalid_parameter("display_name", "display_name must not be empty"));
+    }
+    validate_coordinates(details.latitude, details.longitude)?;
+    if details.timezone.parse::<chrono_tz::Tz>().is_err() {
+        return Err(ApiError::invalid_parameter("timezone", format!("Unknown IANA time zone: {}", details.timezone)));
+    }
+    if let Some(country) = &details.country {
+        if country.len() != 2 || !country.chars().all(|c| c.is_ascii_alphabetic()) {
+            return Err(ApiError::invalid_parameter("country", format!("Invalid country code: {} (expected ISO 3166-1 alpha-2)", country)));
+        }
+        details.country = Some(country.to_ascii_uppercase());
+    }
+    for station_id in &details.station_ids {
+        if station_id.len() != 11 || !station_id.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return Err(ApiError::invalid_parameter("station_ids", format!("Invalid GHCN station ID: {}", station_id)));
+        }
+    }
+    Ok(details)
+}
+
+/// Register a location
+#[utoipa::path(
+    post,
+    path = "/admin/locations",
+    tag = "admin",
+    request_body = NewLocation,
+    responses(
+        (status = 201, description = "Location registered", body = Location),
+        (status = 400, response = BadRequest),
+        (status = 409, response = Conflict),
+        (status = 415, response = UnsupportedMediaType),
+        (status = 422, response = UnprocessableEntity),
+    )
+)]
+pub async fn create_location<R: ClimateRepository>(
+    State(repo): State<R>,
+    Json(request): Json<NewLocation>,
+) -> Result<(StatusCode, Json<Location>), ApiError> {
+    if request.location.trim().is_empty() {
+        return Err(ApiError::invalid_parameter("location", "location must not be empty"));
+    }
+    let details = validate_location_details(request.details)?;
+
+    let created = repo
+        .insert_location(&request.location, &details)
+        .await
+        .map_err(internal_error("Failed to insert location"))?;
+
+    match created {
+        Some(location) => Ok((StatusCode::CREATED, Json(location))),
+        None => Err(ApiError::conflict("location_exists", format!("Location {} already exists", request.location))),
+    }
+}
+
+/// Get a registered location
+#[utoipa::path(
+    get,
+    path = "/admin/locations/{location}",
+    tag = "admin",
+    params(("location" = String, Path, description = "Location name")),
+    responses(
+        (status = 200, description = "The registry entry", body = Location),
+        (status = 404, response = NotFound),
+    )
+)]
+pub async fn get_location<R: ClimateRepository>(
+    Path(location): Path<String>,
+    State(repo): State<R>,
+) -> Result<Json<Location>, ApiError> {
+    let found = repo
+        .get_location(&location)
+        .await
+        .map_err(internal_error("Failed to query location"))?;
+
+    found
+        .map(Json)
+        .ok_or_else(|| ApiError::not_found("location_not_found", format!("Location {} is not registered", location)))
+}
+
+/// Replace the registry fields of a location
+#[utoipa::path(
+    put,
+    path = "/admin/locations/{location}",
+    tag = "admin",
+    params(("location" = String, Path, description = "Location name")),
+    request_body = LocationDetails,
+    responses(
+        (status = 200, description = "The updated registry entry", body = Location),
+        (status = 400, response = BadRequest),
+        (status = 404, response = NotFound),
+        (status = 415, response = UnsupportedMediaType),
+        (status = 422, response = UnprocessableEntity),
+    )
+)]
+pub async fn update_location<R: ClimateRepository>(
+    Path(location): Path<String>,
+    State(repo): State<R>,
+    Json(details): Json<LocationDetails>,
+) -> Result<Json<Location>, ApiError> {
+    let details = validate_location_details(details)?;
+
+    let updated = repo
+        .update_location(&location, &details)
+        .await
+        .map_err(internal_error("Failed to update location"))?;
+
+    updated
+        .map(Json)
+        .ok_or_else(|| ApiError::not_found("location_not_found", format!("Location {} is not registered", location)))
+}
+
+/// Remove a location from the registry
+///
+/// Its daily rows are kept.
+#[utoipa::path(
+    delete,
+    path = "/admin/locations/{location}",
+    tag = "admin",
+    params(("location" = String, Path, description = "Location name")),
+    responses(
+        (status = 204, description = "Location removed"),
+        (status = 404, response = NotFound),
+    )
+)]
+pub async fn delete_location<R: ClimateRepository>(
+    Path(location): Path<String>,
+    State(repo): State<R>,
+) -> Result<StatusCode, ApiError> {
+    let deleted = repo
+        .delete_location(&location)
+        .await
+        .map_err(internal_error("Failed to delete location"))?;
+
+    if deleted {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(ApiError::not_found("location_not_found", format!("Location {} is not registered", location)))
+    }
+}
+
+/// List API keys
+///
+/// Every key with its role and rate limit; the keys themselves are not stored.
+#[utoipa::path(
+    get,
+    path = "/admin/keys",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Every API key", body = Vec<ApiKey>),
+    )
+)]
+pub async fn list_api_keys<R: ClimateRepository>(State(repo): State<R>) -> Result<Json<Vec<ApiKey>>, ApiError> {
+    let keys = repo
+        .list_api_keys()
+        .await
+        .map_err(internal_error("Failed to query API keys"))?;
+    Ok(Json(keys))
+}
+
+/// Create an API key
+///
+/// The key is only part of this response; store it, as it cannot be shown again.
+#[utoipa::path(
+    post,
+    path = "/admin/keys",
+    tag = "admin",
+    request_body = NewApiKey,
+    responses(
+        (status = 201, description = "Key created", body = CreatedApiKey),
+        (status = 400, response = BadRequest),
+        (status = 409, response = Conflict),
+        (status = 415, response = UnsupportedMediaType),
+        (status = 422, response = UnprocessableEntity),
+    )
+)]
+pub async fn create_api_key<R: ClimateRepository>(
+    State(repo): State<R>,
+    Json(request): Json<NewApiKey>,
+) -> Result<(StatusCode, Json<CreatedApiKey>), ApiError> {
+    if request.name.trim().is_empty() {
+        return Err(ApiError::invalid_parameter("name", "name must not be empty"));
+    }
+    if request.rate_limit == Some(0) {
+        return Err(ApiError::invalid_parameter("rate_limit", "rate_limit must be greater than 0"));
+    }
+
+    let created = auth::create_key(&repo, &request)
+        .await
+        .map_err(internal_error("Failed to insert API key"))?;
+
+    match created {
+        Some(created) => {
+            tracing::info!("Created {} key {}", created.details.role.as_str(), created.details.name);
+            Ok((StatusCode::CREATED, Json(created)))
+        }
+        None => Err(ApiError::conflict("key_exists", format!("A key named {} already exists", request.name))),
+    }
+}
+
+/// Revoke an API key
+#[utoipa::path(
+    delete,
+    path = "/admin/keys/{name}",
+    tag = "admin",
+    params(("name" = String, Path, description = "Key name")),
+    responses(
+        (status = 204, description = "Key deleted"),
+        (status = 404, response = NotFound),
+    )
+)]
+pub async fn delete_api_key<R: ClimateRepository>(
+    Path(name): Path<String>,
+    State(repo): State<R>,
+) -> Result<StatusCode, ApiError> {
+    let deleted = repo
+        .delete_api_key(&name)
+        .await
+        .map_err(internal_error("Failed to delete API key"))?;
+
+    if deleted {
+        tracing::info!("Revoked key {}", name);
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(ApiError::not_found("key_not_found", format!("No key named {}", name)))
+    }
+}
+
+/// `GET /healthz`: the process is up.
+pub async fn healthz() -> &'static str {
+    "ok"
+}
+
+/// `GET /readyz`: a database connection can be obtained and queried.
+pub async fn readyz<R: ClimateRepository>(State(repo): State<R>) -> Result<&'static str, ApiError> {
+    repo.ping().await.map_err(|e| {
+        tracing::warn!("Not ready: {}", e);
+        ApiError::from(e)
+    })?;
+    Ok("ok")
+}
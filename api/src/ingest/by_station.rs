@@ -0,0 +1,132 @@
+//! Parser for GHCN-Daily `by_station/*.csv` files.
+//!
+//! Each line holds one element value for one day:
+//! `ID,YYYYMMDD,ELEMENT,DATA_VALUE,M_FLAG,Q_FLAG,S_FLAG,OBS_TIME`, e.g.
+//! `USW00023230,20240115,TMAX,156,,,W,2400`. Files are published without a
+//! header, but a leading `ID,DATE,...` header line is tolerated.
+
+use anyhow::{bail, Context, Result};
+use chrono::NaiveDate;
+use std::io::Read;
+
+use super::{parse_flag, Record, MISSING_VALUE};
+use crate::observations::{Element, Observation};
+
+/// Stream the records of a by-station CSV file.
+pub fn records<R: Read>(reader: R) -> impl Iterator<Item = Result<Record>> {
+    let reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(reader);
+
+    reader.into_records().enumerate().filter_map(|(index, row)| {
+        let line = index + 1;
+        let row = match row {
+            Ok(row) => row,
+            Err(e) => return Some(Err(e).with_context(|| format!("line {}", line))),
+        };
+        if line == 1 && row.get(1).is_some_and(|date| date.trim().eq_ignore_ascii_case("DATE")) {
+            return None;
+        }
+        parse_row(&row).with_context(|| format!("line {}", line)).transpose()
+    })
+}
+
+/// Parse one row; missing values (`-9999`) yield `None`.
+fn parse_row(row: &csv::StringRecord) -> Result<Option<Record>> {
+    if row.len() < 4 {
+        bail!("expected at least 4 fields, found {}", row.len());
+    }
+    let station_id = row[0].trim();
+    let date = NaiveDate::parse_from_str(row[1].trim(), "%Y%m%d")
+        .with_context(|| format!("invalid date {:?}", &row[1]))?;
+    let value: f64 = row[3]
+        .trim()
+        .parse()
+        .with_context(|| format!("invalid value {:?}", &row[3]))?;
+    if value == MISSING_VALUE {
+        return Ok(None);
+    }
+
+    Ok(Some(Record {
+        station_id: station_id.to_string(),
+        date,
+        observation: Observation {
+            element: Element::from_code(&row[2]),
+            value,
+            mflag: row.get(4).and_then(parse_flag),
+            qflag: row.get(5).and_then(parse_flag),
+            sflag: row.get(6).and_then(parse_flag),
+        },
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(csv: &str) -> Result<Vec<Record>> {
+        records(csv.as_bytes()).collect()
+    }
+
+    #[test]
+    fn parses_values_and_flags() {
+        let records = parse(
+            "USW00023230,20240115,TMAX,156,,,W,2400\n\
+             USW00023230,20240115,PRCP,0,T,,W,\n\
+             USW00023230,20240116,TMIN,-33, ,G,7,0700\n",
+        )
+        .unwrap();
+
+        assert!(records.iter().all(|r| r.station_id == "USW00023230"));
+        let dates: Vec<_> = records.iter().map(|r| r.date.to_string()).collect();
+        assert_eq!(dates, ["2024-01-15", "2024-01-15", "2024-01-16"]);
+        let observations: Vec<_> = records.into_iter().map(|r| r.observation).collect();
+        assert_eq!(
+            observations,
+            [
+                Observation { element: Element::Tmax, value: 156.0, mflag: None, qflag: None, sflag: Some('W') },
+                Observation { element: Element::Prcp, value: 0.0, mflag: Some('T'), qflag: None, sflag: Some('W') },
+                Observation { element: Element::Tmin, value: -33.0, mflag: None, qflag: Some('G'), sflag: Some('7') },
+            ]
+        );
+    }
+
+    #[test]
+    fn skips_a_header_and_missing_values() {
+        let records = parse(
+            "ID,DATE,ELEMENT,DATA_VALUE,M_FLAG,Q_FLAG,S_FLAG,OBS_TIME\n\
+             USW00023230,20240115,TMAX,-9999,,,W,\n\
+             USW00023230,20240115,TMIN,44,,,W,\n",
+        )
+        .unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].observation.element, Element::Tmin);
+    }
+
+    #[test]
+    fn rows_without_flag_fields_have_no_flags() {
+        let records = parse("USW00023230,20240115,SNOW,25\n").unwrap();
+        assert!(!records[0].observation.has_flags());
+    }
+
+    #[test]
+    fn rejects_malformed_rows_with_their_line() {
+        let error = |csv: &str| format!("{:#}", parse(csv).unwrap_err());
+        assert_eq!(
+            error("USW00023230,20240115,TMAX,156\nUSW00023230,20240230,TMAX,156\n"),
+            "line 2: invalid date \"20240230\": input is out of range"
+        );
+        assert_eq!(
+            error("USW00023230,2024-01-15,TMAX,156\n"),
+            "line 1: invalid date \"2024-01-15\": input contains invalid characters"
+        );
+        assert_eq!(
+            error("USW00023230,20240115,TMAX,15.6x\n"),
+            "line 1: invalid value \"15.6x\": invalid float literal"
+        );
+        assert_eq!(error("USW00023230,20240115,TMAX\n"), "line 1: expected at least 4 fields, found 3");
+        // A header is only recognised on the first line
+        assert!(error("USW00023230,20240115,TMAX,1\nID,DATE,ELEMENT,DATA_VALUE\n").starts_with("line 2: invalid date"));
+    }
+}
@@ -0,0 +1,206 @@
+//! Parser for GHCN-Daily fixed-width `.dly` files (as found in `ghcnd_all.tar.gz`).
+//!
+//! Each line holds one element of one station for a whole month:
+//!
+//! | Columns | Field                                   |
+//! |---------|-----------------------------------------|
+//! | 1-11    | Station ID                              |
+//! | 12-15   | Year                                    |
+//! | 16-17   | Month                                   |
+//! | 18-21   | Element                                 |
+//! | 22-269  | 31 groups of VALUE (5), MFLAG, QFLAG, SFLAG |
+//!
+//! Days that do not exist in the month carry the missing value `-9999`.
+
+use anyhow::{bail, Context, Result};
+use chrono::NaiveDate;
+use std::io::BufRead;
+
+use super::{parse_flag, Record, MISSING_VALUE};
+use crate::observations::{Element, Observation};
+
+/// Width of the station/year/month/element header.
+const HEADER_WIDTH: usize = 21;
+
+/// Width of one day's value and flags.
+const DAY_WIDTH: usize = 8;
+
+const DAYS_PER_LINE: usize = 31;
+
+/// Stream the records of a `.dly` file.
+pub fn records<R: BufRead>(reader: R) -> impl Iterator<Item = Result<Record>> {
+    reader.lines().enumerate().flat_map(|(index, line)| {
+        let line_number = index + 1;
+        let parsed = line
+            .map_err(anyhow::Error::from)
+            .and_then(|line| parse_line(&line))
+            .with_context(|| format!("line {}", line_number));
+        match parsed {
+            Ok(records) => records.into_iter().map(Ok).collect::<Vec<_>>(),
+            Err(e) => vec![Err(e)],
+        }
+    })
+}
+
+/// Parse one station-month line into its non-missing daily values.
+fn parse_line(line: &str) -> Result<Vec<Record>> {
+    let line = line.trim_end();
+    if line.is_empty() {
+        return Ok(Vec::new());
+    }
+    if !line.is_ascii() || line.len() < HEADER_WIDTH {
+        bail!("expected a {} character header, found {:?}", HEADER_WIDTH, line);
+    }
+
+    let station_id = line[0..11].trim();
+    let year: i32 = line[11..15]
+        .trim()
+        .parse()
+        .with_context(|| format!("invalid year {:?}", &line[11..15]))?;
+    let month: u32 = line[15..17]
+        .trim()
+        .parse()
+        .with_context(|| format!("invalid month {:?}", &line[15..17]))?;
+    let element = Element::from_code(line[17..21].trim());
+
+    let mut records = Vec::new();
+    for day in 1..=DAYS_PER_LINE {
+        let start = HEADER_WIDTH + (day - 1) * DAY_WIDTH;
+        // Trailing blanks may have been stripped from the last groups
+        let Some(group) = line.get(start..).filter(|g| !g.is_empty()) else {
+            break;
+        };
+        let group = &group[..group.len().min(DAY_WIDTH)];
+        let field = group.get(0..5).unwrap_or(group);
+        let value: f64 = field
+            .trim()
+            .parse()
+            .with_context(|| format!("day {}: invalid value {:?}", day, field))?;
+        if value == MISSING_VALUE {
+            continue;
+        }
+        let date = NaiveDate::from_ymd_opt(year, month, day as u32)
+            .with_context(|| format!("day {}: {}-{:02} has no such day", day, year, month))?;
+
+        records.push(Record {
+            station_id: station_id.to_string(),
+            date,
+            observation: Observation {
+                element: element.clone(),
+                value,
+                mflag: group.get(5..6).and_then(parse_flag),
+                qflag: group.get(6..7).and_then(parse_flag),
+                sflag: group.get(7..8).and_then(parse_flag),
+            },
+        });
+    }
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tenths of °C on February 1st and 3rd of a leap year; days 30 and 31 are
+    /// missing, as in every February line.
+    const FEBRUARY_TMAX: &str = concat!(
+        "USW00023230202402TMAX",
+        "  156  W-9999     -33 G7-9999   -9999   -9999   -9999   -9999   -9999   -9999   ",
+        "-9999   -9999   -9999   -9999   -9999   -9999   -9999   -9999   -9999   -9999   ",
+        "-9999   -9999   -9999   -9999   -9999   -9999   -9999   -9999   -9999   -9999   ",
+        "-9999   ",
+    );
+
+    /// Trace and measured precipitation, with the trailing blanks of the last flags stripped.
+    const DECEMBER_PRCP: &str = concat!(
+        "USW00023230202312PRCP",
+        "    0T 7-9999   -9999   -9999   -9999   -9999   -9999   -9999   -9999   -9999   ",
+        "-9999   -9999   -9999   -9999   -9999   -9999   -9999   -9999   -9999   -9999   ",
+        "-9999   -9999   -9999   -9999   -9999   -9999   -9999   -9999   -9999   -9999   ",
+        "   25T",
+    );
+
+    /// A value on a day February does not have.
+    const FEBRUARY_30TH: &str = concat!(
+        "USW00023230202402TMIN",
+        "-9999   -9999   -9999   -9999   -9999   -9999   -9999   -9999   -9999   -9999   ",
+        "-9999   -9999   -9999   -9999   -9999   -9999   -9999   -9999   -9999   -9999   ",
+        "-9999   -9999   -9999   -9999   -9999   -9999   -9999   -9999   -9999      12  W",
+        "-9999   ",
+    );
+
+    fn observations(line: &str) -> Vec<(String, Observation)> {
+        let records = parse_line(line).unwrap();
+        assert!(records.iter().all(|r| r.station_id == "USW00023230"));
+        records.into_iter().map(|r| (r.date.to_string(), r.observation)).collect()
+    }
+
+    fn observation(element: Element, value: f64, flags: [Option<char>; 3]) -> Observation {
+        let [mflag, qflag, sflag] = flags;
+        Observation { element, value, mflag, qflag, sflag }
+    }
+
+    #[test]
+    fn parses_the_days_of_a_line_skipping_missing_values() {
+        assert_eq!(FEBRUARY_TMAX.len(), HEADER_WIDTH + DAYS_PER_LINE * DAY_WIDTH);
+        assert_eq!(
+            observations(FEBRUARY_TMAX),
+            [
+                ("2024-02-01".to_string(), observation(Element::Tmax, 156.0, [None, None, Some('W')])),
+                ("2024-02-03".to_string(), observation(Element::Tmax, -33.0, [None, Some('G'), Some('7')])),
+            ]
+        );
+        // As published, with the blank flags of the last group stripped
+        assert_eq!(parse_line(FEBRUARY_TMAX.trim_end()).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn reads_a_last_group_cut_short() {
+        assert_eq!(
+            observations(DECEMBER_PRCP),
+            [
+                ("2023-12-01".to_string(), observation(Element::Prcp, 0.0, [Some('T'), None, Some('7')])),
+                ("2023-12-31".to_string(), observation(Element::Prcp, 25.0, [Some('T'), None, None])),
+            ]
+        );
+        // Down to the value alone
+        let value_only = &DECEMBER_PRCP[..DECEMBER_PRCP.len() - 1];
+        assert_eq!(observations(value_only)[1].1, observation(Element::Prcp, 25.0, [None; 3]));
+    }
+
+    #[test]
+    fn skips_blank_lines() {
+        assert!(parse_line("").unwrap().is_empty());
+        assert!(parse_line("   ").unwrap().is_empty());
+    }
+
+    #[test]
+    fn rejects_malformed_lines() {
+        let error = |line: &str| format!("{:#}", parse_line(line).unwrap_err());
+        assert_eq!(error("USW000232302024"), "expected a 21 character header, found \"USW000232302024\"");
+        assert!(error("USW00023230 2024 février").starts_with("expected a 21 character header"));
+        assert_eq!(
+            error(&FEBRUARY_TMAX.replace("202402TMAX", "20X402TMAX")),
+            "invalid year \"20X4\": invalid digit found in string"
+        );
+        assert_eq!(
+            error(&FEBRUARY_TMAX.replace("202402TMAX", "2024FETMAX")),
+            "invalid month \"FE\": invalid digit found in string"
+        );
+        assert_eq!(error(&FEBRUARY_TMAX.replace("202402TMAX", "202413TMAX")), "day 1: 2024-13 has no such day");
+        assert_eq!(error(FEBRUARY_30TH), "day 30: 2024-02 has no such day");
+        assert_eq!(
+            error(&FEBRUARY_TMAX.replace("  156", "  1x6")),
+            "day 1: invalid value \"  1x6\": invalid float literal"
+        );
+    }
+
+    #[test]
+    fn reports_the_line_of_an_error() {
+        let file = format!("{}\n\n{}\n", FEBRUARY_TMAX, FEBRUARY_30TH);
+        let results: Vec<_> = records(file.as_bytes()).collect();
+        assert_eq!(results.len(), 3);
+        assert!(results[..2].iter().all(Result::is_ok));
+        assert_eq!(format!("{:#}", results[2].as_ref().unwrap_err()), "line 3: day 30: 2024-02 has no such day");
+    }
+}
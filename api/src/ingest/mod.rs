@@ -0,0 +1,535 @@
+//! `api ingest`: load GHCN-Daily station files (by-station CSV or `.dly`) into
+//! the `daily` table.
+//!
+//! Every file is grouped into one row per station and date, with the day's
+//! elements encoded by [`observations::encode`]. `.dly` files are streamed a
+//! batch at a time; by-station CSV files are held in memory whole, as nothing
+//! orders their lines (see [`DayBatches`]). Rows are written in batches
+//! through `COPY` into a temporary staging table and then upserted on the
+//! unique `(station_id, date)` key, so re-running an ingest never creates
+//! duplicates. Each batch notifies [`cache::CHANGES_CHANNEL`] of the locations
+//! it writes, so running servers drop their cached responses.
+
+mod by_station;
+mod dly;
+pub mod stations;
+
+use anyhow::{bail, Context, Result};
+use chrono::{Datelike, NaiveDate};
+use clap::Args;
+use flate2::read::GzDecoder;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::pin::pin;
+use std::time::Instant;
+use tokio_postgres::binary_copy::BinaryCopyInWriter;
+use tokio_postgres::types::Type;
+
+use crate::cache;
+use crate::db::DbPool;
+use crate::observations::{self, Observation};
+
+/// Value GHCN uses for a missing observation.
+const MISSING_VALUE: f64 = -9999.0;
+
+#[derive(Debug, Args)]
+pub struct IngestArgs {
+    /// GHCN-Daily by-station (`.csv`) or fixed-width (`.dly`) files, optionally
+    /// gzip-compressed, or directories of them such as an extracted `ghcnd_all`
+    #[arg(required = true)]
+    pub files: Vec<PathBuf>,
+
+    /// Location name to store the rows under
+    #[arg(long)]
+    pub location: Option<String>,
+
+    /// Station name to store the rows under
+    #[arg(long)]
+    pub station_name: Option<String>,
+
+    /// Station list with `ID`, `NAME` and `CITY` columns (e.g. `city_stations.csv`
+    /// from `dataprep`), used to look up the location and name of each station
+    #[arg(long)]
+    pub stations: Option<PathBuf>,
+
+    /// Number of days written per COPY batch
+    #[arg(long, default_value_t = 5000)]
+    pub batch_size: usize,
+}
+
+/// One element value of one station and day, as read from a source file.
+#[derive(Debug, Clone)]
+pub struct Record {
+    pub station_id: String,
+    pub date: NaiveDate,
+    pub observation: Observation,
+}
+
+/// A row of the `daily` table.
+struct DailyRow {
+    location: String,
+    station_id: String,
+    station_name: Option<String>,
+    date: NaiveDate,
+    data: Value,
+}
+
+/// Station name and location from the `--stations` list.
+struct StationInfo {
+    name: String,
+    location: String,
+}
+
+/// Layout of a GHCN-Daily station file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    /// `by_station/*.csv`: one element value per line
+    ByStation,
+    /// `ghcnd_all/*.dly`: one element per station-month line
+    Dly,
+}
+
+impl Format {
+    /// Detect the format from the file name, ignoring a `.gz` suffix.
+    fn of(path: &Path) -> Option<Format> {
+        let name = path.file_name()?.to_str()?.to_ascii_lowercase();
+        let name = name.strip_suffix(".gz").unwrap_or(&name);
+        if name.ends_with(".dly") {
+            Some(Format::Dly)
+        } else if name.ends_with(".csv") {
+            Some(Format::ByStation)
+        } else {
+            None
+        }
+    }
+}
+
+/// Parse a GHCN flag field; blank means "no flag".
+fn parse_flag(field: &str) -> Option<char> {
+    field.trim().chars().next()
+}
+
+pub async fn run(args: IngestArgs, pool: DbPool) -> Result<()> {
+    if args.batch_size == 0 {
+        bail!("--batch-size must be greater than 0");
+    }
+    let catalog = match &args.stations {
+        Some(path) => load_station_list(path)?,
+        None if args.location.is_none() => bail!("either --location or --stations is required"),
+        None => HashMap::new(),
+    };
+
+    let files = expand_paths(&args.files)?;
+
+    let mut client = pool.get().await.context("failed to get database connection")?;
+    let started = Instant::now();
+    let (mut total_days, mut total_values) = (0u64, 0u64);
+
+    for (index, path) in files.iter().enumerate() {
+        let Some(format) = Format::of(path) else {
+            bail!("{}: unknown file type, expected .csv or .dly", path.display());
+        };
+        let reader = open(path)?;
+        let records: Box<dyn Iterator<Item = Result<Record>>> = match format {
+            Format::ByStation => Box::new(by_station::records(reader)),
+            Format::Dly => Box::new(dly::records(reader)),
+        };
+
+        let mut days = DayBatches::new(format, args.batch_size);
+        let (mut written, mut value_count) = (0u64, 0u64);
+        // A final `None` hands out the days still buffered at the end of the file
+        for record in records.map(Some).chain([None]) {
+            let batches = match record {
+                Some(record) => {
+                    let record = record.with_context(|| format!("failed to parse {}", path.display()))?;
+                    value_count += 1;
+                    days.push(record).with_context(|| format!("failed to parse {}", path.display()))?
+                }
+                None => days.finish(),
+            };
+            for batch in batches {
+                let rows = daily_rows(batch, &args, &catalog, path)?;
+                write_batch(&mut client, &rows)
+                    .await
+                    .with_context(|| format!("{}: failed to write batch", path.display()))?;
+                written += rows.len() as u64;
+                tracing::info!("[{}/{}] {}: {} days written", index + 1, files.len(), path.display(), written);
+            }
+        }
+
+        total_days += written;
+        total_values += value_count;
+    }
+
+    let elapsed = started.elapsed().as_secs_f64();
+    tracing::info!(
+        "Ingested {} files: {} days, {} values in {:.1}s ({:.0} days/s)",
+        files.len(),
+        total_days,
+        total_values,
+        elapsed,
+        total_days as f64 / elapsed.max(f64::EPSILON)
+    );
+    Ok(())
+}
+
+/// Replace directories by the station files they contain, in name order.
+fn expand_paths(paths: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for path in paths {
+        if path.is_dir() {
+            let mut entries = Vec::new();
+            for entry in fs::read_dir(path).with_context(|| format!("failed to read {}", path.display()))? {
+                let entry = entry.with_context(|| format!("failed to read {}", path.display()))?.path();
+                if entry.is_file() && Format::of(&entry).is_some() {
+                    entries.push(entry);
+                }
+            }
+            if entries.is_empty() {
+                bail!("{}: no .csv or .dly files found", path.display());
+            }
+            entries.sort();
+            files.extend(entries);
+        } else {
+            files.push(path.clone());
+        }
+    }
+    Ok(files)
+}
+
+/// Open a file, transparently decompressing `.gz` files.
+fn open(path: &Path) -> Result<Box<dyn BufRead>> {
+    let file = File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+    let reader = BufReader::new(file);
+    if path.extension().is_some_and(|ext| ext == "gz") {
+        Ok(Box::new(BufReader::new(GzDecoder::new(reader))))
+    } else {
+        Ok(Box::new(reader))
+    }
+}
+
+/// Observations of each station and date.
+type Days = BTreeMap<(String, NaiveDate), Vec<Observation>>;
+
+/// Groups the records of a station file into days and hands them out in
+/// batches of at most `batch_size` days once no later record can add to them.
+///
+/// `.dly` lines are grouped by station-month, so the days buffered when a new
+/// station-month starts are complete and memory stays within about a batch.
+/// By-station CSV files carry no ordering guarantee, so their days are all
+/// buffered until the end of the file and memory grows with the file.
+struct DayBatches {
+    days: Days,
+    batch_size: usize,
+    /// Whether records arrive grouped by station-month
+    by_month: bool,
+    /// Station-month of the last record
+    month: Option<(String, i32, u32)>,
+    /// Station-months with days already handed out
+    handed_out: HashSet<(String, i32, u32)>,
+}
+
+impl DayBatches {
+    fn new(format: Format, batch_size: usize) -> Self {
+        DayBatches {
+            days: BTreeMap::new(),
+            batch_size,
+            by_month: format == Format::Dly,
+            month: None,
+            handed_out: HashSet::new(),
+        }
+    }
+
+    /// Add a record, returning full batches of the days completed before it.
+    fn push(&mut self, record: Record) -> Result<Vec<Days>> {
+        let mut batches = Vec::new();
+        let (year, month) = (record.date.year(), record.date.month());
+        let same_month = self
+            .month
+            .as_ref()
+            .is_some_and(|(station_id, y, m)| *station_id == record.station_id && (*y, *m) == (year, month));
+        if self.by_month && !same_month {
+            let key = (record.station_id.clone(), year, month);
+            if self.handed_out.contains(&key) {
+                // Its days were written already and would be replaced rather than merged
+                bail!(
+                    "station {} {}-{:02} continues after its days were written; \
+                     lines must be grouped by station and month",
+                    key.0,
+                    key.1,
+                    key.2
+                );
+            }
+            self.month = Some(key);
+            batches = self.take_batches(false);
+        }
+        self.days
+            .entry((record.station_id, record.date))
+            .or_default()
+            .push(record.observation);
+        Ok(batches)
+    }
+
+    /// Hand out every day left, in batches.
+    fn finish(&mut self) -> Vec<Days> {
+        self.take_batches(true)
+    }
+
+    /// Split full batches off the buffered days, and with `all` the rest too.
+    fn take_batches(&mut self, all: bool) -> Vec<Days> {
+        let mut batches = Vec::new();
+        while self.days.len() >= self.batch_size || (all && !self.days.is_empty()) {
+            let rest = match self.days.keys().nth(self.batch_size).cloned() {
+                Some(first_of_rest) => self.days.split_off(&first_of_rest),
+                None => BTreeMap::new(),
+            };
+            let batch = std::mem::replace(&mut self.days, rest);
+            for (station_id, date) in batch.keys() {
+                self.handed_out.insert((station_id.clone(), date.year(), date.month()));
+            }
+            batches.push(batch);
+        }
+        batches
+    }
+}
+
+/// Build the `daily` rows of a batch of days, looking up each station's location
+/// and name.
+fn daily_rows(
+    days: Days,
+    args: &IngestArgs,
+    catalog: &HashMap<String, StationInfo>,
+    path: &Path,
+) -> Result<Vec<DailyRow>> {
+    let mut rows = Vec::with_capacity(days.len());
+    for ((station_id, date), day_observations) in days {
+        let info = catalog.get(&station_id);
+        let location = match (&args.location, info) {
+            (Some(location), _) => location.clone(),
+            (None, Some(info)) => info.location.clone(),
+            (None, None) => bail!("{}: station {} is not in the station list", path.display(), station_id),
+        };
+        rows.push(DailyRow {
+            location,
+            station_name: args.station_name.clone().or_else(|| info.map(|i| i.name.clone())),
+            station_id,
+            date,
+            data: observations::encode(&day_observations),
+        });
+    }
+    Ok(rows)
+}
+
+/// Load the `ID`/`NAME`/`CITY` columns of a station list.
+fn load_station_list(path: &Path) -> Result<HashMap<String, StationInfo>> {
+    let mut reader = csv::Reader::from_path(path).with_context(|| format!("failed to open {}", path.display()))?;
+    let headers = reader.headers()?.clone();
+    let column = |name: &str| {
+        headers
+            .iter()
+            .position(|h| h.trim().eq_ignore_ascii_case(name))
+            .with_context(|| format!("{}: missing {} column", path.display(), name))
+    };
+    let (id, name, city) = (column("ID")?, column("NAME")?, column("CITY")?);
+
+    let mut stations = HashMap::new();
+    for row in reader.records() {
+        let row = row.with_context(|| format!("failed to parse {}", path.display()))?;
+        stations.insert(
+            row[id].trim().to_string(),
+            StationInfo {
+                name: row[name].trim().to_string(),
+                location: row[city].trim().to_string(),
+            },
+        );
+    }
+    Ok(stations)
+}
+
+/// COPY a batch into a staging table, upsert it into `daily` and announce the
+/// locations written.
+async fn write_batch(client: &mut deadpool_postgres::Client, batch: &[DailyRow]) -> Result<()> {
+    let transaction = client.transaction().await?;
+    transaction
+        .batch_execute(
+            "CREATE TEMP TABLE ingest_staging (
+                location TEXT,
+                station_id TEXT,
+                station_name TEXT,
+                date DATE,
+                data JSONB
+            ) ON COMMIT DROP",
+        )
+        .await?;
+
+    let sink = transaction
+        .copy_in("COPY ingest_staging (location, station_id, station_name, date, data) FROM STDIN (FORMAT binary)")
+        .await?;
+    let mut writer = pin!(BinaryCopyInWriter::new(
+        sink,
+        &[Type::TEXT, Type::TEXT, Type::TEXT, Type::DATE, Type::JSONB],
+    ));
+    for row in batch {
+        writer
+            .as_mut()
+            .write(&[&row.location, &row.station_id, &row.station_name, &row.date, &row.data])
+            .await?;
+    }
+    writer.finish().await?;
+
+    // Delivered on commit; rows moving to another location change the old one too
+    transaction
+        .execute(
+            "SELECT pg_notify($1, location) FROM (
+                SELECT location FROM ingest_staging
+                UNION
+                SELECT daily.location FROM daily JOIN ingest_staging USING (station_id, date)
+            ) written",
+            &[&cache::CHANGES_CHANNEL],
+        )
+        .await?;
+
+    transaction
+        .batch_execute(
+            "INSERT INTO daily (location, station_id, station_name, date, data)
+             SELECT location, station_id, station_name, date, data FROM ingest_staging
+             ON CONFLICT (station_id, date) DO UPDATE SET
+                location = EXCLUDED.location,
+                station_name = EXCLUDED.station_name,
+                data = EXCLUDED.data",
+        )
+        .await?;
+    transaction.commit().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::DatabaseConfig;
+    use crate::{db, migrations};
+
+    #[test]
+    fn reads_blank_flags_as_none() {
+        assert_eq!(parse_flag(""), None);
+        assert_eq!(parse_flag(" "), None);
+        assert_eq!(parse_flag("T"), Some('T'));
+        assert_eq!(parse_flag(" G"), Some('G'));
+    }
+
+    fn record(station_id: &str, date: &str) -> Record {
+        Record {
+            station_id: station_id.to_string(),
+            date: date.parse().unwrap(),
+            observation: Observation {
+                element: observations::Element::Tmax,
+                value: 156.0,
+                mflag: None,
+                qflag: None,
+                sflag: None,
+            },
+        }
+    }
+
+    fn day_counts(batches: &[Days]) -> Vec<usize> {
+        batches.iter().map(BTreeMap::len).collect()
+    }
+
+    #[test]
+    fn hands_out_dly_days_once_their_month_is_done() {
+        let mut days = DayBatches::new(Format::Dly, 2);
+        for date in ["2024-01-01", "2024-01-02", "2024-01-03", "2024-01-01"] {
+            assert!(days.push(record("USW00023230", date)).unwrap().is_empty());
+        }
+        // February starts: January's three days are complete
+        let batches = days.push(record("USW00023230", "2024-02-01")).unwrap();
+        assert_eq!(day_counts(&batches), [2]);
+        assert_eq!(batches[0][&("USW00023230".to_string(), "2024-01-01".parse().unwrap())].len(), 2);
+        assert!(days.push(record("USW00023230", "2024-02-02")).unwrap().is_empty());
+        assert_eq!(day_counts(&days.finish()), [2, 1]);
+        assert!(days.finish().is_empty());
+    }
+
+    #[test]
+    fn refuses_a_dly_month_that_continues_after_being_written() {
+        let mut days = DayBatches::new(Format::Dly, 1);
+        days.push(record("USW00023230", "2024-01-01")).unwrap();
+        assert_eq!(day_counts(&days.push(record("USW00023230", "2024-02-01")).unwrap()), [1]);
+        let error = days.push(record("USW00023230", "2024-01-02")).unwrap_err();
+        assert!(error.to_string().contains("USW00023230 2024-01 continues"), "{error}");
+
+        // A month not written yet may come back
+        let mut days = DayBatches::new(Format::Dly, 10);
+        days.push(record("USW00023230", "2024-01-01")).unwrap();
+        days.push(record("USW00023230", "2024-02-01")).unwrap();
+        days.push(record("USW00023230", "2024-01-01")).unwrap();
+        assert_eq!(day_counts(&days.finish()), [2]);
+    }
+
+    #[test]
+    fn holds_csv_days_until_the_end_of_the_file() {
+        let mut days = DayBatches::new(Format::ByStation, 1);
+        for date in ["2024-01-01", "2024-02-01", "2024-01-01", "2024-03-01"] {
+            assert!(days.push(record("USW00023230", date)).unwrap().is_empty());
+        }
+        let batches = days.finish();
+        assert_eq!(day_counts(&batches), [1, 1, 1]);
+        assert_eq!(batches[0].values().next().unwrap().len(), 2);
+    }
+
+    /// Needs a Postgres at `DB_HOST` (default `localhost`) it may migrate and
+    /// write rows of station `ZZTEST00001` to.
+    #[tokio::test]
+    #[ignore = "needs Postgres"]
+    async fn writing_a_batch_again_replaces_its_rows() {
+        let config = DatabaseConfig {
+            host: Some(std::env::var("DB_HOST").unwrap_or_else(|_| "localhost".to_string())),
+            user: Some(std::env::var("DB_USER").unwrap_or_else(|_| "postgres".to_string())),
+            password: std::env::var("DB_PASSWORD").ok(),
+            ..DatabaseConfig::default()
+        };
+        let pool = db::create_pool(&config).unwrap();
+        migrations::migrate(&pool).await.unwrap();
+        let mut client = pool.get().await.unwrap();
+        let station_id = "ZZTEST00001";
+        let count = "SELECT count(*), count(*) FILTER (WHERE xmax = 0) FROM daily WHERE station_id = $1";
+        client.execute("DELETE FROM daily WHERE station_id = $1", &[&station_id]).await.unwrap();
+
+        let batch = |location: &str| -> Vec<DailyRow> {
+            ["2024-01-15", "2024-01-16"]
+                .iter()
+                .map(|date| DailyRow {
+                    location: location.to_string(),
+                    station_id: station_id.to_string(),
+                    station_name: None,
+                    date: date.parse().unwrap(),
+                    data: observations::encode(&[Observation {
+                        element: observations::Element::Tmax,
+                        value: 156.0,
+                        mflag: None,
+                        qflag: None,
+                        sflag: Some('W'),
+                    }]),
+                })
+                .collect()
+        };
+        write_batch(&mut client, &batch("Testville")).await.unwrap();
+        let row = client.query_one(count, &[&station_id]).await.unwrap();
+        assert_eq!((row.get::<_, i64>(0), row.get::<_, i64>(1)), (2, 2));
+
+        // The second run updates both rows in place and inserts none
+        write_batch(&mut client, &batch("Elsewhere")).await.unwrap();
+        let row = client.query_one(count, &[&station_id]).await.unwrap();
+        assert_eq!((row.get::<_, i64>(0), row.get::<_, i64>(1)), (2, 0));
+        let locations = client
+            .query("SELECT DISTINCT location FROM daily WHERE station_id = $1", &[&station_id])
+            .await
+            .unwrap();
+        assert_eq!(locations.iter().map(|r| r.get::<_, String>(0)).collect::<Vec<_>>(), ["Elsewhere"]);
+
+        client.execute("DELETE FROM daily WHERE station_id = $1", &[&station_id]).await.unwrap();
+    }
+}
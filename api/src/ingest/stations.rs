@@ -0,0 +1,330 @@
+//! `api ingest-stations`: load the GHCN-Daily station catalogue.
+//!
+//! `ghcnd-stations.txt` holds one fixed-width line per station:
+//!
+//! | Columns | Field         |
+//! |---------|---------------|
+//! | 1-11    | Station ID    |
+//! | 13-20   | Latitude      |
+//! | 22-30   | Longitude     |
+//! | 32-37   | Elevation (m) |
+//! | 39-40   | State         |
+//! | 42-71   | Name          |
+//! | 73-75   | GSN flag      |
+//! | 77-79   | HCN/CRN flag  |
+//! | 81-85   | WMO ID        |
+//!
+//! `ghcnd-inventory.txt` holds one line per station and element: station ID,
+//! latitude and longitude as above, then the element (32-35), first year
+//! (37-40) and last year (42-45) of record.
+
+use anyhow::{bail, Context, Result};
+use clap::Args;
+use std::collections::HashMap;
+use std::io::BufRead;
+use std::path::{Path, PathBuf};
+use std::pin::pin;
+use tokio_postgres::binary_copy::BinaryCopyInWriter;
+use tokio_postgres::types::Type;
+
+use super::open;
+use crate::db::DbPool;
+use crate::observations::Element;
+use crate::stations::{ElementPeriod, Station};
+
+/// Elevation GHCN uses when it is unknown.
+const MISSING_ELEVATION: f64 = -999.9;
+
+#[derive(Debug, Args)]
+pub struct StationsArgs {
+    /// Station list (`ghcnd-stations.txt`, optionally gzip-compressed)
+    pub stations: PathBuf,
+
+    /// Period of record per station and element (`ghcnd-inventory.txt`)
+    #[arg(long)]
+    pub inventory: Option<PathBuf>,
+}
+
+pub async fn run(args: StationsArgs, pool: DbPool) -> Result<()> {
+    let mut stations = read_stations(&args.stations)?;
+    if let Some(path) = &args.inventory {
+        let mut inventory = read_inventory(path)?;
+        for station in &mut stations {
+            station.elements = inventory.remove(&station.station_id).unwrap_or_default();
+        }
+        if !inventory.is_empty() {
+            tracing::warn!("{}: {} stations are not in the station list", path.display(), inventory.len());
+        }
+    }
+
+    let mut client = pool.get().await.context("failed to get database connection")?;
+    write_stations(&mut client, &stations, args.inventory.is_some())
+        .await
+        .context("failed to write stations")?;
+
+    tracing::info!(
+        "Loaded {} stations with {} element periods",
+        stations.len(),
+        stations.iter().map(|s| s.elements.len()).sum::<usize>()
+    );
+    Ok(())
+}
+
+/// Trimmed text of the 1-based, inclusive columns `start..=end`, tolerating
+/// lines whose trailing blanks were stripped.
+fn column(line: &str, start: usize, end: usize) -> &str {
+    line.get(start - 1..end.min(line.len())).unwrap_or("").trim()
+}
+
+fn optional(field: &str) -> Option<String> {
+    (!field.is_empty()).then(|| field.to_string())
+}
+
+fn parse_coordinate(line: &str, start: usize, end: usize, name: &str) -> Result<f64> {
+    let field = column(line, start, end);
+    field.parse().with_context(|| format!("invalid {} {:?}", name, field))
+}
+
+fn read_lines<T>(path: &Path, parse: impl Fn(&str) -> Result<T>) -> Result<Vec<T>> {
+    let mut items = Vec::new();
+    for (index, line) in open(path)?.lines().enumerate() {
+        let line = line.with_context(|| format!("failed to read {}", path.display()))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let item = parse(&line).with_context(|| format!("failed to parse {}: line {}", path.display(), index + 1))?;
+        items.push(item);
+    }
+    Ok(items)
+}
+
+/// Read `ghcnd-stations.txt`.
+fn read_stations(path: &Path) -> Result<Vec<Station>> {
+    read_lines(path, parse_station)
+}
+
+fn parse_station(line: &str) -> Result<Station> {
+    if !line.is_ascii() {
+        bail!("unexpected non-ASCII characters");
+    }
+    let station_id = column(line, 1, 11);
+    if station_id.is_empty() {
+        bail!("missing station ID");
+    }
+    let elevation = column(line, 32, 37);
+    let elevation: f64 = elevation
+        .parse()
+        .with_context(|| format!("invalid elevation {:?}", elevation))?;
+
+    Ok(Station {
+        station_id: station_id.to_string(),
+        name: column(line, 42, 71).to_string(),
+        latitude: parse_coordinate(line, 13, 20, "latitude")?,
+        longitude: parse_coordinate(line, 22, 30, "longitude")?,
+        elevation: (elevation != MISSING_ELEVATION).then_some(elevation),
+        state: optional(column(line, 39, 40)),
+        gsn_flag: optional(column(line, 73, 75)),
+        hcn_crn_flag: optional(column(line, 77, 79)),
+        wmo_id: optional(column(line, 81, 85)),
+        elements: Vec::new(),
+    })
+}
+
+/// Read `ghcnd-inventory.txt`, grouped by station ID.
+fn read_inventory(path: &Path) -> Result<HashMap<String, Vec<ElementPeriod>>> {
+    let mut inventory: HashMap<String, Vec<ElementPeriod>> = HashMap::new();
+    for (station_id, period) in read_lines(path, parse_inventory_line)? {
+        inventory.entry(station_id).or_default().push(period);
+    }
+    Ok(inventory)
+}
+
+fn parse_inventory_line(line: &str) -> Result<(String, ElementPeriod)> {
+    let year = |start: usize, name: &str| -> Result<i32> {
+        let field = column(line, start, start + 3);
+        field.parse().with_context(|| format!("invalid {} {:?}", name, field))
+    };
+    let element = column(line, 32, 35);
+    if element.is_empty() {
+        bail!("missing element");
+    }
+    Ok((
+        column(line, 1, 11).to_string(),
+        ElementPeriod {
+            element: Element::from_code(element),
+            first_year: year(37, "first year")?,
+            last_year: year(42, "last year")?,
+        },
+    ))
+}
+
+/// Upsert the stations and, when an inventory was read, replace their element periods.
+async fn write_stations(client: &mut deadpool_postgres::Client, stations: &[Station], replace_inventory: bool) -> Result<()> {
+    let transaction = client.transaction().await?;
+    transaction
+        .batch_execute(
+            "CREATE TEMP TABLE stations_staging (LIKE stations) ON COMMIT DROP;
+             CREATE TEMP TABLE inventory_staging (LIKE station_inventory) ON COMMIT DROP;",
+        )
+        .await?;
+
+    let sink = transaction
+        .copy_in(
+            "COPY stations_staging
+                (station_id, name, latitude, longitude, elevation, state, gsn_flag, hcn_crn_flag, wmo_id)
+             FROM STDIN (FORMAT binary)",
+        )
+        .await?;
+    let mut writer = pin!(BinaryCopyInWriter::new(
+        sink,
+        &[
+            Type::TEXT,
+            Type::TEXT,
+            Type::FLOAT8,
+            Type::FLOAT8,
+            Type::FLOAT8,
+            Type::TEXT,
+            Type::TEXT,
+            Type::TEXT,
+            Type::TEXT,
+        ],
+    ));
+    for s in stations {
+        writer
+            .as_mut()
+            .write(&[
+                &s.station_id,
+                &s.name,
+                &s.latitude,
+                &s.longitude,
+                &s.elevation,
+                &s.state,
+                &s.gsn_flag,
+                &s.hcn_crn_flag,
+                &s.wmo_id,
+            ])
+            .await?;
+    }
+    writer.finish().await?;
+
+    transaction
+        .batch_execute(
+            "INSERT INTO stations
+             SELECT * FROM stations_staging
+             ON CONFLICT (station_id) DO UPDATE SET
+                name = EXCLUDED.name,
+                latitude = EXCLUDED.latitude,
+                longitude = EXCLUDED.longitude,
+                elevation = EXCLUDED.elevation,
+                state = EXCLUDED.state,
+                gsn_flag = EXCLUDED.gsn_flag,
+                hcn_crn_flag = EXCLUDED.hcn_crn_flag,
+                wmo_id = EXCLUDED.wmo_id",
+        )
+        .await?;
+
+    if replace_inventory {
+        let sink = transaction
+            .copy_in("COPY inventory_staging (station_id, element, first_year, last_year) FROM STDIN (FORMAT binary)")
+            .await?;
+        let mut writer = pin!(BinaryCopyInWriter::new(sink, &[Type::TEXT, Type::TEXT, Type::INT4, Type::INT4]));
+        for s in stations {
+            for period in &s.elements {
+                writer
+                    .as_mut()
+                    .write(&[&s.station_id, &period.element.code(), &period.first_year, &period.last_year])
+                    .await?;
+            }
+        }
+        writer.finish().await?;
+
+        transaction
+            .batch_execute(
+                "DELETE FROM station_inventory i
+                 USING stations_staging s
+                 WHERE i.station_id = s.station_id;
+                 INSERT INTO station_inventory SELECT * FROM inventory_staging;",
+            )
+            .await?;
+    }
+
+    transaction.commit().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OAKLAND: &str = "USW00023230  37.7214 -122.2208    1.8 CA OAKLAND INTL AP                        72493";
+    const MELBOURNE: &str = "ASN00086071 -37.8075  144.9700   31.2    MELBOURNE REGIONAL OFFICE      GSN     94868";
+    const LOCKWOOD: &str = "USC00045123  35.7733 -120.9683 -999.9 CA LOCKWOOD                           HCN";
+
+    #[test]
+    fn parses_a_station() {
+        let station = parse_station(OAKLAND).unwrap();
+        assert_eq!(station.station_id, "USW00023230");
+        assert_eq!(station.name, "OAKLAND INTL AP");
+        assert_eq!((station.latitude, station.longitude), (37.7214, -122.2208));
+        assert_eq!(station.elevation, Some(1.8));
+        assert_eq!(station.state.as_deref(), Some("CA"));
+        assert_eq!((station.gsn_flag, station.hcn_crn_flag), (None, None));
+        assert_eq!(station.wmo_id.as_deref(), Some("72493"));
+        assert!(station.elements.is_empty());
+    }
+
+    #[test]
+    fn parses_a_southern_station_without_a_state() {
+        let station = parse_station(MELBOURNE).unwrap();
+        assert_eq!((station.latitude, station.longitude), (-37.8075, 144.97));
+        assert_eq!(station.state, None);
+        assert_eq!(station.name, "MELBOURNE REGIONAL OFFICE");
+        assert_eq!(station.gsn_flag.as_deref(), Some("GSN"));
+    }
+
+    #[test]
+    fn parses_a_station_of_unknown_elevation_with_trailing_blanks_stripped() {
+        let station = parse_station(LOCKWOOD).unwrap();
+        assert_eq!(station.elevation, None);
+        assert_eq!(station.hcn_crn_flag.as_deref(), Some("HCN"));
+        assert_eq!(station.wmo_id, None);
+    }
+
+    #[test]
+    fn rejects_malformed_stations() {
+        let error = |line: &str| format!("{:#}", parse_station(line).unwrap_err());
+        assert_eq!(
+            error(&OAKLAND.replace("37.7214", "37.72l4")),
+            "invalid latitude \"37.72l4\": invalid float literal"
+        );
+        assert_eq!(
+            error(&MELBOURNE.replace(" 144.9700", "144 58.2E")),
+            "invalid longitude \"144 58.2E\": invalid float literal"
+        );
+        assert_eq!(
+            error(&OAKLAND.replace("   1.8", "      ")),
+            "invalid elevation \"\": cannot parse float from empty string"
+        );
+        assert_eq!(error(&OAKLAND.replace("USW00023230", "           ")), "missing station ID");
+        assert_eq!(error(&OAKLAND.replace("OAKLAND", "OAKLÄND")), "unexpected non-ASCII characters");
+        assert!(error("USW00023230").starts_with("invalid elevation"));
+    }
+
+    #[test]
+    fn parses_an_inventory_line() {
+        let (station_id, period) = parse_inventory_line("USW00023230  37.7214 -122.2208 TMAX 1948 2024").unwrap();
+        assert_eq!(station_id, "USW00023230");
+        assert_eq!((period.element, period.first_year, period.last_year), (Element::Tmax, 1948, 2024));
+
+        let error = |line: &str| format!("{:#}", parse_inventory_line(line).unwrap_err());
+        assert_eq!(error("USW00023230  37.7214 -122.2208"), "missing element");
+        assert_eq!(
+            error("USW00023230  37.7214 -122.2208 TMAX 1948"),
+            "invalid last year \"\": cannot parse integer from empty string"
+        );
+        assert_eq!(
+            error("USW00023230  37.7214 -122.2208 TMAX 19x8 2024"),
+            "invalid first year \"19x8\": invalid digit found in string"
+        );
+    }
+}
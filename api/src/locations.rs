@@ -0,0 +1,133 @@
+//! Location registry: the place on Earth behind each `daily.location` name.
+
+use crate::models::{Location, LocationDetails};
+
+const COLUMNS: &str = "location, display_name, latitude, longitude, elevation, country, timezone, station_ids";
+
+fn from_row(row: &tokio_postgres::Row) -> Location {
+    Location {
+        location: row.get(0),
+        registered: true,
+        display_name: row.get(1),
+        latitude: row.get(2),
+        longitude: row.get(3),
+        elevation: row.get(4),
+        country: row.get(5),
+        timezone: row.get(6),
+        station_ids: row.get(7),
+        distance_km: None,
+    }
+}
+
+/// Every registered location, followed by the names that only appear in `daily`.
+pub async fn list(client: &tokio_postgres::Client) -> Result<Vec<Location>, tokio_postgres::Error> {
+    let registered = client
+        .query(&format!("SELECT {} FROM locations ORDER BY location", COLUMNS), &[])
+        .await?;
+    let unregistered = client
+        .query(
+            "SELECT DISTINCT location FROM daily d
+             WHERE location IS NOT NULL
+             AND NOT EXISTS (SELECT 1 FROM locations l WHERE l.location = d.location)
+             ORDER BY location",
+            &[],
+        )
+        .await?;
+
+    let mut locations: Vec<Location> = registered.iter().map(from_row).collect();
+    locations.extend(unregistered.iter().map(|row| Location {
+        location: row.get(0),
+        registered: false,
+        display_name: None,
+        latitude: None,
+        longitude: None,
+        elevation: None,
+        country: None,
+        timezone: None,
+        station_ids: Vec::new(),
+        distance_km: None,
+    }));
+    Ok(locations)
+}
+
+pub async fn get(client: &tokio_postgres::Client, location: &str) -> Result<Option<Location>, tokio_postgres::Error> {
+    let row = client
+        .query_opt(&format!("SELECT {} FROM locations WHERE location = $1", COLUMNS), &[&location])
+        .await?;
+    Ok(row.as_ref().map(from_row))
+}
+
+/// Register a location; returns `None` when it is already registered.
+pub async fn insert(
+    client: &tokio_postgres::Client,
+    location: &str,
+    details: &LocationDetails,
+) -> Result<Option<Location>, tokio_postgres::Error> {
+    let row = client
+        .query_opt(
+            &format!(
+                "INSERT INTO locations ({})
+                 VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+                 ON CONFLICT (location) DO NOTHING
+                 RETURNING {}",
+                COLUMNS, COLUMNS
+            ),
+            &[
+                &location,
+                &details.display_name,
+                &details.latitude,
+                &details.longitude,
+                &details.elevation,
+                &details.country,
+                &details.timezone,
+                &details.station_ids,
+            ],
+        )
+        .await?;
+    Ok(row.as_ref().map(from_row))
+}
+
+/// Replace the details of a registered location; returns `None` when it is not registered.
+pub async fn update(
+    client: &tokio_postgres::Client,
+    location: &str,
+    details: &LocationDetails,
+) -> Result<Option<Location>, tokio_postgres::Error> {
+    let row = client
+        .query_opt(
+            &format!(
+                "UPDATE locations SET
+                    display_name = $2,
+                    latitude = $3,
+                    longitude = $4,
+                    elevation = $5,
+                    country = $6,
+                    timezone = $7,
+                    station_ids = $8,
+                    updated_at = now()
+                 WHERE location = $1
+                 RETURNING {}",
+                COLUMNS
+            ),
+            &[
+                &location,
+                &details.display_name,
+                &details.latitude,
+                &details.longitude,
+                &details.elevation,
+                &details.country,
+                &details.timezone,
+                &details.station_ids,
+            ],
+        )
+        .await?;
+    Ok(row.as_ref().map(from_row))
+}
+
+/// Remove a location from the registry, leaving its daily rows in place.
+pub async fn delete(client: &tokio_postgres::Client, location: &str) -> Result<bool, tokio_postgres::Error> {
+    let deleted = client
+        .execute("DELETE FROM locations WHERE location = $1", &[&location])
+        .await?;
+    Ok(deleted > 0)
+}
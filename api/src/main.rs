@@ -1,54 +1,311 @@
+mod anomalies;
+mod auth;
+mod cache;
+mod config;
 mod db;
+mod error;
+mod export;
+mod extract;
 mod handlers;
+mod ingest;
+mod locations;
+mod metrics;
+mod migrations;
 mod models;
+mod normals;
+mod observations;
+mod openapi;
+mod repository;
+mod stations;
+mod stats;
+#[cfg(test)]
+mod tests;
 
 use axum::{
-    routing::get,
+    http::HeaderValue,
+    middleware,
+    routing::{delete, get, post},
     Router,
 };
+use anyhow::Context;
+use clap::{Args, Parser, Subcommand};
+use models::{
+    AnomalyRequest, LocationsRequest, NormalsRequest, PrecipitationRequest, TemperatureRequest,
+    YearlyPrecipitationRequest,
+};
 use std::net::SocketAddr;
-use tower_http::cors::CorsLayer;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Notify;
+use tower_http::cors::{Any, CorsLayer};
+use tower_http::request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+use utoipa::OpenApi;
+use utoipa_swagger_ui::SwaggerUi;
+
+#[derive(Debug, Parser)]
+#[command(version, about = "Historical climate REST API", args_conflicts_with_subcommands = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Options of `serve`, which runs when no subcommand is given
+    #[command(flatten)]
+    serve: ServeArgs,
+
+    #[command(flatten)]
+    config: config::ConfigArgs,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Run the HTTP server (the default when no subcommand is given)
+    Serve(ServeArgs),
+    /// Apply pending database schema migrations
+    Migrate(migrations::MigrateArgs),
+    /// Load GHCN-Daily station files (by-station CSV or .dly) into the daily table
+    Ingest(ingest::IngestArgs),
+    /// Load the GHCN-Daily station list and inventory into the stations tables
+    IngestStations(ingest::stations::StationsArgs),
+    /// Print the OpenAPI document of the HTTP API
+    Openapi(openapi::OpenapiArgs),
+    /// Create, list and revoke API keys
+    Keys(auth::KeysArgs),
+    /// Check the configuration
+    Config {
+        #[command(subcommand)]
+        command: config::ConfigCommand,
+    },
+}
+
+#[derive(Debug, Args)]
+struct ServeArgs {
+    /// Apply pending migrations before accepting requests
+    #[arg(long, env = "RUN_MIGRATIONS")]
+    migrate: bool,
+}
 
 #[tokio::main]
-async fn main() {
+async fn main() -> anyhow::Result<()> {
     // Load environment variables from .env file
     dotenvy::dotenv().ok();
 
-    // Initialize tracing
-    tracing_subscriber::registry()
-        .with(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| "api=debug,tower_http=debug".into()),
-        )
-        .with(tracing_subscriber::fmt::layer())
-        .init();
-
-    // Create database connection pool
-    let db_pool = db::create_pool().await;
-
-    // Build our application with routes
-    let app = Router::new()
-        .route("/get_locations", get(handlers::get_locations))
-        .route("/get_average_temp_by_date", get(handlers::get_average_temp_by_date))
-        .route("/get_total_precipitation_by_month", get(handlers::get_total_precipitation_by_month))
-        .route("/get_yearly_precipitation", get(handlers::get_yearly_precipitation))
-        .layer(CorsLayer::permissive())
-        .with_state(db_pool);
-
-    // Run the server
-    let host = std::env::var("SERVER_HOST").unwrap_or_else(|_| "0.0.0.0".to_string());
-    let port = std::env::var("SERVER_PORT")
-        .unwrap_or_else(|_| "3000".to_string())
-        .parse::<u16>()
-        .expect("Invalid SERVER_PORT");
-    
-    let addr = SocketAddr::from((
-        host.parse::<std::net::IpAddr>().expect("Invalid SERVER_HOST"),
-        port,
-    ));
+    let cli = Cli::parse();
+    let command = cli.command.unwrap_or(Command::Serve(cli.serve));
+    if let Command::Openapi(args) = command {
+        // Needs neither the database nor any other setting
+        return openapi::run(args);
+    }
+
+    let config = config::Config::load(&cli.config)?;
+    init_tracing(&config.log);
+
+    match command {
+        Command::Serve(args) => serve(args, &config).await,
+        Command::Migrate(args) => migrations::run(args, db::create_pool(&config.database)?).await,
+        Command::Ingest(args) => ingest::run(args, db::create_pool(&config.database)?).await,
+        Command::IngestStations(args) => ingest::stations::run(args, db::create_pool(&config.database)?).await,
+        Command::Openapi(_) => unreachable!("handled above"),
+        Command::Keys(args) => auth::run(args, db::create_pool(&config.database)?).await,
+        Command::Config { command } => config::run(command, &config),
+    }
+}
+
+fn init_tracing(log: &config::LogConfig) {
+    // The filter was checked when the configuration was loaded
+    let filter = tracing_subscriber::EnvFilter::new(&log.filter);
+    let registry = tracing_subscriber::registry().with(filter);
+    match log.format {
+        config::LogFormat::Text => registry.with(tracing_subscriber::fmt::layer()).init(),
+        config::LogFormat::Json => registry.with(tracing_subscriber::fmt::layer().json()).init(),
+    }
+}
+
+/// The HTTP routes, served from `repo`.
+///
+/// Each request gets an `x-request-id` (the client's, or a new UUID), echoed in
+/// the response and in error bodies. Data endpoints take a key of the `read`
+/// role and everything else an `admin` key, except the OpenAPI document at
+/// `/openapi.json`, Swagger UI at `/docs`, the probes (`/healthz`, `/readyz`)
+/// and `/metrics`. The locations and aggregates are served from `cache`, which
+/// writes through the API clear. Browsers may call it from the origins of
+/// `server.cors_origins`. Requests not answered within
+/// `server.request_timeout_secs` fail with 504.
+fn router<R: repository::ClimateRepository>(repo: R, cache: cache::ResponseCache, config: &config::Config) -> Router {
+    let auth = auth::Auth::new(repo.clone(), &config.auth);
+
+    let reads = Router::new()
+        .route(
+            "/get_locations",
+            get(handlers::get_locations::<R>)
+                .layer(middleware::from_fn_with_state(cache.clone(), cache::responses::<LocationsRequest>)),
+        )
+        .route(
+            "/get_average_temp_by_date",
+            get(handlers::get_average_temp_by_date::<R>)
+                .layer(middleware::from_fn_with_state(cache.clone(), cache::responses::<TemperatureRequest>)),
+        )
+        .route(
+            "/get_total_precipitation_by_month",
+            get(handlers::get_total_precipitation_by_month::<R>)
+                .layer(middleware::from_fn_with_state(cache.clone(), cache::responses::<PrecipitationRequest>)),
+        )
+        .route(
+            "/get_yearly_precipitation",
+            get(handlers::get_yearly_precipitation::<R>)
+                .layer(middleware::from_fn_with_state(cache.clone(), cache::responses::<YearlyPrecipitationRequest>)),
+        )
+        .route("/get_daily_observations", get(handlers::get_daily_observations::<R>))
+        .route(
+            "/get_normals",
+            get(handlers::get_normals::<R>)
+                .layer(middleware::from_fn_with_state(cache.clone(), cache::responses::<NormalsRequest>)),
+        )
+        .route(
+            "/get_anomalies",
+            get(handlers::get_anomalies::<R>)
+                .layer(middleware::from_fn_with_state(cache.clone(), cache::responses::<AnomalyRequest>)),
+        )
+        .route("/get_nearest_stations", get(handlers::get_nearest_stations::<R>))
+        .route_layer(middleware::from_fn_with_state(auth.require(auth::Role::Read), auth::authenticate::<R>));
+
+    let writes = Router::new()
+        .route("/compute_normals", post(handlers::compute_normals::<R>))
+        .route("/admin/locations", post(handlers::create_location::<R>))
+        .route(
+            "/admin/locations/:location",
+            get(handlers::get_location::<R>)
+                .put(handlers::update_location::<R>)
+                .delete(handlers::delete_location::<R>),
+        )
+        .route_layer(middleware::from_fn_with_state(cache.clone(), cache::clear_on_write));
+
+    let admin = Router::new()
+        .route("/admin/keys", get(handlers::list_api_keys::<R>).post(handlers::create_api_key::<R>))
+        .route(
+            "/admin/keys/:name",
+            delete(handlers::delete_api_key::<R>)
+                .layer(middleware::from_fn_with_state(auth.clone(), auth::forget_on_revoke::<R>)),
+        )
+        .merge(writes)
+        .route_layer(middleware::from_fn_with_state(auth.require(auth::Role::Admin), auth::authenticate::<R>));
+
+    Router::new()
+        .merge(reads)
+        .merge(admin)
+        .merge(SwaggerUi::new("/docs").url("/openapi.json", openapi::ApiDoc::openapi()))
+        .route("/healthz", get(handlers::healthz))
+        .route("/readyz", get(handlers::readyz::<R>))
+        .route("/metrics", get(metrics::metrics))
+        .fallback(error::route_not_found)
+        .method_not_allowed_fallback(error::method_not_allowed)
+        .layer(middleware::from_fn(cache::conditional))
+        .layer(middleware::from_fn_with_state(
+            Duration::from_secs(config.server.request_timeout_secs),
+            error::timeout,
+        ))
+        .layer(middleware::from_fn(error::scope_request_id))
+        .layer(middleware::from_fn(metrics::track))
+        .layer(PropagateRequestIdLayer::x_request_id())
+        .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
+        .layer(cors(&config.server.cors_origins))
+        .with_state(repo)
+}
+
+/// CORS headers for `origins`, already checked to be origins or a lone `*`;
+/// none when empty.
+fn cors(origins: &[String]) -> CorsLayer {
+    if origins.iter().any(|origin| origin == "*") {
+        return CorsLayer::permissive();
+    }
+    let origins: Vec<HeaderValue> = origins.iter().filter_map(|origin| origin.parse().ok()).collect();
+    CorsLayer::new()
+        .allow_origin(origins)
+        .allow_methods(Any)
+        .allow_headers(Any)
+        .expose_headers(Any)
+}
+
+/// Serve until SIGTERM or Ctrl-C, then stop accepting connections and give
+/// the requests in flight `server.shutdown_timeout_secs` to finish.
+async fn serve(args: ServeArgs, config: &config::Config) -> anyhow::Result<()> {
+    if args.migrate {
+        // Without the statement timeout of the requests
+        migrations::migrate(&db::create_pool(&config.database)?).await?;
+    }
+    let database = config::DatabaseConfig {
+        statement_timeout_secs: config.database.statement_timeout_secs.or(Some(config.server.request_timeout_secs)),
+        ..config.database.clone()
+    };
+    let db_pool = db::create_pool(&database)?;
+
+    let cache = cache::ResponseCache::from_config(&config.cache);
+    if cache.is_enabled() {
+        tokio::spawn(cache::listen(
+            db::connection_config(&config.database)?,
+            db::tls_connector(&config.database)?,
+            cache.clone(),
+        ));
+    }
+    if config.auth.disabled {
+        tracing::warn!("Authentication is disabled; every route is open");
+    }
+    metrics::watch_pool(db_pool.clone());
+    let repo = repository::PostgresRepository::new(db_pool).with_cancellation(db::tls_connector(&database)?);
+    let app = router(repo, cache, config);
+
+    let addr = SocketAddr::from((config.server.host, config.server.port));
+    let listener = tokio::net::TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("listening on {}", addr))?;
     tracing::info!("Server listening on {}", addr);
-    
-    let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+
+    let stopping = Arc::new(Notify::new());
+    let server = axum::serve(listener, app).with_graceful_shutdown({
+        let stopping = stopping.clone();
+        async move {
+            shutdown_signal().await;
+            stopping.notify_one();
+        }
+    });
+    let grace = Duration::from_secs(config.server.shutdown_timeout_secs);
+    tokio::select! {
+        result = server => result?,
+        _ = async {
+            stopping.notified().await;
+            tokio::time::sleep(grace).await;
+        } => tracing::warn!("Requests still in flight after {} s; stopping anyway", grace.as_secs()),
+    }
+    tracing::info!("Server stopped");
+    Ok(())
+}
+
+/// Wait for SIGTERM (as sent by Kubernetes to stop a pod) or Ctrl-C.
+async fn shutdown_signal() {
+    let interrupt = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            tracing::error!("Failed to listen for Ctrl-C: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                tracing::error!("Failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = interrupt => {}
+        _ = terminate => {}
+    }
+    tracing::info!("Shutting down; finishing the requests in flight");
 }
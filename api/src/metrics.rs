@@ -0,0 +1,211 @@
+//! Prometheus metrics, served at `/metrics`.
+//!
+//! [`track`] times every request by route (the matched path, so path
+//! parameters do not multiply the series) and records the `daily` rows its
+//! queries scanned, which [`PostgresRepository`](crate::repository::PostgresRepository)
+//! reports through [`rows_scanned`]. Query durations and pool waits are
+//! recorded by the repository too; pool gauges are read when scraped.
+
+use axum::{
+    extract::{MatchedPath, Request},
+    http::{header, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use prometheus::{
+    exponential_buckets, Encoder, HistogramOpts, HistogramVec, IntCounter, IntGauge, Opts, Registry, TextEncoder,
+};
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, LazyLock, OnceLock};
+use std::time::Instant;
+
+use crate::db::DbPool;
+use crate::error::ApiError;
+
+/// Route label of requests that matched no route.
+const UNMATCHED_ROUTE: &str = "unmatched";
+
+struct Metrics {
+    registry: Registry,
+    request_duration: HistogramVec,
+    rows_scanned: HistogramVec,
+    query_duration: HistogramVec,
+    queries_cancelled: IntCounter,
+    pool_wait: prometheus::Histogram,
+    pool_timeouts: IntCounter,
+    pool_max_size: IntGauge,
+    pool_size: IntGauge,
+    pool_available: IntGauge,
+    pool_waiting: IntGauge,
+}
+
+static METRICS: LazyLock<Metrics> = LazyLock::new(|| {
+    let registry = Registry::new();
+    let request_duration = HistogramVec::new(
+        HistogramOpts::new("http_request_duration_seconds", "Time to the response headers, by route"),
+        &["method", "route", "status"],
+    )
+    .unwrap();
+    let rows_scanned = HistogramVec::new(
+        HistogramOpts::new("daily_rows_scanned", "Rows of daily read by the queries of a request, by route")
+            .buckets(exponential_buckets(10.0, 4.0, 10).unwrap()),
+        &["route"],
+    )
+    .unwrap();
+    let query_duration = HistogramVec::new(
+        HistogramOpts::new("db_query_duration_seconds", "Duration of database queries, by repository operation"),
+        &["query"],
+    )
+    .unwrap();
+    let pool_wait = prometheus::Histogram::with_opts(HistogramOpts::new(
+        "db_pool_wait_seconds",
+        "Time spent waiting for a pooled connection",
+    ))
+    .unwrap();
+    let queries_cancelled =
+        IntCounter::new("db_queries_cancelled_total", "Queries cancelled as their request was dropped").unwrap();
+    let pool_timeouts = IntCounter::new("db_pool_timeouts_total", "Pooled connections not obtained in time").unwrap();
+    let gauge = |name: &str, help: &str| IntGauge::with_opts(Opts::new(name, help)).unwrap();
+    let metrics = Metrics {
+        pool_max_size: gauge("db_pool_max_size", "Most connections the pool may open"),
+        pool_size: gauge("db_pool_connections", "Connections currently open"),
+        pool_available: gauge("db_pool_available", "Open connections not in use"),
+        pool_waiting: gauge("db_pool_waiting", "Requests waiting for a connection"),
+        registry,
+        request_duration,
+        rows_scanned,
+        query_duration,
+        queries_cancelled,
+        pool_wait,
+        pool_timeouts,
+    };
+    let collectors: [Box<dyn prometheus::core::Collector>; 10] = [
+        Box::new(metrics.request_duration.clone()),
+        Box::new(metrics.rows_scanned.clone()),
+        Box::new(metrics.query_duration.clone()),
+        Box::new(metrics.queries_cancelled.clone()),
+        Box::new(metrics.pool_wait.clone()),
+        Box::new(metrics.pool_timeouts.clone()),
+        Box::new(metrics.pool_max_size.clone()),
+        Box::new(metrics.pool_size.clone()),
+        Box::new(metrics.pool_available.clone()),
+        Box::new(metrics.pool_waiting.clone()),
+    ];
+    for collector in collectors {
+        metrics.registry.register(collector).unwrap();
+    }
+    metrics
+});
+
+/// The pool reported by the `db_pool_*` gauges.
+static POOL: OnceLock<DbPool> = OnceLock::new();
+
+/// Report the size of `pool` in the `db_pool_*` gauges.
+pub fn watch_pool(pool: DbPool) {
+    let _ = POOL.set(pool);
+}
+
+/// `daily` rows scanned for one request, recorded under its route once the
+/// request and any stream reading for it are done.
+pub struct RequestRows {
+    route: String,
+    rows: AtomicU64,
+    scanned: AtomicBool,
+}
+
+impl RequestRows {
+    pub fn add(&self, rows: u64) {
+        self.rows.fetch_add(rows, Ordering::Relaxed);
+        self.scanned.store(true, Ordering::Relaxed);
+    }
+}
+
+impl Drop for RequestRows {
+    fn drop(&mut self) {
+        if *self.scanned.get_mut() {
+            METRICS
+                .rows_scanned
+                .with_label_values(&[&self.route])
+                .observe(*self.rows.get_mut() as f64);
+        }
+    }
+}
+
+tokio::task_local! {
+    /// Rows scanned by the request being handled, see [`track`].
+    static REQUEST_ROWS: Arc<RequestRows>;
+}
+
+/// The row count of the request being handled, to keep adding to from a
+/// spawned task (e.g. a cursor streaming an export).
+pub fn request_rows() -> Option<Arc<RequestRows>> {
+    REQUEST_ROWS.try_with(Arc::clone).ok()
+}
+
+/// Count `rows` of `daily` as scanned by the request being handled.
+pub fn rows_scanned(rows: u64) {
+    let _ = REQUEST_ROWS.try_with(|request| request.add(rows));
+}
+
+/// Run a database query (or a few) of repository operation `query`, recording its duration.
+pub async fn query<F: Future>(query: &str, future: F) -> F::Output {
+    let _timer = METRICS.query_duration.with_label_values(&[query]).start_timer();
+    future.await
+}
+
+/// Count a query cancelled because the request running it was dropped.
+pub fn query_cancelled() {
+    METRICS.queries_cancelled.inc();
+}
+
+/// Record the wait for a pooled connection, and whether it timed out.
+pub fn pool_wait(started: Instant, timed_out: bool) {
+    METRICS.pool_wait.observe(started.elapsed().as_secs_f64());
+    if timed_out {
+        METRICS.pool_timeouts.inc();
+    }
+}
+
+/// Middleware recording the latency and `daily` rows of each request by route.
+pub async fn track(request: Request, next: Next) -> Response {
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or(UNMATCHED_ROUTE, |path| path.as_str())
+        .to_string();
+    let method = request.method().to_string();
+    let rows = Arc::new(RequestRows {
+        route: route.clone(),
+        rows: AtomicU64::new(0),
+        scanned: AtomicBool::new(false),
+    });
+
+    let started = Instant::now();
+    let response = REQUEST_ROWS.scope(rows, next.run(request)).await;
+    METRICS
+        .request_duration
+        .with_label_values(&[&method, &route, response.status().as_str()])
+        .observe(started.elapsed().as_secs_f64());
+    response
+}
+
+/// `GET /metrics`: every metric in the Prometheus text format.
+pub async fn metrics() -> Response {
+    if let Some(pool) = POOL.get() {
+        let status = pool.status();
+        METRICS.pool_max_size.set(status.max_size as i64);
+        METRICS.pool_size.set(status.size as i64);
+        METRICS.pool_available.set(status.available as i64);
+        METRICS.pool_waiting.set(status.waiting as i64);
+    }
+
+    let encoder = TextEncoder::new();
+    let mut body = Vec::new();
+    if let Err(e) = encoder.encode(&METRICS.registry.gather(), &mut body) {
+        tracing::error!("Failed to encode metrics: {}", e);
+        return ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "metrics_failed", "The metrics could not be encoded")
+            .into_response();
+    }
+    ([(header::CONTENT_TYPE, encoder.format_type().to_string())], body).into_response()
+}
@@ -0,0 +1,11 @@
+-- Daily observations: one row per station and date, with the day's elements in
+-- `data` as a JSON array of `{"<ELEMENT>": value, "mflag": .., "qflag": .., "sflag": ..}`.
+-- IF NOT EXISTS adopts tables created by the former dataprep/make_daily_table.sql.
+CREATE TABLE IF NOT EXISTS daily (
+    id SERIAL PRIMARY KEY,
+    location TEXT,
+    station_id TEXT,
+    station_name TEXT,
+    date DATE,
+    data JSONB
+);
@@ -0,0 +1,28 @@
+-- Rows without a station or date cannot be keyed; leave deciding what they
+-- were to whoever loaded them.
+DO $$
+DECLARE
+    incomplete BIGINT;
+BEGIN
+    SELECT count(*) INTO incomplete FROM daily WHERE station_id IS NULL OR date IS NULL;
+    IF incomplete > 0 THEN
+        RAISE EXCEPTION '% rows of daily have no station_id or date', incomplete
+            USING HINT = 'Delete or complete them, e.g. DELETE FROM daily WHERE station_id IS NULL OR date IS NULL, and migrate again.';
+    END IF;
+END
+$$;
+
+-- Keep only the most recently loaded row of each station and date before
+-- making the pair unique.
+DELETE FROM daily d
+USING daily newer
+WHERE newer.station_id = d.station_id
+AND newer.date = d.date
+AND newer.id > d.id;
+
+ALTER TABLE daily
+    ALTER COLUMN station_id SET NOT NULL,
+    ALTER COLUMN date SET NOT NULL,
+    ADD CONSTRAINT daily_station_id_date_key UNIQUE (station_id, date);
+
+CREATE INDEX daily_location_date_idx ON daily (location, date);
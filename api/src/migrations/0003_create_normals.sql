@@ -0,0 +1,15 @@
+-- Smoothed daily climate normals per location and base period, in raw GHCN units
+-- (tenths of °C for TMAX/TMIN, tenths of mm for PRCP). Filled by POST /compute_normals.
+CREATE TABLE IF NOT EXISTS normals (
+    location TEXT NOT NULL,
+    base_start_year INTEGER NOT NULL,
+    base_end_year INTEGER NOT NULL,
+    element TEXT NOT NULL,
+    day_of_year SMALLINT NOT NULL CHECK (day_of_year BETWEEN 1 AND 366),
+    value DOUBLE PRECISION NOT NULL,
+    raw_mean DOUBLE PRECISION,
+    sample_count INTEGER NOT NULL,
+    method TEXT NOT NULL,
+    computed_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (location, base_start_year, base_end_year, element, day_of_year)
+);
@@ -0,0 +1,23 @@
+-- GHCN-Daily station catalogue, filled by `api ingest-stations` from ghcnd-stations.txt.
+CREATE TABLE IF NOT EXISTS stations (
+    station_id TEXT PRIMARY KEY,
+    name TEXT NOT NULL,
+    latitude DOUBLE PRECISION NOT NULL,
+    longitude DOUBLE PRECISION NOT NULL,
+    elevation DOUBLE PRECISION,
+    state TEXT,
+    gsn_flag TEXT,
+    hcn_crn_flag TEXT,
+    wmo_id TEXT
+);
+
+CREATE INDEX IF NOT EXISTS stations_latitude_idx ON stations (latitude);
+
+-- Period of record per station and element, from ghcnd-inventory.txt.
+CREATE TABLE IF NOT EXISTS station_inventory (
+    station_id TEXT NOT NULL REFERENCES stations (station_id) ON DELETE CASCADE,
+    element TEXT NOT NULL,
+    first_year INTEGER NOT NULL,
+    last_year INTEGER NOT NULL,
+    PRIMARY KEY (station_id, element)
+);
@@ -0,0 +1,14 @@
+-- Registry of the locations rows in `daily` are stored under, with where they are
+-- on Earth. Managed through the /admin/locations endpoints.
+CREATE TABLE IF NOT EXISTS locations (
+    location TEXT PRIMARY KEY,
+    display_name TEXT NOT NULL,
+    latitude DOUBLE PRECISION NOT NULL CHECK (latitude BETWEEN -90 AND 90),
+    longitude DOUBLE PRECISION NOT NULL CHECK (longitude BETWEEN -180 AND 180),
+    elevation DOUBLE PRECISION,
+    country TEXT,
+    timezone TEXT NOT NULL,
+    station_ids TEXT[] NOT NULL DEFAULT '{}',
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
@@ -0,0 +1,55 @@
+-- Extract the core elements of `data` into generated columns so aggregates can be
+-- computed in SQL instead of decoding every row in the api.
+--
+-- These functions mirror `observations::decode`: `data` is an array of objects
+-- (a single object is accepted too, which lax jsonpath treats as a one-item
+-- array), values may be numbers or numeric strings, and the first item holding a
+-- usable value of the element wins.
+
+CREATE FUNCTION daily_item(data JSONB, element TEXT) RETURNS JSONB
+LANGUAGE SQL IMMUTABLE PARALLEL SAFE
+AS $$
+    SELECT jsonb_path_query_first(
+        data,
+        'lax $[*] ? (@.type() == "object" && exists(@.keyvalue() ? (@.key == $element && (@.value.type() == "number" || @.value like_regex "^\\s*[-+]?([0-9]+\\.?[0-9]*|\\.[0-9]+)([eE][-+]?[0-9]+)?\\s*$"))))',
+        jsonb_build_object('element', element)
+    )
+$$;
+
+-- Stored value of `element`, unconverted.
+CREATE FUNCTION daily_value(data JSONB, element TEXT) RETURNS DOUBLE PRECISION
+LANGUAGE SQL IMMUTABLE PARALLEL SAFE
+AS $$
+    SELECT (daily_item(data, element) ->> element)::DOUBLE PRECISION
+$$;
+
+-- Quality flag of the stored value of `element`; NULL when blank.
+CREATE FUNCTION daily_qflag(data JSONB, element TEXT) RETURNS TEXT
+LANGUAGE SQL IMMUTABLE PARALLEL SAFE
+AS $$
+    SELECT NULLIF(left(btrim(daily_item(data, element) ->> 'qflag', E' \t\r\n'), 1), '')
+$$;
+
+-- Whether a value with quality flag `qflag` passes the `qc` policy
+-- ('strict', 'lenient' or 'none'); mirrors `observations::Qc::accepts`.
+CREATE FUNCTION qc_accepts(qflag TEXT, qc TEXT) RETURNS BOOLEAN
+LANGUAGE SQL IMMUTABLE PARALLEL SAFE
+AS $$
+    SELECT qflag IS NULL
+        OR qc = 'none'
+        OR (qc = 'lenient' AND qflag IN ('O', 'S', 'T', 'R', 'M', 'W'))
+$$;
+
+ALTER TABLE daily
+    ADD COLUMN tmax DOUBLE PRECISION GENERATED ALWAYS AS (daily_value(data, 'TMAX')) STORED,
+    ADD COLUMN tmax_qflag TEXT GENERATED ALWAYS AS (daily_qflag(data, 'TMAX')) STORED,
+    ADD COLUMN tmin DOUBLE PRECISION GENERATED ALWAYS AS (daily_value(data, 'TMIN')) STORED,
+    ADD COLUMN tmin_qflag TEXT GENERATED ALWAYS AS (daily_qflag(data, 'TMIN')) STORED,
+    ADD COLUMN tavg DOUBLE PRECISION GENERATED ALWAYS AS (daily_value(data, 'TAVG')) STORED,
+    ADD COLUMN tavg_qflag TEXT GENERATED ALWAYS AS (daily_qflag(data, 'TAVG')) STORED,
+    ADD COLUMN prcp DOUBLE PRECISION GENERATED ALWAYS AS (daily_value(data, 'PRCP')) STORED,
+    ADD COLUMN prcp_qflag TEXT GENERATED ALWAYS AS (daily_qflag(data, 'PRCP')) STORED;
+
+-- Calendar-day lookups across years (get_average_temp_by_date, monthly totals)
+CREATE INDEX daily_location_month_day_idx
+    ON daily (location, (EXTRACT(MONTH FROM date)), (EXTRACT(DAY FROM date)));
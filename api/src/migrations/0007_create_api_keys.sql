@@ -0,0 +1,12 @@
+-- Keys of the HTTP API. Only the SHA-256 hash of a key is stored; the key
+-- itself is shown once, when `api keys create` or POST /admin/keys makes it.
+CREATE TABLE IF NOT EXISTS api_keys (
+    name TEXT PRIMARY KEY,
+    key_hash TEXT NOT NULL UNIQUE,
+    -- First characters of the key, to tell keys apart in listings
+    prefix TEXT NOT NULL,
+    role TEXT NOT NULL CHECK (role IN ('read', 'admin')),
+    -- Requests per minute; the server default when null
+    rate_limit INTEGER CHECK (rate_limit > 0),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
@@ -0,0 +1,9 @@
+-- Normals depend on the quality-control policy applied to their base period:
+-- keep one set per policy. Normals stored before were computed with `strict`
+-- unless a request asked otherwise, which was not recorded.
+ALTER TABLE normals ADD COLUMN qc TEXT NOT NULL DEFAULT 'strict' CHECK (qc IN ('strict', 'lenient', 'none'));
+ALTER TABLE normals ALTER COLUMN qc DROP DEFAULT;
+
+ALTER TABLE normals
+    DROP CONSTRAINT normals_pkey,
+    ADD PRIMARY KEY (location, base_start_year, base_end_year, qc, element, day_of_year);
@@ -0,0 +1,197 @@
+//! Embedded, versioned schema migrations.
+//!
+//! Migrations are applied in version order, each in its own transaction together
+//! with its row in `schema_migrations`. A transaction-scoped advisory lock keeps
+//! concurrently starting instances from applying the same migration twice. The
+//! checksum of every applied migration is compared with the embedded SQL, so an
+//! edited migration is reported instead of silently diverging.
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use clap::Args;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use tokio_postgres::GenericClient;
+
+use crate::db::DbPool;
+
+/// Key of the advisory lock held while migrating.
+const LOCK_KEY: i64 = 0x006d_6967_7261_7465; // "migrate"
+
+pub struct Migration {
+    pub version: i32,
+    pub name: &'static str,
+    pub sql: &'static str,
+}
+
+pub const MIGRATIONS: &[Migration] = &[
+    Migration { version: 1, name: "create_daily", sql: include_str!("0001_create_daily.sql") },
+    Migration { version: 2, name: "daily_constraints", sql: include_str!("0002_daily_constraints.sql") },
+    Migration { version: 3, name: "create_normals", sql: include_str!("0003_create_normals.sql") },
+    Migration { version: 4, name: "create_stations", sql: include_str!("0004_create_stations.sql") },
+    Migration { version: 5, name: "create_locations", sql: include_str!("0005_create_locations.sql") },
+    Migration { version: 6, name: "daily_element_columns", sql: include_str!("0006_daily_element_columns.sql") },
+    Migration { version: 7, name: "create_api_keys", sql: include_str!("0007_create_api_keys.sql") },
+    Migration { version: 8, name: "normals_qc", sql: include_str!("0008_normals_qc.sql") },
+];
+
+#[derive(Debug, Args)]
+pub struct MigrateArgs {
+    /// List applied and pending migrations without applying anything
+    #[arg(long)]
+    pub status: bool,
+}
+
+impl Migration {
+    fn checksum(&self) -> String {
+        Sha256::digest(self.sql.as_bytes())
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+}
+
+/// A row of `schema_migrations`.
+struct Applied {
+    name: String,
+    checksum: String,
+    applied_at: DateTime<Utc>,
+}
+
+pub async fn run(args: MigrateArgs, pool: DbPool) -> Result<()> {
+    if args.status {
+        status(&pool).await
+    } else {
+        migrate(&pool).await
+    }
+}
+
+async fn ensure_history_table(client: &impl GenericClient) -> Result<()> {
+    client
+        .batch_execute(
+            "CREATE TABLE IF NOT EXISTS schema_migrations (
+                version INTEGER PRIMARY KEY,
+                name TEXT NOT NULL,
+                checksum TEXT NOT NULL,
+                applied_at TIMESTAMPTZ NOT NULL DEFAULT now()
+            )",
+        )
+        .await
+        .context("failed to create schema_migrations")
+}
+
+async fn load_applied(client: &impl GenericClient) -> Result<HashMap<i32, Applied>> {
+    let rows = client
+        .query("SELECT version, name, checksum, applied_at FROM schema_migrations", &[])
+        .await
+        .context("failed to read schema_migrations")?;
+    Ok(rows
+        .iter()
+        .map(|row| {
+            (
+                row.get(0),
+                Applied {
+                    name: row.get(1),
+                    checksum: row.get(2),
+                    applied_at: row.get(3),
+                },
+            )
+        })
+        .collect())
+}
+
+/// Fail when an applied migration differs from the embedded one.
+fn verify(applied: &HashMap<i32, Applied>) -> Result<()> {
+    for migration in MIGRATIONS {
+        if let Some(row) = applied.get(&migration.version) {
+            if row.checksum != migration.checksum() {
+                bail!(
+                    "migration {} ({}) was changed after it was applied",
+                    migration.version,
+                    migration.name
+                );
+            }
+        }
+    }
+    let latest = MIGRATIONS.last().map_or(0, |m| m.version);
+    if let Some(version) = applied.keys().copied().filter(|v| *v > latest).max() {
+        bail!(
+            "database is at migration {}, newer than this binary knows ({}); upgrade the api",
+            version,
+            latest
+        );
+    }
+    Ok(())
+}
+
+/// Apply every pending migration.
+pub async fn migrate(pool: &DbPool) -> Result<()> {
+    let mut client = pool.get().await.context("failed to get database connection")?;
+
+    let mut count = 0;
+    loop {
+        let transaction = client.transaction().await?;
+        transaction
+            .execute("SELECT pg_advisory_xact_lock($1)", &[&LOCK_KEY])
+            .await
+            .context("failed to take the migration lock")?;
+        ensure_history_table(&*transaction).await?;
+
+        // Re-read under the lock: another instance may have migrated meanwhile
+        let applied = load_applied(&*transaction).await?;
+        verify(&applied)?;
+        let Some(migration) = MIGRATIONS.iter().find(|m| !applied.contains_key(&m.version)) else {
+            transaction.commit().await?;
+            break;
+        };
+
+        tracing::info!("Applying migration {} ({})", migration.version, migration.name);
+        transaction
+            .batch_execute(migration.sql)
+            .await
+            .with_context(|| format!("migration {} ({}) failed", migration.version, migration.name))?;
+        transaction
+            .execute(
+                "INSERT INTO schema_migrations (version, name, checksum) VALUES ($1, $2, $3)",
+                &[&migration.version, &migration.name, &migration.checksum()],
+            )
+            .await?;
+        transaction.commit().await?;
+        count += 1;
+    }
+
+    match count {
+        0 => tracing::info!("Database schema is up to date"),
+        _ => tracing::info!("Applied {} migrations", count),
+    }
+    Ok(())
+}
+
+/// Print the state of every known migration.
+async fn status(pool: &DbPool) -> Result<()> {
+    let client = pool.get().await.context("failed to get database connection")?;
+    let has_history: bool = client
+        .query_one("SELECT to_regclass('schema_migrations') IS NOT NULL", &[])
+        .await?
+        .get(0);
+    let applied = match has_history {
+        true => load_applied(&**client).await?,
+        false => HashMap::new(),
+    };
+
+    for migration in MIGRATIONS {
+        let state = match applied.get(&migration.version) {
+            Some(row) if row.checksum != migration.checksum() => format!("changed since applied at {}", row.applied_at),
+            Some(row) => format!("applied at {}", row.applied_at),
+            None => "pending".to_string(),
+        };
+        println!("{:>4}  {:<20} {}", migration.version, migration.name, state);
+    }
+    let latest = MIGRATIONS.last().map_or(0, |m| m.version);
+    let mut unknown: Vec<_> = applied.iter().filter(|(v, _)| **v > latest).collect();
+    unknown.sort_by_key(|(v, _)| **v);
+    for (version, row) in unknown {
+        println!("{:>4}  {:<20} unknown to this binary, applied at {}", version, row.name, row.applied_at);
+    }
+    Ok(())
+}
@@ -1,52 +1,502 @@
+use chrono::{DateTime, NaiveDate, Utc};
 use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
 
-#[derive(Debug, Serialize, Deserialize)]
+use crate::auth::Role;
+use crate::observations::{Element, Observation, Qc, Units};
+use crate::normals::Smoothing;
+use crate::stations::ElementPeriod;
+use crate::stats::Summary;
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct Location {
+    /// Name the `daily` rows are stored under
     pub location: String,
+    /// Whether the location is in the registry; unregistered locations only
+    /// have daily rows and no coordinates
+    pub registered: bool,
+    pub display_name: Option<String>,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    /// Metres above sea level
+    pub elevation: Option<f64>,
+    /// ISO 3166-1 alpha-2 country code
+    pub country: Option<String>,
+    /// IANA time zone, e.g. `America/Los_Angeles`
+    pub timezone: Option<String>,
+    /// GHCN-Daily stations assigned to the location
+    pub station_ids: Vec<String>,
+    /// Distance from the point given to `get_locations`, in km
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub distance_km: Option<f64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct LocationsRequest {
+    /// Sort registered locations by distance from this point
+    pub latitude: Option<f64>,
+    /// Longitude of the point; required together with `latitude`
+    pub longitude: Option<f64>,
+}
+
+/// Registry fields of a location, as sent to the admin endpoints.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct LocationDetails {
+    pub display_name: String,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub elevation: Option<f64>,
+    pub country: Option<String>,
+    pub timezone: String,
+    #[serde(default)]
+    pub station_ids: Vec<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct NewLocation {
+    pub location: String,
+    #[serde(flatten)]
+    pub details: LocationDetails,
+}
+
+#[derive(Debug, Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct TemperatureRequest {
+    /// Day of the month (1-31)
     pub day: u32,
+    /// Month (1-12)
     pub month: u32,
-    pub samples: u32,
+    /// Comma-separated percentiles (0-100) to report, e.g. `10,90`
+    pub percentiles: Option<String>,
+    /// Number of years to include; only needed when the range is not fully given
+    pub samples: Option<u32>,
+    /// First year of the range (inclusive)
+    pub start_year: Option<i32>,
+    /// Last year of the range (inclusive); defaults to the year of `as_of`
+    pub end_year: Option<i32>,
+    /// Reference date used for defaults instead of today
+    pub as_of: Option<NaiveDate>,
+    /// Location name as returned by `/get_locations`
     pub location: String,
+    #[serde(default)]
+    pub units: Units,
+    #[serde(default)]
+    pub qc: Qc,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct TemperatureResponse {
     pub day: u32,
     pub month: u32,
+    pub start_year: i32,
+    pub end_year: i32,
+    pub as_of: NaiveDate,
     pub samples_requested: u32,
     pub samples_found: u32,
-    pub average_temperature: f64,
-    pub temperatures: Vec<f64>,
+    pub units: Units,
+    pub temperature_unit: String,
+    pub qc: Qc,
+    /// Number of values excluded by the `qc` policy
+    pub values_dropped: u32,
+    pub percentiles: Vec<f64>,
+    pub tmax: Option<Summary>,
+    pub tmin: Option<Summary>,
+    pub tavg: Option<Summary>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct PrecipitationRequest {
+    /// Month (1-12)
     pub month: u32,
-    pub samples: u32,
+    /// Number of years to include; only needed when the range is not fully given
+    pub samples: Option<u32>,
+    /// First year of the range (inclusive)
+    pub start_year: Option<i32>,
+    /// Last year of the range (inclusive); defaults to the year of `as_of`
+    pub end_year: Option<i32>,
+    /// Reference date used for defaults instead of today
+    pub as_of: Option<NaiveDate>,
+    /// Location name as returned by `/get_locations`
     pub location: String,
+    #[serde(default)]
+    pub units: Units,
+    #[serde(default)]
+    pub qc: Qc,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct PrecipitationResponse {
     pub month: u32,
+    pub start_year: i32,
+    pub end_year: i32,
+    pub as_of: NaiveDate,
     pub samples_requested: u32,
     pub samples_found: u32,
+    pub units: Units,
+    pub precipitation_unit: String,
+    pub qc: Qc,
+    /// Number of values excluded by the `qc` policy
+    pub values_dropped: u32,
     pub precipitation_by_year: std::collections::HashMap<i32, f64>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct YearlyPrecipitationRequest {
-    pub samples: u32,
+    /// Number of years to include; only needed when the range is not fully given
+    pub samples: Option<u32>,
+    /// First year of the range (inclusive)
+    pub start_year: Option<i32>,
+    /// Last year of the range (inclusive); defaults to the year of `as_of`
+    pub end_year: Option<i32>,
+    /// Reference date used for defaults instead of today
+    pub as_of: Option<NaiveDate>,
+    /// Location name as returned by `/get_locations`
     pub location: String,
+    #[serde(default)]
+    pub units: Units,
+    #[serde(default)]
+    pub qc: Qc,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct YearlyPrecipitationResponse {
+    pub start_year: i32,
+    pub end_year: i32,
+    pub as_of: NaiveDate,
     pub samples: u32,
     pub samples_found: u32,
+    pub units: Units,
+    pub precipitation_unit: String,
+    pub qc: Qc,
+    /// Number of values excluded by the `qc` policy
+    pub values_dropped: u32,
     pub yearly_precipitation: std::collections::HashMap<i32, f64>,
 }
+
+/// Inclusive range of years resolved from `samples`, `start_year`, `end_year` and `as_of`.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct YearRange {
+    pub start_year: i32,
+    pub end_year: i32,
+    pub as_of: NaiveDate,
+}
+
+impl YearRange {
+    /// Number of years covered by the range.
+    pub fn years(&self) -> u32 {
+        (self.end_year - self.start_year + 1) as u32
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DailyObservationsRequest {
+    /// Location name as returned by `/get_locations`
+    pub location: String,
+    /// First date to include
+    pub start_date: NaiveDate,
+    /// Last date to include
+    pub end_date: NaiveDate,
+    /// Comma-separated GHCN element codes, e.g. `TMAX,TMIN,PRCP`
+    pub elements: Option<String>,
+    /// Page size, 1-10000 (default 366)
+    pub limit: Option<u32>,
+    /// Number of rows to skip (default 0)
+    pub offset: Option<u32>,
+    #[serde(default)]
+    pub units: Units,
+    #[serde(default)]
+    pub qc: Qc,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct DailyObservation {
+    pub date: NaiveDate,
+    pub station_id: Option<String>,
+    pub values: std::collections::BTreeMap<String, f64>,
+    /// GHCN flags of the values that carry any, keyed by element code
+    #[serde(default, skip_serializing_if = "std::collections::BTreeMap::is_empty")]
+    pub flags: std::collections::BTreeMap<String, ObservationFlags>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ObservationFlags {
+    pub mflag: Option<char>,
+    pub qflag: Option<char>,
+    pub sflag: Option<char>,
+}
+
+impl From<&Observation> for ObservationFlags {
+    fn from(observation: &Observation) -> Self {
+        ObservationFlags {
+            mflag: observation.mflag,
+            qflag: observation.qflag,
+            sflag: observation.sflag,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct DailyObservationsResponse {
+    pub location: String,
+    pub start_date: NaiveDate,
+    pub end_date: NaiveDate,
+    pub elements: Option<Vec<String>>,
+    pub limit: u32,
+    pub offset: u32,
+    pub total: u64,
+    pub units: Units,
+    /// Unit label for each element code present in `observations`
+    pub element_units: std::collections::BTreeMap<String, String>,
+    pub qc: Qc,
+    /// Number of values excluded by the `qc` policy
+    pub values_dropped: u32,
+    /// Number of rows on this page left out because their `data` could not be decoded
+    pub rows_corrupt: u32,
+    pub observations: Vec<DailyObservation>,
+}
+
+#[derive(Debug, Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ComputeNormalsRequest {
+    /// Location name as returned by `/get_locations`
+    pub location: String,
+    /// First year of the base period (default 1991)
+    pub base_start_year: Option<i32>,
+    /// Last year of the base period (default 2020)
+    pub base_end_year: Option<i32>,
+    #[serde(default)]
+    pub method: Smoothing,
+    #[serde(default)]
+    pub qc: Qc,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ElementNormalsSummary {
+    pub element: String,
+    /// Distinct years of the base period with data
+    pub years: u32,
+    /// Days of year (out of 366) with at least one value
+    pub days_with_data: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ComputeNormalsResponse {
+    pub location: String,
+    pub base_start_year: i32,
+    pub base_end_year: i32,
+    pub method: Smoothing,
+    pub qc: Qc,
+    /// Number of values excluded by the `qc` policy
+    pub values_dropped: u32,
+    pub elements: Vec<ElementNormalsSummary>,
+}
+
+#[derive(Debug, Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct NormalsRequest {
+    /// Location name as returned by `/get_locations`
+    pub location: String,
+    /// First year of the base period (default 1991)
+    pub base_start_year: Option<i32>,
+    /// Last year of the base period (default 2020)
+    pub base_end_year: Option<i32>,
+    /// Return the normals of a single date
+    pub date: Option<NaiveDate>,
+    /// Return the normals of every date of a year
+    pub year: Option<i32>,
+    #[serde(default)]
+    pub units: Units,
+    /// Policy the requested normals were computed with
+    #[serde(default)]
+    pub qc: Qc,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct DailyNormal {
+    pub date: NaiveDate,
+    pub day_of_year: u32,
+    pub tmax: Option<f64>,
+    pub tmin: Option<f64>,
+    pub prcp: Option<f64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct NormalsResponse {
+    pub location: String,
+    pub base_start_year: i32,
+    pub base_end_year: i32,
+    pub method: Smoothing,
+    pub qc: Qc,
+    pub units: Units,
+    /// Unit label for each element code
+    pub element_units: std::collections::BTreeMap<String, String>,
+    pub normals: Vec<DailyNormal>,
+}
+
+#[derive(Debug, Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AnomalyRequest {
+    /// Location name as returned by `/get_locations`
+    pub location: String,
+    /// First date to compare
+    pub start_date: NaiveDate,
+    /// Last date to compare (at most 3660 days after `start_date`)
+    pub end_date: NaiveDate,
+    /// First year of the baseline period (default 1991)
+    pub base_start_year: Option<i32>,
+    /// Last year of the baseline period (default 2020)
+    pub base_end_year: Option<i32>,
+    #[serde(default)]
+    pub units: Units,
+    #[serde(default)]
+    pub qc: Qc,
+}
+
+/// Where the baseline normals of an anomaly response came from.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum BaselineSource {
+    /// Normals stored by POST /compute_normals
+    Stored,
+    /// Normals computed on the fly from the baseline period
+    Computed,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ElementAnomaly {
+    pub observed: f64,
+    pub normal: f64,
+    /// Observed minus normal
+    pub departure: f64,
+    /// Running sum of departures since `start_date`
+    pub cumulative_departure: f64,
+    /// Share (0-100) of baseline values for this day of year below the observed value,
+    /// counting ties as half
+    pub percentile_rank: Option<f64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct DailyAnomaly {
+    pub date: NaiveDate,
+    pub tmax: Option<ElementAnomaly>,
+    pub tmin: Option<ElementAnomaly>,
+    pub prcp: Option<ElementAnomaly>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct AnomalySummary {
+    /// Days with at least one observed element
+    pub days_with_data: u32,
+    /// Mean TMAX departure over the days with TMAX
+    pub tmax_departure: Option<f64>,
+    /// Mean TMIN departure over the days with TMIN
+    pub tmin_departure: Option<f64>,
+    /// Mean of the TMAX and TMIN departures
+    pub mean_temperature_departure: Option<f64>,
+    pub prcp_observed: Option<f64>,
+    /// Normal precipitation over the days with PRCP
+    pub prcp_normal: Option<f64>,
+    /// Observed precipitation as a percentage of normal
+    pub prcp_percent_of_normal: Option<f64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct AnomalyResponse {
+    pub location: String,
+    pub start_date: NaiveDate,
+    pub end_date: NaiveDate,
+    pub base_start_year: i32,
+    pub base_end_year: i32,
+    pub baseline: BaselineSource,
+    pub method: Smoothing,
+    pub units: Units,
+    pub temperature_unit: String,
+    pub precipitation_unit: String,
+    pub qc: Qc,
+    /// Number of values excluded by the `qc` policy
+    pub values_dropped: u32,
+    pub summary: AnomalySummary,
+    pub days: Vec<DailyAnomaly>,
+}
+
+#[derive(Debug, Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct NearestStationsRequest {
+    /// Latitude of the search point
+    pub latitude: f64,
+    /// Longitude of the search point
+    pub longitude: f64,
+    /// Number of stations to return (default 10)
+    pub limit: Option<u32>,
+    /// Search radius in km (default 100)
+    pub max_distance_km: Option<f64>,
+    /// Comma-separated element codes coverage is measured for (default `TMAX,TMIN,PRCP`)
+    pub elements: Option<String>,
+    /// First year of the coverage period (default 1991)
+    pub start_year: Option<i32>,
+    /// Last year of the coverage period (default: the current year)
+    pub end_year: Option<i32>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct NearestStation {
+    pub station_id: String,
+    pub name: String,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub elevation: Option<f64>,
+    pub state: Option<String>,
+    pub wmo_id: Option<String>,
+    pub distance_km: f64,
+    /// Share (0-1) of the coverage period recorded, averaged over the requested elements
+    pub coverage: f64,
+    /// Distance divided by coverage, the ranking key; absent without coverage
+    pub effective_distance_km: Option<f64>,
+    /// Period of record of every element the station reports
+    pub elements: Vec<ElementPeriod>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct NearestStationsResponse {
+    pub latitude: f64,
+    pub longitude: f64,
+    pub max_distance_km: f64,
+    pub elements: Vec<Element>,
+    pub start_year: i32,
+    pub end_year: i32,
+    pub stations: Vec<NearestStation>,
+}
+
+/// An API key as listed by the admin endpoints, without the key itself.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ApiKey {
+    pub name: String,
+    pub role: Role,
+    /// First characters of the key, to tell keys apart
+    pub prefix: String,
+    /// Requests per minute; the server default when null
+    pub rate_limit: Option<u32>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct NewApiKey {
+    /// Unique name of the key, e.g. the client using it
+    pub name: String,
+    pub role: Role,
+    /// Requests per minute; the server default when null
+    pub rate_limit: Option<u32>,
+}
+
+/// A new API key. `key` is not stored and cannot be shown again.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct CreatedApiKey {
+    /// Send as `Authorization: Bearer <key>` or `X-API-Key: <key>`
+    pub key: String,
+    #[serde(flatten)]
+    pub details: ApiKey,
+}
@@ -0,0 +1,433 @@
+//! Climate normals: smoothed per-day-of-year baselines for TMAX, TMIN and PRCP.
+//!
+//! Daily values from a base period (1991-2020 by default) are averaged per day of
+//! year on a 366-day calendar, so February 29th keeps its own (sparser) slot and
+//! March 1st is always day 61. The noisy daily means are then smoothed across the
+//! year, either with a least-squares harmonic fit or a circular moving window, and
+//! stored in the `normals` table in raw GHCN units.
+
+use chrono::{Datelike, NaiveDate};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::f64::consts::PI;
+use utoipa::ToSchema;
+
+use crate::observations::{self, Element, Observation, Qc};
+
+pub const DEFAULT_BASE_START_YEAR: i32 = 1991;
+pub const DEFAULT_BASE_END_YEAR: i32 = 2020;
+
+/// Elements a normal is computed for.
+pub const NORMAL_ELEMENTS: [Element; 3] = [Element::Tmax, Element::Tmin, Element::Prcp];
+
+/// Fewest distinct years with data before an element gets a normal.
+pub const MIN_YEARS: u32 = 10;
+
+/// Number of day-of-year slots, including February 29th.
+pub const DAYS_IN_CYCLE: usize = 366;
+
+/// Number of harmonics used by [`Smoothing::Harmonic`].
+const HARMONICS: usize = 3;
+
+/// Half-width in days of the [`Smoothing::Window`] moving window (31 days wide).
+const WINDOW_HALF_WIDTH: usize = 15;
+
+/// How daily means are smoothed across the year.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Smoothing {
+    /// Weighted least-squares fit of a mean plus three annual harmonics
+    #[default]
+    Harmonic,
+    /// Count-weighted circular moving average over a 31-day window
+    Window,
+}
+
+impl Smoothing {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Smoothing::Harmonic => "harmonic",
+            Smoothing::Window => "window",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Smoothing> {
+        match name {
+            "harmonic" => Some(Smoothing::Harmonic),
+            "window" => Some(Smoothing::Window),
+            _ => None,
+        }
+    }
+}
+
+/// Day of year (1-366) on a leap-year calendar.
+pub fn day_of_year(date: NaiveDate) -> u32 {
+    NaiveDate::from_ymd_opt(2000, date.month(), date.day())
+        .map(|d| d.ordinal())
+        .unwrap_or_else(|| date.ordinal())
+}
+
+/// Normals of one element for every day of year, in raw GHCN units.
+#[derive(Debug, Clone)]
+pub struct ElementNormals {
+    pub element: Element,
+    /// Smoothed normal, indexed by day of year - 1
+    pub values: Vec<f64>,
+    /// Unsmoothed mean of the base period, indexed by day of year - 1
+    pub raw_means: Vec<Option<f64>>,
+    /// Number of values behind each raw mean
+    pub sample_counts: Vec<u32>,
+    /// Distinct years contributing any value
+    pub years: u32,
+}
+
+/// Per-day-of-year sums of one element over a base period.
+#[derive(Debug, Clone)]
+pub struct DaySums {
+    pub element: Element,
+    /// Sum of the values of each day of year, indexed by day of year - 1
+    pub sums: Vec<f64>,
+    /// Number of values behind each sum
+    pub counts: Vec<u32>,
+    /// Distinct years contributing any value
+    pub years: u32,
+}
+
+impl DaySums {
+    pub fn new(element: Element) -> Self {
+        DaySums {
+            element,
+            sums: vec![0.0; DAYS_IN_CYCLE],
+            counts: vec![0; DAYS_IN_CYCLE],
+            years: 0,
+        }
+    }
+}
+
+/// Compute normals from daily observations of the base period.
+///
+/// Observations are expected to have passed quality control already. Elements
+/// with fewer than [`MIN_YEARS`] years of data are left out.
+pub fn compute<I>(days: I, smoothing: Smoothing) -> Vec<ElementNormals>
+where
+    I: IntoIterator<Item = (NaiveDate, Vec<Observation>)>,
+{
+    let mut sums: Vec<DaySums> = NORMAL_ELEMENTS.iter().cloned().map(DaySums::new).collect();
+    let mut years: Vec<BTreeSet<i32>> = vec![BTreeSet::new(); NORMAL_ELEMENTS.len()];
+
+    for (date, day_observations) in days {
+        let index = day_of_year(date) as usize - 1;
+        for (acc, years) in sums.iter_mut().zip(years.iter_mut()) {
+            if let Some(value) = observations::value_of(&day_observations, &acc.element) {
+                acc.sums[index] += value;
+                acc.counts[index] += 1;
+                years.insert(date.year());
+            }
+        }
+    }
+    for (acc, years) in sums.iter_mut().zip(&years) {
+        acc.years = years.len() as u32;
+    }
+
+    smooth(sums, smoothing)
+}
+
+/// Compute normals from per-day-of-year sums, leaving out elements with fewer
+/// than [`MIN_YEARS`] years of data.
+pub fn smooth(sums: Vec<DaySums>, smoothing: Smoothing) -> Vec<ElementNormals> {
+    sums.into_iter()
+        .filter_map(|acc| {
+            if acc.years < MIN_YEARS {
+                return None;
+            }
+            let raw_means: Vec<Option<f64>> = acc
+                .sums
+                .iter()
+                .zip(&acc.counts)
+                .map(|(sum, count)| (*count > 0).then(|| sum / *count as f64))
+                .collect();
+            let mut values = match smoothing {
+                Smoothing::Harmonic => harmonic_fit(&raw_means, &acc.counts, HARMONICS)?,
+                Smoothing::Window => window_smooth(&acc.sums, &acc.counts, WINDOW_HALF_WIDTH)?,
+            };
+            // A harmonic fit can dip below zero through a dry season
+            if acc.element == Element::Prcp {
+                values.iter_mut().for_each(|v| *v = v.max(0.0));
+            }
+            Some(ElementNormals {
+                element: acc.element,
+                values,
+                raw_means,
+                sample_counts: acc.counts,
+                years: acc.years,
+            })
+        })
+        .collect()
+}
+
+/// Fit `mean + Σ aₖ·cos(2πkd/366) + bₖ·sin(2πkd/366)` to the daily means by
+/// least squares, weighting each day by its sample count.
+fn harmonic_fit(means: &[Option<f64>], weights: &[u32], harmonics: usize) -> Option<Vec<f64>> {
+    let terms = 1 + 2 * harmonics;
+    let basis = |day: usize| -> Vec<f64> {
+        let angle = 2.0 * PI * (day as f64 + 1.0) / DAYS_IN_CYCLE as f64;
+        let mut row = vec![1.0];
+        for k in 1..=harmonics {
+            row.push((k as f64 * angle).cos());
+            row.push((k as f64 * angle).sin());
+        }
+        row
+    };
+
+    // Normal equations (XᵀWX)β = XᵀWy
+    let mut xtx = vec![vec![0.0; terms]; terms];
+    let mut xty = vec![0.0; terms];
+    for (day, (mean, weight)) in means.iter().zip(weights).enumerate() {
+        let Some(mean) = mean else { continue };
+        let w = *weight as f64;
+        let row = basis(day);
+        for i in 0..terms {
+            xty[i] += w * row[i] * mean;
+            for j in 0..terms {
+                xtx[i][j] += w * row[i] * row[j];
+            }
+        }
+    }
+
+    let coefficients = solve(xtx, xty)?;
+    Some(
+        (0..DAYS_IN_CYCLE)
+            .map(|day| basis(day).iter().zip(&coefficients).map(|(x, c)| x * c).sum())
+            .collect(),
+    )
+}
+
+/// Solve a small dense linear system by Gaussian elimination with partial pivoting.
+fn solve(mut a: Vec<Vec<f64>>, mut b: Vec<f64>) -> Option<Vec<f64>> {
+    let n = b.len();
+    for col in 0..n {
+        let pivot = (col..n).max_by(|&i, &j| a[i][col].abs().total_cmp(&a[j][col].abs()))?;
+        if a[pivot][col].abs() < 1e-12 {
+            return None;
+        }
+        a.swap(col, pivot);
+        b.swap(col, pivot);
+        let (upper, lower) = a.split_at_mut(col + 1);
+        let pivot_row = &upper[col];
+        for (offset, row) in lower.iter_mut().enumerate() {
+            let factor = row[col] / pivot_row[col];
+            for (x, p) in row[col..].iter_mut().zip(&pivot_row[col..]) {
+                *x -= factor * p;
+            }
+            b[col + 1 + offset] -= factor * b[col];
+        }
+    }
+
+    let mut x = vec![0.0; n];
+    for row in (0..n).rev() {
+        let tail: f64 = (row + 1..n).map(|k| a[row][k] * x[k]).sum();
+        x[row] = (b[row] - tail) / a[row][row];
+    }
+    Some(x)
+}
+
+/// Count-weighted circular moving average of the daily sums.
+fn window_smooth(sums: &[f64], counts: &[u32], half_width: usize) -> Option<Vec<f64>> {
+    let n = sums.len();
+    let mut smoothed = Vec::with_capacity(n);
+    for day in 0..n {
+        let (mut sum, mut count) = (0.0, 0u32);
+        for offset in 0..=2 * half_width {
+            let index = (day + n + offset - half_width) % n;
+            sum += sums[index];
+            count += counts[index];
+        }
+        if count == 0 {
+            return None;
+        }
+        smoothed.push(sum / count as f64);
+    }
+    Some(smoothed)
+}
+
+/// Replace the stored normals of a location, base period and QC policy.
+pub async fn store(
+    transaction: &tokio_postgres::Transaction<'_>,
+    location: &str,
+    base_start_year: i32,
+    base_end_year: i32,
+    qc: Qc,
+    smoothing: Smoothing,
+    normals: &[ElementNormals],
+) -> Result<(), tokio_postgres::Error> {
+    transaction
+        .execute(
+            "DELETE FROM normals WHERE location = $1 AND base_start_year = $2 AND base_end_year = $3 AND qc = $4",
+            &[&location, &base_start_year, &base_end_year, &qc.as_str()],
+        )
+        .await?;
+
+    // One row per element and day, sent as parallel arrays in a single statement
+    let rows = normals.len() * DAYS_IN_CYCLE;
+    let (mut elements, mut days) = (Vec::with_capacity(rows), Vec::with_capacity(rows));
+    let (mut values, mut raw_means, mut sample_counts) =
+        (Vec::with_capacity(rows), Vec::with_capacity(rows), Vec::with_capacity(rows));
+    for normal in normals {
+        for day in 0..DAYS_IN_CYCLE {
+            elements.push(normal.element.code());
+            days.push(day as i16 + 1);
+            values.push(normal.values[day]);
+            raw_means.push(normal.raw_means[day]);
+            sample_counts.push(normal.sample_counts[day] as i32);
+        }
+    }
+    transaction
+        .execute(
+            "INSERT INTO normals
+                (location, base_start_year, base_end_year, qc, element, day_of_year, value, raw_mean, sample_count, method)
+             SELECT $1, $2, $3, $4, element, day_of_year, value, raw_mean, sample_count, $5
+             FROM unnest($6::TEXT[], $7::SMALLINT[], $8::DOUBLE PRECISION[], $9::DOUBLE PRECISION[], $10::INTEGER[])
+                AS day (element, day_of_year, value, raw_mean, sample_count)",
+            &[
+                &location,
+                &base_start_year,
+                &base_end_year,
+                &qc.as_str(),
+                &smoothing.as_str(),
+                &elements,
+                &days,
+                &values,
+                &raw_means,
+                &sample_counts,
+            ],
+        )
+        .await?;
+
+    Ok(())
+}
+
+/// Smoothed normals of a location and base period, in raw GHCN units.
+#[derive(Debug, Clone, Default)]
+pub struct NormalsTable {
+    pub smoothing: Smoothing,
+    values: HashMap<Element, Vec<f64>>,
+}
+
+impl NormalsTable {
+    /// Build a table from freshly computed normals.
+    pub fn from_computed(smoothing: Smoothing, normals: &[ElementNormals]) -> NormalsTable {
+        NormalsTable {
+            smoothing,
+            values: normals.iter().map(|n| (n.element.clone(), n.values.clone())).collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Normal of `element` on the day of year of `date`.
+    pub fn value(&self, element: &Element, date: NaiveDate) -> Option<f64> {
+        self.values
+            .get(element)
+            .map(|days| days[day_of_year(date) as usize - 1])
+    }
+}
+
+/// Load the stored normals of a location, base period and QC policy.
+pub async fn load(
+    client: &tokio_postgres::Client,
+    location: &str,
+    base_start_year: i32,
+    base_end_year: i32,
+    qc: Qc,
+) -> Result<NormalsTable, tokio_postgres::Error> {
+    let rows = client
+        .query(
+            "SELECT element, day_of_year, value, method
+             FROM normals
+             WHERE location = $1 AND base_start_year = $2 AND base_end_year = $3 AND qc = $4
+             ORDER BY element, day_of_year",
+            &[&location, &base_start_year, &base_end_year, &qc.as_str()],
+        )
+        .await?;
+
+    let mut smoothing = Smoothing::default();
+    let mut values: HashMap<Element, Vec<f64>> = HashMap::new();
+    for row in rows {
+        let element = Element::from_code(row.get(0));
+        let day: i16 = row.get(1);
+        let method: String = row.get(3);
+        smoothing = Smoothing::from_name(&method).unwrap_or_default();
+        let days = values.entry(element).or_insert_with(|| vec![f64::NAN; DAYS_IN_CYCLE]);
+        if let Some(slot) = (day as usize).checked_sub(1).and_then(|i| days.get_mut(i)) {
+            *slot = row.get(2);
+        }
+    }
+
+    Ok(NormalsTable { smoothing, values })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: &[f64], expected: &[f64]) {
+        assert_eq!(actual.len(), expected.len());
+        for (day, (a, e)) in actual.iter().zip(expected).enumerate() {
+            assert!((a - e).abs() < 1e-9, "day {}: {} != {}", day, a, e);
+        }
+    }
+
+    #[test]
+    fn recovers_a_sinusoid_from_the_days_it_is_sampled_on() {
+        let curve: Vec<f64> = (0..DAYS_IN_CYCLE)
+            .map(|day| {
+                let angle = 2.0 * PI * (day as f64 + 1.0) / DAYS_IN_CYCLE as f64;
+                12.0 + 9.5 * angle.cos() - 3.0 * angle.sin() + 1.25 * (2.0 * angle).sin()
+            })
+            .collect();
+        // Every third day unsampled, with weights that must not matter for an exact fit
+        let means: Vec<Option<f64>> = curve.iter().enumerate().map(|(day, v)| (day % 3 != 0).then_some(*v)).collect();
+        let weights: Vec<u32> = (0..DAYS_IN_CYCLE)
+            .map(|day| if day % 3 == 0 { 0 } else { 1 + day as u32 % 4 })
+            .collect();
+
+        assert_close(&harmonic_fit(&means, &weights, HARMONICS).unwrap(), &curve);
+    }
+
+    #[test]
+    fn cannot_fit_without_samples() {
+        assert!(harmonic_fit(&[None; DAYS_IN_CYCLE], &[0; DAYS_IN_CYCLE], HARMONICS).is_none());
+    }
+
+    #[test]
+    fn solves_a_system_that_needs_pivoting() {
+        let a = vec![vec![0.0, 2.0, 1.0], vec![1.0, 1.0, 1.0], vec![2.0, 1.0, 0.0]];
+        assert_close(&solve(a, vec![7.0, 6.0, 4.0]).unwrap(), &[1.0, 2.0, 3.0]);
+
+        let singular = vec![vec![1.0, 2.0], vec![2.0, 4.0]];
+        assert!(solve(singular, vec![3.0, 6.0]).is_none());
+    }
+
+    #[test]
+    fn smooths_across_the_year_boundary() {
+        let sums: Vec<f64> = (0..10).map(f64::from).collect();
+        let smoothed = window_smooth(&sums, &[1; 10], 1).unwrap();
+        assert_close(&smoothed[..2], &[10.0 / 3.0, 1.0]);
+        assert_close(&smoothed[9..], &[17.0 / 3.0]);
+    }
+
+    #[test]
+    fn weights_the_window_by_sample_count() {
+        // Day 4 has no samples and day 5 two of them
+        let sums = [0.0, 1.0, 2.0, 3.0, 0.0, 10.0, 6.0, 7.0, 8.0, 9.0];
+        let counts = [1, 1, 1, 1, 0, 2, 1, 1, 1, 1];
+        let smoothed = window_smooth(&sums, &counts, 1).unwrap();
+        assert_close(&smoothed[3..6], &[5.0 / 2.0, 13.0 / 3.0, 16.0 / 3.0]);
+
+        // A window without any samples leaves no normal
+        let counts = [1, 1, 1, 0, 0, 0, 1, 1, 1, 1];
+        assert!(window_smooth(&sums, &counts, 1).is_none());
+    }
+}
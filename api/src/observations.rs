@@ -0,0 +1,456 @@
+//! Typed model for the GHCN-Daily observations stored in the `daily.data` column.
+//!
+//! Values are stored exactly as published by NOAA (e.g. tenths of °C, tenths of mm);
+//! [`Quantity`] knows the stored unit of every element and converts it on the way out.
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value;
+use std::fmt;
+use utoipa::ToSchema;
+
+/// Physical quantity measured by an element, which determines its stored unit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Quantity {
+    /// Tenths of degrees C
+    Temperature,
+    /// Tenths of mm (rain, melted snow, evaporation, ice thickness)
+    Precipitation,
+    /// Whole mm (snowfall, snow depth)
+    Depth,
+    /// Whole cm (frozen ground layers, gauge heights)
+    Length,
+    /// Tenths of meters per second
+    Speed,
+    /// Degrees from north
+    Direction,
+    /// Kilometers (wind movement)
+    Distance,
+    /// Tenths of hectopascals
+    Pressure,
+    /// Percent (cloudiness, sunshine, relative humidity)
+    Percent,
+    /// Minutes (sunshine duration)
+    Minutes,
+    /// Time of day as HHMM
+    TimeOfDay,
+    /// Number of days included in a multiday total
+    Days,
+    /// Presence indicator (weather types), always 1
+    Indicator,
+}
+
+/// Unit system used when returning values.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Units {
+    #[default]
+    Metric,
+    Imperial,
+    /// Values exactly as stored in GHCN-Daily
+    Raw,
+}
+
+impl Quantity {
+    /// Convert a stored GHCN value into the requested unit system.
+    pub fn convert(self, raw: f64, units: Units) -> f64 {
+        let metric = match self {
+            Quantity::Temperature | Quantity::Precipitation | Quantity::Speed | Quantity::Pressure => raw / 10.0,
+            _ => raw,
+        };
+        match units {
+            Units::Raw => raw,
+            Units::Metric => metric,
+            Units::Imperial => match self {
+                Quantity::Temperature => metric * 9.0 / 5.0 + 32.0,
+                Quantity::Precipitation | Quantity::Depth => metric / 25.4,
+                Quantity::Length => metric / 2.54,
+                Quantity::Speed => metric * 2.236_936,
+                Quantity::Distance => metric * 0.621_371,
+                Quantity::Pressure => metric * 0.029_53,
+                _ => metric,
+            },
+        }
+    }
+
+    /// Convert a stored difference between two values (no offset, e.g. 1 °C = 1.8 °F).
+    pub fn convert_delta(self, raw: f64, units: Units) -> f64 {
+        self.convert(raw, units) - self.convert(0.0, units)
+    }
+
+    /// Label for values of this quantity in the requested unit system.
+    pub fn unit_label(self, units: Units) -> &'static str {
+        match (self, units) {
+            (Quantity::Temperature, Units::Raw) => "tenths of °C",
+            (Quantity::Temperature, Units::Metric) => "°C",
+            (Quantity::Temperature, Units::Imperial) => "°F",
+            (Quantity::Precipitation, Units::Raw) => "tenths of mm",
+            (Quantity::Precipitation | Quantity::Depth, Units::Imperial) => "in",
+            (Quantity::Precipitation | Quantity::Depth, _) => "mm",
+            (Quantity::Length, Units::Imperial) => "in",
+            (Quantity::Length, _) => "cm",
+            (Quantity::Speed, Units::Raw) => "tenths of m/s",
+            (Quantity::Speed, Units::Metric) => "m/s",
+            (Quantity::Speed, Units::Imperial) => "mph",
+            (Quantity::Distance, Units::Imperial) => "mi",
+            (Quantity::Distance, _) => "km",
+            (Quantity::Pressure, Units::Raw) => "tenths of hPa",
+            (Quantity::Pressure, Units::Metric) => "hPa",
+            (Quantity::Pressure, Units::Imperial) => "inHg",
+            (Quantity::Direction, _) => "degrees",
+            (Quantity::Percent, _) => "%",
+            (Quantity::Minutes, _) => "minutes",
+            (Quantity::TimeOfDay, _) => "HHMM",
+            (Quantity::Days, _) => "days",
+            (Quantity::Indicator, _) => "indicator",
+        }
+    }
+}
+
+macro_rules! elements {
+    ($($variant:ident => $code:literal, $quantity:ident, $doc:literal;)*) => {
+        /// A GHCN-Daily element code (see the GHCN-Daily readme, section III).
+        ///
+        /// The parameterised soil temperature (`SN*#`, `SX*#`) and weather type
+        /// (`WT**`, `WV**`) families keep their full code; any other unknown code is
+        /// preserved in [`Element::Other`].
+        #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+        pub enum Element {
+            $(#[doc = $doc] $variant,)*
+            /// Minimum soil temperature, `SN` + ground cover + depth code
+            SoilMin(String),
+            /// Maximum soil temperature, `SX` + ground cover + depth code
+            SoilMax(String),
+            /// Weather type, `WT**`
+            WeatherType(String),
+            /// Weather in the vicinity, `WV**`
+            WeatherVicinity(String),
+            /// Any code not known to this module
+            Other(String),
+        }
+
+        impl Element {
+            /// The GHCN element code, e.g. `TMAX`.
+            pub fn code(&self) -> &str {
+                match self {
+                    $(Element::$variant => $code,)*
+                    Element::SoilMin(code)
+                    | Element::SoilMax(code)
+                    | Element::WeatherType(code)
+                    | Element::WeatherVicinity(code)
+                    | Element::Other(code) => code,
+                }
+            }
+
+            /// Parse an element code; matching is case-insensitive.
+            pub fn from_code(code: &str) -> Element {
+                let code = code.trim().to_ascii_uppercase();
+                match code.as_str() {
+                    $($code => Element::$variant,)*
+                    c if c.len() == 4 && c.starts_with("SN") => Element::SoilMin(code),
+                    c if c.len() == 4 && c.starts_with("SX") => Element::SoilMax(code),
+                    c if c.len() == 4 && c.starts_with("WT") => Element::WeatherType(code),
+                    c if c.len() == 4 && c.starts_with("WV") => Element::WeatherVicinity(code),
+                    _ => Element::Other(code),
+                }
+            }
+
+            /// The quantity measured by this element, if known.
+            pub fn quantity(&self) -> Option<Quantity> {
+                match self {
+                    $(Element::$variant => Some(Quantity::$quantity),)*
+                    Element::SoilMin(_) | Element::SoilMax(_) => Some(Quantity::Temperature),
+                    Element::WeatherType(_) | Element::WeatherVicinity(_) => Some(Quantity::Indicator),
+                    Element::Other(_) => None,
+                }
+            }
+        }
+    };
+}
+
+elements! {
+    // Core elements
+    Prcp => "PRCP", Precipitation, "Precipitation";
+    Snow => "SNOW", Depth, "Snowfall";
+    Snwd => "SNWD", Depth, "Snow depth";
+    Tmax => "TMAX", Temperature, "Maximum temperature";
+    Tmin => "TMIN", Temperature, "Minimum temperature";
+    // Additional elements
+    Acmc => "ACMC", Percent, "Average cloudiness midnight to midnight from 30-second ceilometer data";
+    Acmh => "ACMH", Percent, "Average cloudiness midnight to midnight from manual observations";
+    Acsc => "ACSC", Percent, "Average cloudiness sunrise to sunset from 30-second ceilometer data";
+    Acsh => "ACSH", Percent, "Average cloudiness sunrise to sunset from manual observations";
+    Adpt => "ADPT", Temperature, "Average dew point temperature";
+    Aslp => "ASLP", Pressure, "Average sea level pressure";
+    Astp => "ASTP", Pressure, "Average station level pressure";
+    Awbt => "AWBT", Temperature, "Average wet bulb temperature";
+    Awdr => "AWDR", Direction, "Average daily wind direction";
+    Awnd => "AWND", Speed, "Average daily wind speed";
+    Daev => "DAEV", Days, "Number of days included in the multiday evaporation total";
+    Dapr => "DAPR", Days, "Number of days included in the multiday precipitation total";
+    Dasf => "DASF", Days, "Number of days included in the multiday snowfall total";
+    Datn => "DATN", Days, "Number of days included in the multiday minimum temperature";
+    Datx => "DATX", Days, "Number of days included in the multiday maximum temperature";
+    Dawm => "DAWM", Days, "Number of days included in the multiday wind movement";
+    Dwpr => "DWPR", Days, "Number of days with non-zero precipitation included in the multiday total";
+    Evap => "EVAP", Precipitation, "Evaporation of water from evaporation pan";
+    Fmtm => "FMTM", TimeOfDay, "Time of fastest mile or fastest 1-minute wind";
+    Frgb => "FRGB", Length, "Base of frozen ground layer";
+    Frgt => "FRGT", Length, "Top of frozen ground layer";
+    Frth => "FRTH", Length, "Thickness of frozen ground layer";
+    Gaht => "GAHT", Length, "Difference between river and gauge height";
+    Mdev => "MDEV", Precipitation, "Multiday evaporation total";
+    Mdpr => "MDPR", Precipitation, "Multiday precipitation total";
+    Mdsf => "MDSF", Depth, "Multiday snowfall total";
+    Mdtn => "MDTN", Temperature, "Multiday minimum temperature";
+    Mdtx => "MDTX", Temperature, "Multiday maximum temperature";
+    Mdwm => "MDWM", Distance, "Multiday wind movement";
+    Mnpn => "MNPN", Temperature, "Daily minimum temperature of water in an evaporation pan";
+    Mxpn => "MXPN", Temperature, "Daily maximum temperature of water in an evaporation pan";
+    Pgtm => "PGTM", TimeOfDay, "Peak gust time";
+    Psun => "PSUN", Percent, "Daily percent of possible sunshine";
+    Rhav => "RHAV", Percent, "Average relative humidity";
+    Rhmn => "RHMN", Percent, "Minimum relative humidity";
+    Rhmx => "RHMX", Percent, "Maximum relative humidity";
+    Tavg => "TAVG", Temperature, "Average temperature";
+    Thic => "THIC", Precipitation, "Thickness of ice on water";
+    Tobs => "TOBS", Temperature, "Temperature at the time of observation";
+    Tsun => "TSUN", Minutes, "Daily total sunshine";
+    Wdf1 => "WDF1", Direction, "Direction of fastest 1-minute wind";
+    Wdf2 => "WDF2", Direction, "Direction of fastest 2-minute wind";
+    Wdf5 => "WDF5", Direction, "Direction of fastest 5-second wind";
+    Wdfg => "WDFG", Direction, "Direction of peak wind gust";
+    Wdfi => "WDFI", Direction, "Direction of highest instantaneous wind";
+    Wdfm => "WDFM", Direction, "Fastest mile wind direction";
+    Wdmv => "WDMV", Distance, "24-hour wind movement";
+    Wesd => "WESD", Precipitation, "Water equivalent of snow on the ground";
+    Wesf => "WESF", Precipitation, "Water equivalent of snowfall";
+    Wsf1 => "WSF1", Speed, "Fastest 1-minute wind speed";
+    Wsf2 => "WSF2", Speed, "Fastest 2-minute wind speed";
+    Wsf5 => "WSF5", Speed, "Fastest 5-second wind speed";
+    Wsfg => "WSFG", Speed, "Peak gust wind speed";
+    Wsfi => "WSFI", Speed, "Highest instantaneous wind speed";
+    Wsfm => "WSFM", Speed, "Fastest mile wind speed";
+}
+
+impl Element {
+    /// Convert a stored value of this element; unknown elements are returned unchanged.
+    pub fn convert(&self, raw: f64, units: Units) -> f64 {
+        self.quantity().map_or(raw, |q| q.convert(raw, units))
+    }
+
+    /// Convert a stored difference of this element; unknown elements are returned unchanged.
+    pub fn convert_delta(&self, raw: f64, units: Units) -> f64 {
+        self.quantity().map_or(raw, |q| q.convert_delta(raw, units))
+    }
+
+    /// Unit label for this element, or `"unknown"` for unrecognised codes.
+    pub fn unit_label(&self, units: Units) -> &'static str {
+        self.quantity().map_or("unknown", |q| q.unit_label(units))
+    }
+}
+
+impl fmt::Display for Element {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.code())
+    }
+}
+
+impl Serialize for Element {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.code())
+    }
+}
+
+impl<'de> Deserialize<'de> for Element {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let code = String::deserialize(deserializer)?;
+        Ok(Element::from_code(&code))
+    }
+}
+
+/// Elements travel as their code, e.g. `"TMAX"`.
+impl utoipa::PartialSchema for Element {
+    fn schema() -> utoipa::openapi::RefOr<utoipa::openapi::schema::Schema> {
+        utoipa::openapi::ObjectBuilder::new()
+            .schema_type(utoipa::openapi::Type::String)
+            .description(Some("GHCN-Daily element code"))
+            .examples(["TMAX"])
+            .into()
+    }
+}
+
+impl ToSchema for Element {}
+
+/// Keys holding the GHCN measurement, quality and source flags next to an element value.
+pub const MFLAG_KEY: &str = "mflag";
+pub const QFLAG_KEY: &str = "qflag";
+pub const SFLAG_KEY: &str = "sflag";
+
+/// A single element value for one day, as stored (unconverted), with its GHCN flags.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Observation {
+    pub element: Element,
+    pub value: f64,
+    /// Measurement flag (e.g. `T` for trace precipitation)
+    pub mflag: Option<char>,
+    /// Quality flag; blank means the value passed all quality checks
+    pub qflag: Option<char>,
+    /// Source flag (e.g. `7` for U.S. Cooperative Summary of the Day)
+    pub sflag: Option<char>,
+}
+
+impl Observation {
+    pub fn has_flags(&self) -> bool {
+        self.mflag.is_some() || self.qflag.is_some() || self.sflag.is_some()
+    }
+}
+
+/// Quality-control policy applied to observations before aggregation.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Qc {
+    /// Drop every value with a quality flag
+    #[default]
+    Strict,
+    /// Drop values that failed a hard check (duplicates, gaps, bounds, ...), but keep
+    /// those only flagged as outliers or inconsistent with neighbours, which are often
+    /// genuine extremes
+    Lenient,
+    /// Keep every value
+    None,
+}
+
+impl Qc {
+    /// Name of the policy as passed to the `qc_accepts` SQL function.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Qc::Strict => "strict",
+            Qc::Lenient => "lenient",
+            Qc::None => "none",
+        }
+    }
+
+    /// Whether an observation passes this policy.
+    ///
+    /// Keep in sync with the `qc_accepts` SQL function (migration 6).
+    pub fn accepts(self, observation: &Observation) -> bool {
+        match (self, observation.qflag) {
+            (Qc::None, _) | (_, None) => true,
+            (Qc::Strict, Some(_)) => false,
+            // O: climatological outlier, S: spatial, T: temporal, R: lagged range,
+            // M: megaconsistency, W: temperature too warm for snow
+            (Qc::Lenient, Some(flag)) => matches!(flag, 'O' | 'S' | 'T' | 'R' | 'M' | 'W'),
+        }
+    }
+}
+
+/// Parse a stored flag; blank strings and nulls mean "no flag".
+fn decode_flag(value: Option<&Value>) -> Option<char> {
+    value
+        .and_then(Value::as_str)
+        .and_then(|s| s.trim().chars().next())
+}
+
+/// Whether `data` has one of the shapes [`decode`] reads: an object, or an
+/// array of objects. Anything else is a corrupt row.
+pub fn is_well_formed(data: &Value) -> bool {
+    match data {
+        Value::Array(items) => items.iter().all(Value::is_object),
+        Value::Object(_) => true,
+        _ => false,
+    }
+}
+
+/// Decode the `data` JSONB column into observations.
+///
+/// Rows are normally stored as an array of objects holding one element and its
+/// flags (`[{"TMAX": 250, "qflag": "I", "sflag": "7"}, {"PRCP": 0}]`), but plain
+/// objects with several elements are accepted as well. Values may be numbers or
+/// numeric strings; anything else is skipped.
+pub fn decode(data: &Value) -> Vec<Observation> {
+    let objects: Vec<&serde_json::Map<String, Value>> = match data {
+        Value::Array(items) => items.iter().filter_map(|item| item.as_object()).collect(),
+        Value::Object(obj) => vec![obj],
+        _ => Vec::new(),
+    };
+
+    let mut observations = Vec::new();
+    for obj in objects {
+        let mflag = decode_flag(obj.get(MFLAG_KEY));
+        let qflag = decode_flag(obj.get(QFLAG_KEY));
+        let sflag = decode_flag(obj.get(SFLAG_KEY));
+
+        for (key, value) in obj {
+            if key == MFLAG_KEY || key == QFLAG_KEY || key == SFLAG_KEY {
+                continue;
+            }
+            let parsed = value
+                .as_f64()
+                .or_else(|| value.as_str().and_then(|s| s.trim().parse::<f64>().ok()));
+            if let Some(value) = parsed {
+                observations.push(Observation {
+                    element: Element::from_code(key),
+                    value,
+                    mflag,
+                    qflag,
+                    sflag,
+                });
+            }
+        }
+    }
+
+    observations
+}
+
+/// Encode observations into the `data` JSONB format read by [`decode`].
+///
+/// Whole-number values are stored as integers, as GHCN publishes them; blank
+/// flags are omitted.
+pub fn encode(observations: &[Observation]) -> Value {
+    let items = observations
+        .iter()
+        .map(|o| {
+            let mut item = serde_json::Map::new();
+            let value = if o.value.fract() == 0.0 && o.value.abs() < i64::MAX as f64 {
+                Value::from(o.value as i64)
+            } else {
+                Value::from(o.value)
+            };
+            item.insert(o.element.code().to_string(), value);
+            for (key, flag) in [(MFLAG_KEY, o.mflag), (QFLAG_KEY, o.qflag), (SFLAG_KEY, o.sflag)] {
+                if let Some(flag) = flag {
+                    item.insert(key.to_string(), Value::from(flag.to_string()));
+                }
+            }
+            Value::Object(item)
+        })
+        .collect();
+    Value::Array(items)
+}
+
+/// Keep the observations of `elements` (all elements when `None`) that pass `qc`.
+///
+/// Returns the kept observations and the number of selected values `qc` rejected.
+pub fn select(observations: Vec<Observation>, elements: Option<&[Element]>, qc: Qc) -> (Vec<Observation>, u32) {
+    let mut dropped = 0;
+    let kept = observations
+        .into_iter()
+        .filter(|o| elements.is_none_or(|list| list.contains(&o.element)))
+        .filter(|o| {
+            let accepted = qc.accepts(o);
+            if !accepted {
+                dropped += 1;
+            }
+            accepted
+        })
+        .collect();
+    (kept, dropped)
+}
+
+/// Stored value of `element` in a day's observations, if present.
+pub fn value_of(observations: &[Observation], element: &Element) -> Option<f64> {
+    observations
+        .iter()
+        .find(|o| &o.element == element)
+        .map(|o| o.value)
+}
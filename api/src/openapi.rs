@@ -0,0 +1,332 @@
+//! OpenAPI 3.1 description of the HTTP API, generated from the handlers and
+//! the types of `models.rs`.
+//!
+//! The document is served at `/openapi.json` with Swagger UI at `/docs`, and
+//! printed by the `openapi` subcommand. [`mcp_tools`] derives MCP tool
+//! definitions from the same document, so the tools of an MCP server stay in
+//! step with the endpoints they call.
+//!
+//! Operations taking the `format` parameter of
+//! [`FormatParams`](crate::export::FormatParams) are given the tabular media
+//! types and the 406 response by the [`ExportFormats`] modifier.
+//!
+//! Every operation takes an API key (the `api_key` bearer scheme); the
+//! [`Authentication`] modifier adds the responses refusing one.
+
+use clap::Args;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use utoipa::openapi::path::{Operation, ParameterIn};
+use utoipa::openapi::schema::{KnownFormat, ObjectBuilder, SchemaFormat, Type};
+use utoipa::openapi::security::{HttpAuthScheme, HttpBuilder, SecurityRequirement, SecurityScheme};
+use utoipa::openapi::{ContentBuilder, OpenApi as OpenApiDocument, Ref, RefOr, Required, Response, ResponseBuilder};
+use utoipa::{Modify, OpenApi, ToResponse, ToSchema};
+
+use crate::error::{Problem, PROBLEM_JSON};
+use crate::export::Format;
+use crate::handlers;
+use crate::models;
+use crate::normals::Smoothing;
+use crate::observations::{Element, Qc, Units};
+use crate::stations::ElementPeriod;
+use crate::stats::{Summary, YearValue};
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Historical climate API", description = "GHCN-Daily observations, climate normals and stations."),
+    paths(
+        handlers::get_locations,
+        handlers::get_average_temp_by_date,
+        handlers::get_total_precipitation_by_month,
+        handlers::get_yearly_precipitation,
+        handlers::get_daily_observations,
+        handlers::compute_normals,
+        handlers::get_normals,
+        handlers::get_anomalies,
+        handlers::get_nearest_stations,
+        handlers::create_location,
+        handlers::get_location,
+        handlers::update_location,
+        handlers::delete_location,
+        handlers::list_api_keys,
+        handlers::create_api_key,
+        handlers::delete_api_key,
+    ),
+    components(
+        schemas(Problem, Units, Qc, Smoothing, Element, ElementPeriod, Summary, YearValue, Format, models::BaselineSource),
+        responses(
+            BadRequest, Unauthorized, Forbidden, NotFound, NotAcceptable, Conflict, UnsupportedMediaType,
+            UnprocessableEntity, TooManyRequests, InternalServerError, ServiceUnavailable, GatewayTimeout,
+        ),
+    ),
+    modifiers(&DatabaseErrors, &ExportFormats, &Authentication, &NoLicense),
+    tags(
+        (name = "locations", description = "Locations with daily observations"),
+        (name = "observations", description = "Daily observations and aggregates over years"),
+        (name = "normals", description = "Climate normals and departures from them"),
+        (name = "stations", description = "The GHCN-Daily station catalogue"),
+        (name = "admin", description = "Location registry and API key administration"),
+    )
+)]
+pub struct ApiDoc;
+
+fn problem_response(description: &str) -> Response {
+    ResponseBuilder::new()
+        .description(description)
+        .content(PROBLEM_JSON, ContentBuilder::new().schema(Some(Ref::from_schema_name(Problem::name()))).build())
+        .build()
+}
+
+macro_rules! problem_responses {
+    ($($name:ident => $description:literal;)*) => {$(
+        #[doc = concat!("Problem details response: ", $description)]
+        pub struct $name;
+
+        impl<'r> ToResponse<'r> for $name {
+            fn response() -> (&'r str, RefOr<Response>) {
+                (stringify!($name), RefOr::T(problem_response($description)))
+            }
+        }
+    )*};
+}
+
+problem_responses! {
+    BadRequest => "Invalid or missing parameters";
+    Unauthorized => "No API key, or one that is not valid";
+    Forbidden => "The API key lacks the admin role";
+    NotFound => "No data, or no such resource";
+    NotAcceptable => "None of the types in the Accept header can be returned";
+    Conflict => "The resource already exists";
+    UnsupportedMediaType => "The request body is not JSON";
+    UnprocessableEntity => "The JSON body does not match the expected fields";
+    TooManyRequests => "The rate limit of the API key is exhausted; retry after `Retry-After` seconds";
+    InternalServerError => "A database query failed";
+    ServiceUnavailable => "No database connection could be obtained";
+    GatewayTimeout => "The request, or one of its database queries, took too long";
+}
+
+/// Every operation reads or writes the database and may fail with a 500, 503 or 504.
+struct DatabaseErrors;
+
+impl Modify for DatabaseErrors {
+    fn modify(&self, openapi: &mut OpenApiDocument) {
+        let responses = [("500", "InternalServerError"), ("503", "ServiceUnavailable"), ("504", "GatewayTimeout")];
+        for item in openapi.paths.paths.values_mut() {
+            for operation in operations(item) {
+                for (status, name) in responses {
+                    let reference = RefOr::Ref(Ref::from_response_name(name));
+                    operation.responses.responses.insert(status.to_string(), reference);
+                }
+            }
+        }
+    }
+}
+
+/// Operations with a `format` parameter also answer in the tabular formats,
+/// and with 406 to an `Accept` header they cannot satisfy.
+struct ExportFormats;
+
+impl Modify for ExportFormats {
+    fn modify(&self, openapi: &mut OpenApiDocument) {
+        for item in openapi.paths.paths.values_mut() {
+            for operation in operations(item) {
+                let exports = operation.parameters.iter().flatten().any(|p| p.name == FORMAT_PARAMETER);
+                if !exports {
+                    continue;
+                }
+                if let Some(RefOr::T(ok)) = operation.responses.responses.get_mut("200") {
+                    for format in [Format::Csv, Format::Ndjson, Format::Parquet] {
+                        let mut schema = ObjectBuilder::new().schema_type(Type::String);
+                        if format == Format::Parquet {
+                            schema = schema.format(Some(SchemaFormat::KnownFormat(KnownFormat::Binary)));
+                        }
+                        let content = ContentBuilder::new().schema(Some(schema)).build();
+                        ok.content.insert(format.content_type().to_string(), content);
+                    }
+                }
+                let reference = RefOr::Ref(Ref::from_response_name("NotAcceptable"));
+                operation.responses.responses.insert("406".to_string(), reference);
+            }
+        }
+    }
+}
+
+/// Every operation takes an API key and may be refused with a 401 or 429; all
+/// but the read-only ones also with a 403, as they need the admin role.
+struct Authentication;
+
+impl Modify for Authentication {
+    fn modify(&self, openapi: &mut OpenApiDocument) {
+        let scheme = HttpBuilder::new()
+            .scheme(HttpAuthScheme::Bearer)
+            .description(Some("API key, also accepted in an `X-API-Key` header"))
+            .build();
+        openapi
+            .components
+            .get_or_insert_with(Default::default)
+            .add_security_scheme(SECURITY_SCHEME, SecurityScheme::Http(scheme));
+        openapi.security = Some(vec![SecurityRequirement::new(SECURITY_SCHEME, Vec::<String>::new())]);
+
+        for item in openapi.paths.paths.values_mut() {
+            if let Some(get) = item.get.as_mut() {
+                let admin = !is_read_only(get);
+                add_refusals(get, admin);
+            }
+            for operation in [&mut item.put, &mut item.post, &mut item.delete].into_iter().flatten() {
+                add_refusals(operation, true);
+            }
+        }
+    }
+}
+
+fn add_refusals(operation: &mut Operation, admin: bool) {
+    let mut responses = vec![("401", "Unauthorized"), ("429", "TooManyRequests")];
+    if admin {
+        responses.push(("403", "Forbidden"));
+    }
+    for (status, name) in responses {
+        let reference = RefOr::Ref(Ref::from_response_name(name));
+        operation.responses.responses.insert(status.to_string(), reference);
+    }
+}
+
+/// The crate declares no license; leave `info.license` out rather than empty.
+struct NoLicense;
+
+impl Modify for NoLicense {
+    fn modify(&self, openapi: &mut OpenApiDocument) {
+        if openapi.info.license.as_ref().is_some_and(|l| l.name.is_empty()) {
+            openapi.info.license = None;
+        }
+    }
+}
+
+fn operations(item: &mut utoipa::openapi::PathItem) -> impl Iterator<Item = &mut Operation> {
+    [&mut item.get, &mut item.put, &mut item.post, &mut item.delete]
+        .into_iter()
+        .filter_map(Option::as_mut)
+}
+
+/// Name of the query parameter selecting the response format.
+const FORMAT_PARAMETER: &str = "format";
+
+const SECURITY_SCHEME: &str = "api_key";
+
+const ADMIN_TAG: &str = "admin";
+
+/// GET operations outside the `admin` tag, callable with a `read` key.
+fn is_read_only(get: &Operation) -> bool {
+    get.tags.iter().flatten().all(|tag| tag != ADMIN_TAG)
+}
+
+/// An MCP tool calling one GET endpoint with its query parameters.
+#[derive(Debug, Clone, Serialize)]
+pub struct McpTool {
+    pub name: String,
+    pub description: String,
+    #[serde(rename = "inputSchema")]
+    pub input_schema: Value,
+}
+
+/// MCP tool definitions of the read-only operations (every GET outside the
+/// `admin` tag), named after their operation IDs.
+///
+/// The input schema of a tool is an object with one property per query
+/// parameter; component references are inlined so the schema stands alone.
+/// Tools always receive JSON, so `format` is left out.
+pub fn mcp_tools(doc: &OpenApiDocument) -> Vec<McpTool> {
+    let schemas = doc
+        .components
+        .as_ref()
+        .and_then(|c| serde_json::to_value(&c.schemas).ok())
+        .unwrap_or_default();
+
+    let mut tools = Vec::new();
+    for item in doc.paths.paths.values() {
+        let Some(operation) = item.get.as_ref().filter(|get| is_read_only(get)) else { continue };
+
+        let mut properties = Map::new();
+        let mut required = Vec::new();
+        for parameter in operation.parameters.iter().flatten() {
+            if !matches!(parameter.parameter_in, ParameterIn::Query) || parameter.name == FORMAT_PARAMETER {
+                continue;
+            }
+            let mut schema = parameter
+                .schema
+                .as_ref()
+                .and_then(|s| serde_json::to_value(s).ok())
+                .unwrap_or_else(|| Value::Object(Map::new()));
+            inline_refs(&mut schema, &schemas);
+            if let (Some(description), Value::Object(object)) = (&parameter.description, &mut schema) {
+                object.insert("description".to_string(), Value::String(description.clone()));
+            }
+            if matches!(parameter.required, Required::True) {
+                required.push(Value::String(parameter.name.clone()));
+            }
+            properties.insert(parameter.name.clone(), schema);
+        }
+
+        tools.push(McpTool {
+            name: operation.operation_id.clone().unwrap_or_default(),
+            description: [&operation.summary, &operation.description]
+                .into_iter()
+                .flatten()
+                .map(|text| text.split_whitespace().collect::<Vec<_>>().join(" "))
+                .collect::<Vec<_>>()
+                .join(". "),
+            input_schema: serde_json::json!({
+                "type": "object",
+                "properties": properties,
+                "required": required,
+            }),
+        });
+    }
+    tools
+}
+
+/// Replace `{"$ref": "#/components/schemas/X"}` with the schema of `X`.
+fn inline_refs(value: &mut Value, schemas: &Value) {
+    match value {
+        Value::Object(object) => {
+            let target = object
+                .get("$ref")
+                .and_then(Value::as_str)
+                .and_then(|r| r.strip_prefix("#/components/schemas/"))
+                .and_then(|name| schemas.get(name));
+            if let Some(target) = target {
+                let mut target = target.clone();
+                inline_refs(&mut target, schemas);
+                *value = target;
+                return;
+            }
+            for child in object.values_mut() {
+                inline_refs(child, schemas);
+            }
+        }
+        Value::Array(items) => {
+            for item in items {
+                inline_refs(item, schemas);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct OpenapiArgs {
+    /// Print the MCP tool definitions derived from the document instead
+    #[arg(long)]
+    mcp_tools: bool,
+}
+
+/// Print the OpenAPI document, or the MCP tools, as JSON.
+pub fn run(args: OpenapiArgs) -> anyhow::Result<()> {
+    let doc = ApiDoc::openapi();
+    let json = if args.mcp_tools {
+        serde_json::to_string_pretty(&mcp_tools(&doc))?
+    } else {
+        doc.to_pretty_json()?
+    };
+    println!("{}", json);
+    Ok(())
+}
@@ -0,0 +1,400 @@
+//! In-memory [`ClimateRepository`] holding fixture data for tests.
+//!
+//! Daily rows keep their raw `data` JSON, so tests exercise the same decoding
+//! (arrays or objects, numeric strings, flags) as rows read from Postgres.
+
+use chrono::{Datelike, NaiveDate};
+use futures_util::StreamExt;
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use super::{
+    merge_element_days, year_span, BaseSums, ClimateRepository, ElementDays, ObservationPage, Result, StationDay,
+    StationDayStream, YearTemperatures, YearTotal,
+};
+use crate::models::{ApiKey, Location, LocationDetails, NewApiKey};
+use crate::normals::{self, DaySums, ElementNormals, NormalsTable, Smoothing, NORMAL_ELEMENTS};
+use crate::observations::{self, Element, Observation, Qc};
+use crate::stations::{self, Station};
+
+struct DailyRow {
+    location: String,
+    station_id: String,
+    date: NaiveDate,
+    data: Value,
+}
+
+impl DailyRow {
+    /// Observations of the row, keeping the first value of each element as
+    /// the generated columns of `daily` do.
+    fn first_values(&self) -> Vec<Observation> {
+        let mut seen = BTreeSet::new();
+        observations::decode(&self.data)
+            .into_iter()
+            .filter(|o| seen.insert(o.element.code().to_string()))
+            .collect()
+    }
+
+    /// The row as read by `daily_observations`, or `None` when its `data` is corrupt.
+    fn station_day(&self) -> Option<StationDay> {
+        observations::is_well_formed(&self.data).then(|| StationDay {
+            date: self.date,
+            station_id: self.station_id.clone(),
+            observations: observations::decode(&self.data),
+        })
+    }
+}
+
+#[derive(Default)]
+struct Store {
+    daily: Vec<DailyRow>,
+    locations: BTreeMap<String, Location>,
+    stations: Vec<Station>,
+    normals: HashMap<(String, i32, i32, Qc), NormalsTable>,
+    /// By name, with their hashes
+    api_keys: BTreeMap<String, (ApiKey, String)>,
+    /// How long `ping` takes
+    ping_delay: Duration,
+}
+
+impl Store {
+    fn rows<'a>(&'a self, location: &'a str, start_date: NaiveDate, end_date: NaiveDate) -> impl Iterator<Item = &'a DailyRow> {
+        self.daily
+            .iter()
+            .filter(move |row| row.location == location && (start_date..=end_date).contains(&row.date))
+    }
+
+    /// Rows between two dates holding any of `elements`, in date and station order.
+    fn matching_rows(
+        &self,
+        location: &str,
+        start_date: NaiveDate,
+        end_date: NaiveDate,
+        elements: Option<&[Element]>,
+    ) -> Vec<&DailyRow> {
+        let mut rows: Vec<&DailyRow> = self
+            .daily
+            .iter()
+            .filter(|row| row.location == location && (start_date..=end_date).contains(&row.date))
+            .filter(|row| {
+                elements.is_none_or(|list| observations::decode(&row.data).iter().any(|o| list.contains(&o.element)))
+            })
+            .collect();
+        rows.sort_by(|a, b| (a.date, &a.station_id).cmp(&(b.date, &b.station_id)));
+        rows
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct MemoryRepository {
+    store: Arc<RwLock<Store>>,
+}
+
+/// The registry entry of `location`, as `locations` would return it.
+fn registered(location: &str, details: &LocationDetails) -> Location {
+    Location {
+        location: location.to_string(),
+        registered: true,
+        display_name: Some(details.display_name.clone()),
+        latitude: Some(details.latitude),
+        longitude: Some(details.longitude),
+        elevation: details.elevation,
+        country: details.country.clone(),
+        timezone: Some(details.timezone.clone()),
+        station_ids: details.station_ids.clone(),
+        distance_km: None,
+    }
+}
+
+impl MemoryRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Make `ping` take `delay`, as a query on a busy database would.
+    pub fn delay_ping(&self, delay: Duration) {
+        self.store.write().unwrap().ping_delay = delay;
+    }
+
+    /// Add a `daily` row with its raw `data` JSON.
+    pub fn insert_day(&self, location: &str, station_id: &str, date: NaiveDate, data: Value) {
+        let mut store = self.store.write().unwrap();
+        store.daily.retain(|row| !(row.station_id == station_id && row.date == date));
+        store.daily.push(DailyRow {
+            location: location.to_string(),
+            station_id: station_id.to_string(),
+            date,
+            data,
+        });
+    }
+
+    /// Add a station to the catalogue.
+    pub fn insert_station(&self, station: Station) {
+        self.store.write().unwrap().stations.push(station);
+    }
+}
+
+impl ClimateRepository for MemoryRepository {
+    async fn list_locations(&self) -> Result<Vec<Location>> {
+        let store = self.store.read().unwrap();
+        let mut locations: Vec<Location> = store.locations.values().cloned().collect();
+        let unregistered: BTreeSet<&str> = store
+            .daily
+            .iter()
+            .map(|row| row.location.as_str())
+            .filter(|location| !store.locations.contains_key(*location))
+            .collect();
+        locations.extend(unregistered.into_iter().map(|location| Location {
+            location: location.to_string(),
+            registered: false,
+            display_name: None,
+            latitude: None,
+            longitude: None,
+            elevation: None,
+            country: None,
+            timezone: None,
+            station_ids: Vec::new(),
+            distance_km: None,
+        }));
+        Ok(locations)
+    }
+
+    async fn get_location(&self, location: &str) -> Result<Option<Location>> {
+        Ok(self.store.read().unwrap().locations.get(location).cloned())
+    }
+
+    async fn insert_location(&self, location: &str, details: &LocationDetails) -> Result<Option<Location>> {
+        let mut store = self.store.write().unwrap();
+        if store.locations.contains_key(location) {
+            return Ok(None);
+        }
+        let registered = registered(location, details);
+        store.locations.insert(location.to_string(), registered.clone());
+        Ok(Some(registered))
+    }
+
+    async fn update_location(&self, location: &str, details: &LocationDetails) -> Result<Option<Location>> {
+        let mut store = self.store.write().unwrap();
+        Ok(store.locations.get_mut(location).map(|existing| {
+            *existing = registered(location, details);
+            existing.clone()
+        }))
+    }
+
+    async fn delete_location(&self, location: &str) -> Result<bool> {
+        Ok(self.store.write().unwrap().locations.remove(location).is_some())
+    }
+
+    async fn stations_within(&self, latitude: f64, longitude: f64, radius_km: f64) -> Result<Vec<Station>> {
+        let ((min_lat, max_lat), longitudes) = stations::bounding_box(latitude, longitude, radius_km);
+        let store = self.store.read().unwrap();
+        Ok(store
+            .stations
+            .iter()
+            .filter(|s| (min_lat..=max_lat).contains(&s.latitude))
+            .filter(|s| match longitudes {
+                None => true,
+                Some((min_lon, max_lon)) if min_lon <= max_lon => (min_lon..=max_lon).contains(&s.longitude),
+                Some((min_lon, max_lon)) => s.longitude >= min_lon || s.longitude <= max_lon,
+            })
+            .cloned()
+            .collect())
+    }
+
+    async fn day_temperatures(
+        &self,
+        location: &str,
+        month: u32,
+        day: u32,
+        start_year: i32,
+        end_year: i32,
+        qc: Qc,
+    ) -> Result<Vec<YearTemperatures>> {
+        let (first, last) = year_span(start_year, end_year);
+        let store = self.store.read().unwrap();
+        let mut years: Vec<YearTemperatures> = store
+            .rows(location, first, last)
+            .filter(|row| row.date.month() == month && row.date.day() == day)
+            .map(|row| {
+                let (accepted, values_dropped) =
+                    observations::select(row.first_values(), Some(&[Element::Tmax, Element::Tmin, Element::Tavg]), qc);
+                YearTemperatures {
+                    year: row.date.year(),
+                    tmax: observations::value_of(&accepted, &Element::Tmax),
+                    tmin: observations::value_of(&accepted, &Element::Tmin),
+                    tavg: observations::value_of(&accepted, &Element::Tavg),
+                    values_dropped,
+                }
+            })
+            .collect();
+        years.sort_by_key(|y| y.year);
+        Ok(years)
+    }
+
+    async fn precipitation_totals(
+        &self,
+        location: &str,
+        month: Option<u32>,
+        start_year: i32,
+        end_year: i32,
+        qc: Qc,
+    ) -> Result<Vec<YearTotal>> {
+        let (first, last) = year_span(start_year, end_year);
+        let store = self.store.read().unwrap();
+        let mut totals: BTreeMap<i32, YearTotal> = BTreeMap::new();
+        for row in store
+            .rows(location, first, last)
+            .filter(|row| month.is_none_or(|m| row.date.month() == m))
+        {
+            let year = row.date.year();
+            let total = totals.entry(year).or_insert(YearTotal { year, total: 0.0, values_dropped: 0 });
+            let (accepted, dropped) = observations::select(row.first_values(), Some(&[Element::Prcp]), qc);
+            total.total += observations::value_of(&accepted, &Element::Prcp).unwrap_or(0.0);
+            total.values_dropped += dropped;
+        }
+        Ok(totals.into_values().collect())
+    }
+
+    async fn daily_observations(
+        &self,
+        location: &str,
+        start_date: NaiveDate,
+        end_date: NaiveDate,
+        elements: Option<&[Element]>,
+        limit: u32,
+        offset: u32,
+    ) -> Result<ObservationPage> {
+        let store = self.store.read().unwrap();
+        let rows = store.matching_rows(location, start_date, end_date, elements);
+
+        let total = rows.len() as u64;
+        let mut days = Vec::new();
+        let mut rows_corrupt = 0;
+        for row in rows.into_iter().skip(offset as usize).take(limit as usize) {
+            match row.station_day() {
+                Some(day) => days.push(day),
+                None => rows_corrupt += 1,
+            }
+        }
+
+        Ok(ObservationPage { total, days, rows_corrupt })
+    }
+
+    async fn stream_daily_observations(
+        &self,
+        location: &str,
+        start_date: NaiveDate,
+        end_date: NaiveDate,
+        elements: Option<&[Element]>,
+        limit: Option<u32>,
+        offset: u32,
+    ) -> Result<StationDayStream> {
+        let store = self.store.read().unwrap();
+        let days: Vec<Result<StationDay>> = store
+            .matching_rows(location, start_date, end_date, elements)
+            .into_iter()
+            .skip(offset as usize)
+            .take(limit.map_or(usize::MAX, |l| l as usize))
+            .filter_map(DailyRow::station_day)
+            .map(Ok)
+            .collect();
+        Ok(futures_util::stream::iter(days).boxed())
+    }
+
+    async fn element_days(&self, location: &str, start_date: NaiveDate, end_date: NaiveDate, qc: Qc) -> Result<ElementDays> {
+        let store = self.store.read().unwrap();
+        let rows = store
+            .rows(location, start_date, end_date)
+            .map(|row| (row.date, row.first_values()));
+        Ok(merge_element_days(rows, qc))
+    }
+
+    async fn base_sums(&self, location: &str, base_start_year: i32, base_end_year: i32, qc: Qc) -> Result<BaseSums> {
+        let (first, last) = year_span(base_start_year, base_end_year);
+        let store = self.store.read().unwrap();
+        let mut sums: Vec<DaySums> = NORMAL_ELEMENTS.iter().cloned().map(DaySums::new).collect();
+        let mut years: Vec<BTreeSet<i32>> = vec![BTreeSet::new(); NORMAL_ELEMENTS.len()];
+        let mut values_dropped = 0;
+
+        // Every station's value counts, as in the SQL aggregates
+        for row in store.rows(location, first, last) {
+            let (accepted, dropped) = observations::select(row.first_values(), Some(&NORMAL_ELEMENTS), qc);
+            values_dropped += dropped;
+            let index = normals::day_of_year(row.date) as usize - 1;
+            for (acc, years) in sums.iter_mut().zip(years.iter_mut()) {
+                if let Some(value) = observations::value_of(&accepted, &acc.element) {
+                    acc.sums[index] += value;
+                    acc.counts[index] += 1;
+                    years.insert(row.date.year());
+                }
+            }
+        }
+        for (acc, years) in sums.iter_mut().zip(&years) {
+            acc.years = years.len() as u32;
+        }
+
+        Ok(BaseSums { sums, values_dropped })
+    }
+
+    async fn load_normals(&self, location: &str, base_start_year: i32, base_end_year: i32, qc: Qc) -> Result<NormalsTable> {
+        let store = self.store.read().unwrap();
+        Ok(store
+            .normals
+            .get(&(location.to_string(), base_start_year, base_end_year, qc))
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    async fn store_normals(
+        &self,
+        location: &str,
+        base_start_year: i32,
+        base_end_year: i32,
+        qc: Qc,
+        smoothing: Smoothing,
+        computed: &[ElementNormals],
+    ) -> Result<()> {
+        self.store.write().unwrap().normals.insert(
+            (location.to_string(), base_start_year, base_end_year, qc),
+            NormalsTable::from_computed(smoothing, computed),
+        );
+        Ok(())
+    }
+
+    async fn list_api_keys(&self) -> Result<Vec<ApiKey>> {
+        Ok(self.store.read().unwrap().api_keys.values().map(|(key, _)| key.clone()).collect())
+    }
+
+    async fn find_api_key(&self, hash: &str) -> Result<Option<ApiKey>> {
+        let store = self.store.read().unwrap();
+        Ok(store.api_keys.values().find(|(_, stored)| stored == hash).map(|(key, _)| key.clone()))
+    }
+
+    async fn insert_api_key(&self, key: &NewApiKey, prefix: &str, hash: &str) -> Result<Option<ApiKey>> {
+        let mut store = self.store.write().unwrap();
+        if store.api_keys.contains_key(&key.name) {
+            return Ok(None);
+        }
+        let stored = ApiKey {
+            name: key.name.clone(),
+            role: key.role,
+            prefix: prefix.to_string(),
+            rate_limit: key.rate_limit,
+            created_at: chrono::Utc::now(),
+        };
+        store.api_keys.insert(key.name.clone(), (stored.clone(), hash.to_string()));
+        Ok(Some(stored))
+    }
+
+    async fn delete_api_key(&self, name: &str) -> Result<bool> {
+        Ok(self.store.write().unwrap().api_keys.remove(name).is_some())
+    }
+
+    async fn ping(&self) -> Result<()> {
+        let delay = self.store.read().unwrap().ping_delay;
+        tokio::time::sleep(delay).await;
+        Ok(())
+    }
+}
@@ -0,0 +1,295 @@
+//! Storage behind the HTTP handlers.
+//!
+//! [`ClimateRepository`] covers everything the handlers read and write:
+//! the location registry, the station catalogue, daily observations, the
+//! aggregates computed from them and the API keys. [`PostgresRepository`]
+//! serves the API; [`MemoryRepository`] holds fixture data for tests.
+
+#[cfg(test)]
+mod memory;
+mod postgres;
+
+use chrono::NaiveDate;
+use futures_util::stream::BoxStream;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::future::Future;
+
+use crate::models::{ApiKey, Location, LocationDetails, NewApiKey};
+use crate::normals::{DaySums, ElementNormals, NormalsTable, Smoothing, NORMAL_ELEMENTS};
+use crate::observations::{self, Element, Observation, Qc};
+use crate::stations::Station;
+
+#[cfg(test)]
+pub use memory::MemoryRepository;
+pub use postgres::PostgresRepository;
+
+#[derive(Debug)]
+pub enum RepositoryError {
+    /// No database connection could be obtained
+    Connection(String),
+    /// A query failed
+    Query(String),
+    /// A query ran past the statement timeout
+    Timeout(String),
+}
+
+impl fmt::Display for RepositoryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RepositoryError::Connection(e) => write!(f, "connection error: {}", e),
+            RepositoryError::Query(e) => write!(f, "query error: {}", e),
+            RepositoryError::Timeout(e) => write!(f, "query timeout: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for RepositoryError {}
+
+pub type Result<T> = std::result::Result<T, RepositoryError>;
+
+/// TMAX, TMIN and TAVG of one station-year on a calendar day, with the values
+/// rejected by quality control left out and counted.
+#[derive(Debug, Clone, PartialEq)]
+pub struct YearTemperatures {
+    pub year: i32,
+    pub tmax: Option<f64>,
+    pub tmin: Option<f64>,
+    pub tavg: Option<f64>,
+    pub values_dropped: u32,
+}
+
+/// Precipitation total of one year (or of one month of it), in raw GHCN units.
+#[derive(Debug, Clone, PartialEq)]
+pub struct YearTotal {
+    pub year: i32,
+    pub total: f64,
+    pub values_dropped: u32,
+}
+
+/// One stored row of daily observations.
+#[derive(Debug, Clone)]
+pub struct StationDay {
+    pub date: NaiveDate,
+    pub station_id: String,
+    pub observations: Vec<Observation>,
+}
+
+/// A page of [`StationDay`]s and the number of rows matching the query.
+#[derive(Debug, Clone)]
+pub struct ObservationPage {
+    pub total: u64,
+    pub days: Vec<StationDay>,
+    /// Rows of the page left out of `days` because their `data` could not be decoded
+    pub rows_corrupt: u32,
+}
+
+/// [`StationDay`]s read one by one, ending early with an error if a read fails.
+pub type StationDayStream = BoxStream<'static, Result<StationDay>>;
+
+/// Observations of [`NORMAL_ELEMENTS`] that passed quality control, one entry
+/// per date in date order with one observation per element; the flags of an
+/// element reported by several stations are those of the first.
+#[derive(Debug, Clone, Default)]
+pub struct ElementDays {
+    pub days: Vec<(NaiveDate, Vec<Observation>)>,
+    pub values_dropped: u32,
+}
+
+/// Per-day-of-year sums of [`NORMAL_ELEMENTS`] over a base period.
+#[derive(Debug, Clone)]
+pub struct BaseSums {
+    /// In [`NORMAL_ELEMENTS`] order
+    pub sums: Vec<DaySums>,
+    pub values_dropped: u32,
+}
+
+/// Locations, observations and aggregates, as read and written by the handlers.
+///
+/// Year ranges are inclusive; `qc` decides which values take part in
+/// aggregates and is applied the same way by every implementation.
+pub trait ClimateRepository: Clone + Send + Sync + 'static {
+    /// Every registered location, followed by the names that only appear in the observations.
+    fn list_locations(&self) -> impl Future<Output = Result<Vec<Location>>> + Send;
+
+    fn get_location(&self, location: &str) -> impl Future<Output = Result<Option<Location>>> + Send;
+
+    /// Register a location; returns `None` when it is already registered.
+    fn insert_location(
+        &self,
+        location: &str,
+        details: &LocationDetails,
+    ) -> impl Future<Output = Result<Option<Location>>> + Send;
+
+    /// Replace the details of a location; returns `None` when it is not registered.
+    fn update_location(
+        &self,
+        location: &str,
+        details: &LocationDetails,
+    ) -> impl Future<Output = Result<Option<Location>>> + Send;
+
+    /// Unregister a location, keeping its observations; returns whether it was registered.
+    fn delete_location(&self, location: &str) -> impl Future<Output = Result<bool>> + Send;
+
+    /// Stations within the bounding box of `radius_km` around a point; callers
+    /// still need to filter on the exact distance.
+    fn stations_within(
+        &self,
+        latitude: f64,
+        longitude: f64,
+        radius_km: f64,
+    ) -> impl Future<Output = Result<Vec<Station>>> + Send;
+
+    /// Temperatures recorded at `location` on `month`/`day` of each year, one
+    /// entry per station-year in year order.
+    fn day_temperatures(
+        &self,
+        location: &str,
+        month: u32,
+        day: u32,
+        start_year: i32,
+        end_year: i32,
+        qc: Qc,
+    ) -> impl Future<Output = Result<Vec<YearTemperatures>>> + Send;
+
+    /// Precipitation totals per year, over `month` only when given. Years with
+    /// rows but no accepted PRCP value have a total of 0.
+    fn precipitation_totals(
+        &self,
+        location: &str,
+        month: Option<u32>,
+        start_year: i32,
+        end_year: i32,
+        qc: Qc,
+    ) -> impl Future<Output = Result<Vec<YearTotal>>> + Send;
+
+    /// Stored rows between two dates in date and station order, restricted to
+    /// rows holding any of `elements` when given.
+    fn daily_observations(
+        &self,
+        location: &str,
+        start_date: NaiveDate,
+        end_date: NaiveDate,
+        elements: Option<&[Element]>,
+        limit: u32,
+        offset: u32,
+    ) -> impl Future<Output = Result<ObservationPage>> + Send;
+
+    /// The rows of [`daily_observations`](Self::daily_observations), all of
+    /// them unless `limit` is given, streamed as they are read. Rows whose
+    /// `data` cannot be decoded are skipped.
+    fn stream_daily_observations(
+        &self,
+        location: &str,
+        start_date: NaiveDate,
+        end_date: NaiveDate,
+        elements: Option<&[Element]>,
+        limit: Option<u32>,
+        offset: u32,
+    ) -> impl Future<Output = Result<StationDayStream>> + Send;
+
+    /// Observations of [`NORMAL_ELEMENTS`] between two dates, merged across stations.
+    fn element_days(
+        &self,
+        location: &str,
+        start_date: NaiveDate,
+        end_date: NaiveDate,
+        qc: Qc,
+    ) -> impl Future<Output = Result<ElementDays>> + Send;
+
+    /// Sums of [`NORMAL_ELEMENTS`] per day of year over a base period.
+    fn base_sums(
+        &self,
+        location: &str,
+        base_start_year: i32,
+        base_end_year: i32,
+        qc: Qc,
+    ) -> impl Future<Output = Result<BaseSums>> + Send;
+
+    /// Normals stored for a location and base period under the `qc` policy;
+    /// empty when none were computed.
+    fn load_normals(
+        &self,
+        location: &str,
+        base_start_year: i32,
+        base_end_year: i32,
+        qc: Qc,
+    ) -> impl Future<Output = Result<NormalsTable>> + Send;
+
+    /// Replace the normals stored for a location and base period under the
+    /// `qc` policy they were computed with.
+    fn store_normals(
+        &self,
+        location: &str,
+        base_start_year: i32,
+        base_end_year: i32,
+        qc: Qc,
+        smoothing: Smoothing,
+        normals: &[ElementNormals],
+    ) -> impl Future<Output = Result<()>> + Send;
+
+    fn list_api_keys(&self) -> impl Future<Output = Result<Vec<ApiKey>>> + Send;
+
+    /// The key whose SHA-256 hash is `hash`.
+    fn find_api_key(&self, hash: &str) -> impl Future<Output = Result<Option<ApiKey>>> + Send;
+
+    /// Store a key by its hash; returns `None` when the name is taken.
+    fn insert_api_key(
+        &self,
+        key: &NewApiKey,
+        prefix: &str,
+        hash: &str,
+    ) -> impl Future<Output = Result<Option<ApiKey>>> + Send;
+
+    /// Delete a key; returns whether it existed.
+    fn delete_api_key(&self, name: &str) -> impl Future<Output = Result<bool>> + Send;
+
+    /// Check that the storage can serve queries.
+    fn ping(&self) -> impl Future<Output = Result<()>> + Send;
+}
+
+/// First and last day of an inclusive year range, clamped to the years the
+/// SQL queries accept (`make_date(GREATEST(year, 1), ...)`).
+pub fn year_span(start_year: i32, end_year: i32) -> (NaiveDate, NaiveDate) {
+    let first = NaiveDate::from_ymd_opt(start_year.clamp(1, 9999), 1, 1).unwrap_or(NaiveDate::MIN);
+    let last = NaiveDate::from_ymd_opt(end_year.clamp(1, 9999), 12, 31).unwrap_or(NaiveDate::MAX);
+    (first, last)
+}
+
+/// Apply `qc` to the [`NORMAL_ELEMENTS`] of each stored row and merge rows that
+/// share a date (several stations for one location), averaging each element
+/// over the stations that reported it as the normals' base sums do.
+fn merge_element_days<I>(rows: I, qc: Qc) -> ElementDays
+where
+    I: IntoIterator<Item = (NaiveDate, Vec<Observation>)>,
+{
+    let mut days: BTreeMap<NaiveDate, Vec<(Observation, u32)>> = BTreeMap::new();
+    let mut values_dropped = 0;
+    for (date, stored) in rows {
+        let (selected, dropped) = observations::select(stored, Some(&NORMAL_ELEMENTS), qc);
+        values_dropped += dropped;
+        let merged = days.entry(date).or_default();
+        for observation in selected {
+            match merged.iter_mut().find(|(o, _)| o.element == observation.element) {
+                Some((o, stations)) => {
+                    o.value += observation.value;
+                    *stations += 1;
+                }
+                None => merged.push((observation, 1)),
+            }
+        }
+    }
+    ElementDays {
+        days: days
+            .into_iter()
+            .map(|(date, merged)| {
+                let observations = merged
+                    .into_iter()
+                    .map(|(o, stations)| Observation { value: o.value / stations as f64, ..o })
+                    .collect();
+                (date, observations)
+            })
+            .collect(),
+        values_dropped,
+    }
+}
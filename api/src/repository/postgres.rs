@@ -0,0 +1,577 @@
+//! [`ClimateRepository`] on the Postgres schema of `src/migrations/`.
+//!
+//! Aggregates are computed in SQL on the generated element columns of
+//! `daily` (`tmax`, `tmax_qflag`, ...), with `qc` applied by the `qc_accepts`
+//! function.
+
+use chrono::NaiveDate;
+use futures_util::StreamExt;
+use serde_json::Value;
+use std::future::Future;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+use tokio_postgres::error::SqlState;
+
+use super::{
+    merge_element_days, BaseSums, ClimateRepository, ElementDays, ObservationPage, RepositoryError, Result,
+    StationDay, StationDayStream, YearTemperatures, YearTotal,
+};
+use crate::auth::keys;
+use crate::db::{DbPool, TlsConnector};
+use crate::models::{ApiKey, Location, LocationDetails, NewApiKey};
+use crate::normals::{self, DaySums, ElementNormals, NormalsTable, Smoothing, NORMAL_ELEMENTS};
+use crate::observations::{self, Element, Observation, Qc};
+use crate::stations::{self, Station};
+use crate::locations;
+use crate::metrics;
+
+/// Columns read by [`element_row`]: the date, then the value and quality flag
+/// of each of [`NORMAL_ELEMENTS`].
+const ELEMENT_DAY_COLUMNS: &str = "date, tmax, tmax_qflag, tmin, tmin_qflag, prcp, prcp_qflag";
+
+impl From<deadpool_postgres::PoolError> for RepositoryError {
+    fn from(e: deadpool_postgres::PoolError) -> Self {
+        RepositoryError::Connection(e.to_string())
+    }
+}
+
+impl From<tokio_postgres::Error> for RepositoryError {
+    fn from(e: tokio_postgres::Error) -> Self {
+        // Queries of requests that went away are cancelled too, but nobody
+        // is left to see that error
+        if e.code() == Some(&SqlState::QUERY_CANCELED) {
+            return RepositoryError::Timeout(e.to_string());
+        }
+        RepositoryError::Query(e.to_string())
+    }
+}
+
+#[derive(Clone)]
+pub struct PostgresRepository {
+    pool: DbPool,
+    /// Connects to cancel the queries of dropped requests, see [`Connection`]
+    cancel_tls: Option<TlsConnector>,
+}
+
+impl PostgresRepository {
+    pub fn new(pool: DbPool) -> Self {
+        PostgresRepository { pool, cancel_tls: None }
+    }
+
+    /// Cancel the query running for a request that is dropped (timed out, or
+    /// its client went away), connecting to the server with `tls` to do so.
+    pub fn with_cancellation(mut self, tls: TlsConnector) -> Self {
+        self.cancel_tls = Some(tls);
+        self
+    }
+
+    /// A pooled connection, recording the wait for it.
+    async fn client(&self) -> Result<Connection> {
+        let started = Instant::now();
+        let client = self.pool.get().await;
+        metrics::pool_wait(started, matches!(client, Err(deadpool_postgres::PoolError::Timeout(_))));
+        Ok(Connection {
+            client: Some(client?),
+            running: Running::default(),
+            cancel_tls: self.cancel_tls.clone(),
+        })
+    }
+}
+
+/// Whether a query of [`run`] is in progress on a [`Connection`].
+#[derive(Clone, Default)]
+struct Running(Arc<AtomicBool>);
+
+/// A pooled connection that, dropped while running a query (the future of a
+/// request is dropped when it times out or its client goes away), cancels the
+/// query on the server and closes, rather than returning to the pool busy.
+struct Connection {
+    client: Option<deadpool_postgres::Object>,
+    running: Running,
+    cancel_tls: Option<TlsConnector>,
+}
+
+impl Connection {
+    fn running(&self) -> Running {
+        self.running.clone()
+    }
+}
+
+impl Deref for Connection {
+    type Target = deadpool_postgres::Object;
+
+    fn deref(&self) -> &Self::Target {
+        self.client.as_ref().expect("taken on drop only")
+    }
+}
+
+impl DerefMut for Connection {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.client.as_mut().expect("taken on drop only")
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        if !self.running.0.load(Ordering::Relaxed) {
+            return;
+        }
+        let (Some(tls), Ok(runtime)) = (self.cancel_tls.clone(), tokio::runtime::Handle::try_current()) else {
+            return;
+        };
+        let client = deadpool_postgres::Object::take(self.client.take().expect("dropped once"));
+        metrics::query_cancelled();
+        runtime.spawn(async move {
+            if let Err(e) = client.cancel_token().cancel_query(tls).await {
+                tracing::warn!("Failed to cancel the query of a dropped request: {}", e);
+            }
+        });
+    }
+}
+
+/// Run `future`, the queries of repository operation `query` on the
+/// connection `running` belongs to, recording their duration.
+async fn run<F: Future>(running: Running, query: &str, future: F) -> F::Output {
+    running.0.store(true, Ordering::Relaxed);
+    let output = metrics::query(query, future).await;
+    running.0.store(false, Ordering::Relaxed);
+    output
+}
+
+/// Filter of the daily observations queries on `$1` location, `$2`-`$3`
+/// dates and `$4` element codes (any when null).
+///
+/// Rows qualify when any of the requested elements is present; the CASE wraps
+/// object-shaped data so both storage formats are searched alike.
+const DAILY_OBSERVATIONS_FILTER: &str = "
+    FROM daily
+    WHERE location = $1
+    AND date BETWEEN $2 AND $3
+    AND data IS NOT NULL
+    AND ($4::TEXT[] IS NULL OR EXISTS (
+        SELECT 1
+        FROM jsonb_array_elements(
+            CASE WHEN jsonb_typeof(data) = 'array' THEN data ELSE jsonb_build_array(data) END
+        ) AS item
+        WHERE jsonb_typeof(item) = 'object' AND item ?| $4::TEXT[]
+    ))
+";
+
+/// Rows fetched from the cursor of [`ClimateRepository::stream_daily_observations`] at a time.
+const CURSOR_BATCH_ROWS: i32 = 1000;
+
+/// Rows of [`DAILY_OBSERVATIONS_FILTER`] in order, `$5` rows (all when null) after skipping `$6`.
+fn daily_observations_query() -> String {
+    format!(
+        "SELECT date, station_id, data::TEXT {} ORDER BY date, station_id LIMIT $5 OFFSET $6",
+        DAILY_OBSERVATIONS_FILTER
+    )
+}
+
+fn element_codes(elements: Option<&[Element]>) -> Option<Vec<String>> {
+    elements.map(|list| list.iter().map(|e| e.code().to_string()).collect())
+}
+
+/// Decode a row of [`daily_observations_query`]; corrupt `data` is logged
+/// and gives `None`.
+fn station_day(row: &tokio_postgres::Row) -> Option<StationDay> {
+    let date: NaiveDate = row.get(0);
+    let station_id: String = row.get(1);
+    let data_str: String = row.get(2);
+    match serde_json::from_str::<Value>(&data_str) {
+        Ok(data) if observations::is_well_formed(&data) => Some(StationDay {
+            date,
+            station_id,
+            observations: observations::decode(&data),
+        }),
+        _ => {
+            tracing::warn!("Corrupt data in daily row {} {}: {}", station_id, date, data_str);
+            None
+        }
+    }
+}
+
+/// Observations of [`NORMAL_ELEMENTS`] from a row of [`ELEMENT_DAY_COLUMNS`].
+fn element_row(row: &tokio_postgres::Row) -> (NaiveDate, Vec<Observation>) {
+    let stored = NORMAL_ELEMENTS.iter().enumerate().filter_map(|(i, element)| {
+        let value: Option<f64> = row.get(1 + 2 * i);
+        let qflag: Option<String> = row.get(2 + 2 * i);
+        value.map(|value| Observation {
+            element: element.clone(),
+            value,
+            mflag: None,
+            qflag: qflag.and_then(|f| f.chars().next()),
+            sflag: None,
+        })
+    });
+    (row.get(0), stored.collect())
+}
+
+impl ClimateRepository for PostgresRepository {
+    async fn list_locations(&self) -> Result<Vec<Location>> {
+        let client = self.client().await?;
+        Ok(run(client.running(), "list_locations", locations::list(&client)).await?)
+    }
+
+    async fn get_location(&self, location: &str) -> Result<Option<Location>> {
+        let client = self.client().await?;
+        Ok(run(client.running(), "get_location", locations::get(&client, location)).await?)
+    }
+
+    async fn insert_location(&self, location: &str, details: &LocationDetails) -> Result<Option<Location>> {
+        let client = self.client().await?;
+        Ok(run(client.running(), "insert_location", locations::insert(&client, location, details)).await?)
+    }
+
+    async fn update_location(&self, location: &str, details: &LocationDetails) -> Result<Option<Location>> {
+        let client = self.client().await?;
+        Ok(run(client.running(), "update_location", locations::update(&client, location, details)).await?)
+    }
+
+    async fn delete_location(&self, location: &str) -> Result<bool> {
+        let client = self.client().await?;
+        Ok(run(client.running(), "delete_location", locations::delete(&client, location)).await?)
+    }
+
+    async fn stations_within(&self, latitude: f64, longitude: f64, radius_km: f64) -> Result<Vec<Station>> {
+        let client = self.client().await?;
+        let query = stations::within_box(&client, latitude, longitude, radius_km);
+        Ok(run(client.running(), "stations_within", query).await?)
+    }
+
+    async fn day_temperatures(
+        &self,
+        location: &str,
+        month: u32,
+        day: u32,
+        start_year: i32,
+        end_year: i32,
+        qc: Qc,
+    ) -> Result<Vec<YearTemperatures>> {
+        let client = self.client().await?;
+
+        // One row per station-year for the calendar day, with the QC-rejected values
+        // blanked and counted
+        let query = "
+            SELECT EXTRACT(YEAR FROM date)::INTEGER AS year,
+                   CASE WHEN qc_accepts(tmax_qflag, $6) THEN tmax END,
+                   CASE WHEN qc_accepts(tmin_qflag, $6) THEN tmin END,
+                   CASE WHEN qc_accepts(tavg_qflag, $6) THEN tavg END,
+                   (tmax IS NOT NULL AND NOT qc_accepts(tmax_qflag, $6))::INTEGER
+                   + (tmin IS NOT NULL AND NOT qc_accepts(tmin_qflag, $6))::INTEGER
+                   + (tavg IS NOT NULL AND NOT qc_accepts(tavg_qflag, $6))::INTEGER
+            FROM daily
+            WHERE location = $5
+            AND EXTRACT(MONTH FROM date) = $1::INTEGER
+            AND EXTRACT(DAY FROM date) = $2::INTEGER
+            AND date BETWEEN make_date(GREATEST($3::INTEGER, 1), 1, 1) AND make_date(LEAST($4::INTEGER, 9999), 12, 31)
+            AND data IS NOT NULL
+            ORDER BY year
+        ";
+        let rows = run(
+            client.running(),
+            "day_temperatures",
+            client.query(
+                query,
+                &[&(month as i32), &(day as i32), &start_year, &end_year, &location, &qc.as_str()],
+            ),
+        )
+        .await?;
+        metrics::rows_scanned(rows.len() as u64);
+
+        Ok(rows
+            .iter()
+            .map(|row| YearTemperatures {
+                year: row.get(0),
+                tmax: row.get(1),
+                tmin: row.get(2),
+                tavg: row.get(3),
+                values_dropped: row.get::<_, i32>(4) as u32,
+            })
+            .collect())
+    }
+
+    async fn precipitation_totals(
+        &self,
+        location: &str,
+        month: Option<u32>,
+        start_year: i32,
+        end_year: i32,
+        qc: Qc,
+    ) -> Result<Vec<YearTotal>> {
+        let client = self.client().await?;
+
+        // Days without a (QC-passing) PRCP value contribute nothing, but still
+        // count the year as sampled
+        let query = "
+            SELECT EXTRACT(YEAR FROM date)::INTEGER AS year,
+                   COALESCE(SUM(prcp) FILTER (WHERE qc_accepts(prcp_qflag, $5)), 0),
+                   COUNT(prcp) FILTER (WHERE NOT qc_accepts(prcp_qflag, $5)),
+                   COUNT(*)
+            FROM daily
+            WHERE location = $4
+            AND ($1::INTEGER IS NULL OR EXTRACT(MONTH FROM date) = $1::INTEGER)
+            AND date BETWEEN make_date(GREATEST($2::INTEGER, 1), 1, 1) AND make_date(LEAST($3::INTEGER, 9999), 12, 31)
+            AND data IS NOT NULL
+            GROUP BY year
+            ORDER BY year
+        ";
+        let month = month.map(|m| m as i32);
+        let rows = run(
+            client.running(),
+            "precipitation_totals",
+            client.query(query, &[&month, &start_year, &end_year, &location, &qc.as_str()]),
+        )
+        .await?;
+        metrics::rows_scanned(rows.iter().map(|row| row.get::<_, i64>(3) as u64).sum());
+
+        Ok(rows
+            .iter()
+            .map(|row| YearTotal {
+                year: row.get(0),
+                total: row.get(1),
+                values_dropped: row.get::<_, i64>(2) as u32,
+            })
+            .collect())
+    }
+
+    async fn daily_observations(
+        &self,
+        location: &str,
+        start_date: NaiveDate,
+        end_date: NaiveDate,
+        elements: Option<&[Element]>,
+        limit: u32,
+        offset: u32,
+    ) -> Result<ObservationPage> {
+        let client = self.client().await?;
+        let element_codes = element_codes(elements);
+
+        let count_query = format!("SELECT COUNT(*) {}", DAILY_OBSERVATIONS_FILTER);
+        let (total, rows) = run(client.running(), "daily_observations", async {
+            let total: i64 = client
+                .query_one(&count_query, &[&location, &start_date, &end_date, &element_codes])
+                .await?
+                .get(0);
+            let rows = client
+                .query(
+                    &daily_observations_query(),
+                    &[&location, &start_date, &end_date, &element_codes, &(limit as i64), &(offset as i64)],
+                )
+                .await?;
+            Ok::<_, tokio_postgres::Error>((total, rows))
+        })
+        .await?;
+        // The count reads every matching row, the page only some of them again
+        metrics::rows_scanned(total as u64);
+
+        let mut days = Vec::with_capacity(rows.len());
+        let mut rows_corrupt = 0;
+        for row in &rows {
+            match station_day(row) {
+                Some(day) => days.push(day),
+                None => rows_corrupt += 1,
+            }
+        }
+
+        Ok(ObservationPage {
+            total: total as u64,
+            days,
+            rows_corrupt,
+        })
+    }
+
+    async fn stream_daily_observations(
+        &self,
+        location: &str,
+        start_date: NaiveDate,
+        end_date: NaiveDate,
+        elements: Option<&[Element]>,
+        limit: Option<u32>,
+        offset: u32,
+    ) -> Result<StationDayStream> {
+        let mut client = self.client().await?;
+        let statement = client.prepare_cached(&daily_observations_query()).await?;
+        let location = location.to_string();
+        let element_codes = element_codes(elements);
+        let limit = limit.map(i64::from);
+        let offset = i64::from(offset);
+        let request_rows = metrics::request_rows();
+
+        // The connection stays with the task until the cursor is exhausted or
+        // the receiver is dropped, which rolls the transaction back
+        let (sender, receiver) = tokio::sync::mpsc::channel(CURSOR_BATCH_ROWS as usize);
+        tokio::spawn(async move {
+            let read = async {
+                let transaction = client.transaction().await?;
+                let portal = transaction
+                    .bind(&statement, &[&location, &start_date, &end_date, &element_codes, &limit, &offset])
+                    .await?;
+                loop {
+                    let rows = metrics::query(
+                        "stream_daily_observations",
+                        transaction.query_portal(&portal, CURSOR_BATCH_ROWS),
+                    )
+                    .await?;
+                    if rows.is_empty() {
+                        return Ok::<_, tokio_postgres::Error>(());
+                    }
+                    if let Some(request_rows) = &request_rows {
+                        request_rows.add(rows.len() as u64);
+                    }
+                    for day in rows.iter().filter_map(station_day) {
+                        if sender.send(Ok(day)).await.is_err() {
+                            return Ok(());
+                        }
+                    }
+                }
+            };
+            if let Err(e) = read.await {
+                let _ = sender.send(Err(RepositoryError::from(e))).await;
+            }
+        });
+
+        Ok(futures_util::stream::unfold(receiver, |mut receiver| async move {
+            receiver.recv().await.map(|item| (item, receiver))
+        })
+        .boxed())
+    }
+
+    async fn element_days(&self, location: &str, start_date: NaiveDate, end_date: NaiveDate, qc: Qc) -> Result<ElementDays> {
+        let client = self.client().await?;
+        let query = format!(
+            "SELECT {}
+             FROM daily
+             WHERE location = $1
+             AND date BETWEEN $2 AND $3
+             AND data IS NOT NULL
+             ORDER BY date",
+            ELEMENT_DAY_COLUMNS
+        );
+        let rows = run(
+            client.running(),
+            "element_days",
+            client.query(&query, &[&location, &start_date, &end_date]),
+        )
+        .await?;
+        metrics::rows_scanned(rows.len() as u64);
+        Ok(merge_element_days(rows.iter().map(element_row), qc))
+    }
+
+    async fn base_sums(&self, location: &str, base_start_year: i32, base_end_year: i32, qc: Qc) -> Result<BaseSums> {
+        let client = self.client().await?;
+
+        // Per-day-of-year sums on a leap-year calendar, so February 29th keeps its slot
+        let sums_query = "
+            SELECT EXTRACT(DOY FROM make_date(2000, EXTRACT(MONTH FROM date)::INTEGER, EXTRACT(DAY FROM date)::INTEGER))::INTEGER AS day_of_year,
+                   COALESCE(SUM(tmax) FILTER (WHERE qc_accepts(tmax_qflag, $4)), 0),
+                   COUNT(tmax) FILTER (WHERE qc_accepts(tmax_qflag, $4)),
+                   COALESCE(SUM(tmin) FILTER (WHERE qc_accepts(tmin_qflag, $4)), 0),
+                   COUNT(tmin) FILTER (WHERE qc_accepts(tmin_qflag, $4)),
+                   COALESCE(SUM(prcp) FILTER (WHERE qc_accepts(prcp_qflag, $4)), 0),
+                   COUNT(prcp) FILTER (WHERE qc_accepts(prcp_qflag, $4))
+            FROM daily
+            WHERE location = $1
+            AND date BETWEEN make_date(GREATEST($2::INTEGER, 1), 1, 1) AND make_date(LEAST($3::INTEGER, 9999), 12, 31)
+            AND data IS NOT NULL
+            GROUP BY day_of_year
+        ";
+        let years_query = "
+            SELECT COUNT(DISTINCT EXTRACT(YEAR FROM date)) FILTER (WHERE tmax IS NOT NULL AND qc_accepts(tmax_qflag, $4)),
+                   COUNT(DISTINCT EXTRACT(YEAR FROM date)) FILTER (WHERE tmin IS NOT NULL AND qc_accepts(tmin_qflag, $4)),
+                   COUNT(DISTINCT EXTRACT(YEAR FROM date)) FILTER (WHERE prcp IS NOT NULL AND qc_accepts(prcp_qflag, $4)),
+                   COUNT(tmax) FILTER (WHERE NOT qc_accepts(tmax_qflag, $4))
+                   + COUNT(tmin) FILTER (WHERE NOT qc_accepts(tmin_qflag, $4))
+                   + COUNT(prcp) FILTER (WHERE NOT qc_accepts(prcp_qflag, $4)),
+                   COUNT(*)
+            FROM daily
+            WHERE location = $1
+            AND date BETWEEN make_date(GREATEST($2::INTEGER, 1), 1, 1) AND make_date(LEAST($3::INTEGER, 9999), 12, 31)
+            AND data IS NOT NULL
+        ";
+        let query_params: [&(dyn tokio_postgres::types::ToSql + Sync); 4] =
+            [&location, &base_start_year, &base_end_year, &qc.as_str()];
+        let (sum_rows, totals) = run(
+            client.running(),
+            "base_sums",
+            futures_util::future::try_join(
+                client.query(sums_query, &query_params),
+                client.query_one(years_query, &query_params),
+            ),
+        )
+        .await?;
+        // Both queries read the same rows
+        metrics::rows_scanned(totals.get::<_, i64>(4) as u64);
+
+        // NORMAL_ELEMENTS order: TMAX, TMIN, PRCP
+        let mut sums: Vec<DaySums> = NORMAL_ELEMENTS.iter().cloned().map(DaySums::new).collect();
+        for row in &sum_rows {
+            let index = row.get::<_, i32>(0) as usize - 1;
+            for (i, acc) in sums.iter_mut().enumerate() {
+                acc.sums[index] = row.get(1 + 2 * i);
+                acc.counts[index] = row.get::<_, i64>(2 + 2 * i) as u32;
+            }
+        }
+        for (i, acc) in sums.iter_mut().enumerate() {
+            acc.years = totals.get::<_, i64>(i) as u32;
+        }
+
+        Ok(BaseSums {
+            sums,
+            values_dropped: totals.get::<_, i64>(3) as u32,
+        })
+    }
+
+    async fn load_normals(&self, location: &str, base_start_year: i32, base_end_year: i32, qc: Qc) -> Result<NormalsTable> {
+        let client = self.client().await?;
+        let query = normals::load(&client, location, base_start_year, base_end_year, qc);
+        Ok(run(client.running(), "load_normals", query).await?)
+    }
+
+    async fn store_normals(
+        &self,
+        location: &str,
+        base_start_year: i32,
+        base_end_year: i32,
+        qc: Qc,
+        smoothing: Smoothing,
+        computed: &[ElementNormals],
+    ) -> Result<()> {
+        let mut client = self.client().await?;
+        run(client.running(), "store_normals", async {
+            let transaction = client.transaction().await?;
+            normals::store(&transaction, location, base_start_year, base_end_year, qc, smoothing, computed).await?;
+            transaction.commit().await
+        })
+        .await?;
+        Ok(())
+    }
+
+    async fn list_api_keys(&self) -> Result<Vec<ApiKey>> {
+        let client = self.client().await?;
+        Ok(run(client.running(), "list_api_keys", keys::list(&client)).await?)
+    }
+
+    async fn find_api_key(&self, hash: &str) -> Result<Option<ApiKey>> {
+        let client = self.client().await?;
+        Ok(run(client.running(), "find_api_key", keys::find(&client, hash)).await?)
+    }
+
+    async fn insert_api_key(&self, key: &NewApiKey, prefix: &str, hash: &str) -> Result<Option<ApiKey>> {
+        let client = self.client().await?;
+        Ok(run(client.running(), "insert_api_key", keys::insert(&client, key, prefix, hash)).await?)
+    }
+
+    async fn delete_api_key(&self, name: &str) -> Result<bool> {
+        let client = self.client().await?;
+        Ok(run(client.running(), "delete_api_key", keys::delete(&client, name)).await?)
+    }
+
+    async fn ping(&self) -> Result<()> {
+        let client = self.client().await?;
+        run(client.running(), "ping", client.simple_query("SELECT 1")).await?;
+        Ok(())
+    }
+}
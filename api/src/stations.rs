@@ -0,0 +1,137 @@
+//! GHCN-Daily station catalogue: where each station is and which elements it
+//! recorded over which years, as loaded from `ghcnd-stations.txt` and
+//! `ghcnd-inventory.txt` into the `stations` and `station_inventory` tables.
+
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::observations::Element;
+
+/// Mean Earth radius used for great-circle distances.
+const EARTH_RADIUS_KM: f64 = 6371.0;
+
+/// Length of one degree of latitude.
+const KM_PER_DEGREE: f64 = 2.0 * std::f64::consts::PI * EARTH_RADIUS_KM / 360.0;
+
+/// A station of the catalogue.
+#[derive(Debug, Clone)]
+pub struct Station {
+    pub station_id: String,
+    pub name: String,
+    pub latitude: f64,
+    pub longitude: f64,
+    /// Metres above sea level, if known
+    pub elevation: Option<f64>,
+    /// U.S. state or Canadian province code
+    pub state: Option<String>,
+    /// `GSN` for stations of the GCOS Surface Network
+    pub gsn_flag: Option<String>,
+    /// `HCN` or `CRN` for U.S. Historical Climatology / Climate Reference Network stations
+    pub hcn_crn_flag: Option<String>,
+    pub wmo_id: Option<String>,
+    /// Period of record per element
+    pub elements: Vec<ElementPeriod>,
+}
+
+/// First and last year a station recorded an element.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ElementPeriod {
+    pub element: Element,
+    pub first_year: i32,
+    pub last_year: i32,
+}
+
+/// Great-circle (haversine) distance between two points in km.
+pub fn distance_km(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    let (phi1, phi2) = (lat1.to_radians(), lat2.to_radians());
+    let dphi = (lat2 - lat1).to_radians();
+    let dlambda = (lon2 - lon1).to_radians();
+    let a = (dphi / 2.0).sin().powi(2) + phi1.cos() * phi2.cos() * (dlambda / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_KM * a.sqrt().min(1.0).asin()
+}
+
+/// Latitude/longitude box containing every point within `radius_km`.
+///
+/// The longitude range is `None` when the box reaches a pole, and wraps
+/// (`min > max`) when it crosses the antimeridian.
+pub fn bounding_box(latitude: f64, longitude: f64, radius_km: f64) -> ((f64, f64), Option<(f64, f64)>) {
+    let dlat = radius_km / KM_PER_DEGREE;
+    let (min_lat, max_lat) = (latitude - dlat, latitude + dlat);
+    if min_lat <= -90.0 || max_lat >= 90.0 {
+        return ((min_lat.max(-90.0), max_lat.min(90.0)), None);
+    }
+    // Widest at the latitude closest to a pole
+    let widest = min_lat.abs().max(max_lat.abs()).to_radians().cos();
+    let dlon = dlat / widest;
+    if dlon >= 180.0 {
+        return ((min_lat, max_lat), None);
+    }
+    let wrap = |lon: f64| (lon + 540.0).rem_euclid(360.0) - 180.0;
+    ((min_lat, max_lat), Some((wrap(longitude - dlon), wrap(longitude + dlon))))
+}
+
+/// Share (0-1) of `start_year..=end_year` covered by the period of record of
+/// each of `elements`, averaged over the elements.
+pub fn coverage(periods: &[ElementPeriod], elements: &[Element], start_year: i32, end_year: i32) -> f64 {
+    let years = (end_year - start_year + 1).max(1) as f64;
+    let covered_years: i32 = elements
+        .iter()
+        .filter_map(|element| periods.iter().find(|p| p.element == *element))
+        .map(|p| (p.last_year.min(end_year) - p.first_year.max(start_year) + 1).max(0))
+        .sum();
+    covered_years as f64 / years / elements.len().max(1) as f64
+}
+
+/// Stations within the bounding box of `radius_km` around a point; callers
+/// still need to filter on the exact distance.
+pub async fn within_box(
+    client: &tokio_postgres::Client,
+    latitude: f64,
+    longitude: f64,
+    radius_km: f64,
+) -> Result<Vec<Station>, tokio_postgres::Error> {
+    let ((min_lat, max_lat), longitudes) = bounding_box(latitude, longitude, radius_km);
+    let (min_lon, max_lon) = longitudes.unzip();
+
+    let rows = client
+        .query(
+            "SELECT s.station_id, s.name, s.latitude, s.longitude, s.elevation, s.state,
+                    s.gsn_flag, s.hcn_crn_flag, s.wmo_id,
+                    COALESCE(
+                        jsonb_agg(
+                            jsonb_build_object('element', i.element, 'first_year', i.first_year, 'last_year', i.last_year)
+                            ORDER BY i.element
+                        ) FILTER (WHERE i.element IS NOT NULL),
+                        '[]'
+                    )::TEXT
+             FROM stations s
+             LEFT JOIN station_inventory i ON i.station_id = s.station_id
+             WHERE s.latitude BETWEEN $1 AND $2
+             AND ($3::FLOAT8 IS NULL OR CASE
+                     WHEN $3 <= $4 THEN s.longitude BETWEEN $3 AND $4
+                     ELSE s.longitude >= $3 OR s.longitude <= $4
+                 END)
+             GROUP BY s.station_id",
+            &[&min_lat, &max_lat, &min_lon, &max_lon],
+        )
+        .await?;
+
+    Ok(rows
+        .iter()
+        .map(|row| {
+            let elements: String = row.get(9);
+            Station {
+                station_id: row.get(0),
+                name: row.get(1),
+                latitude: row.get(2),
+                longitude: row.get(3),
+                elevation: row.get(4),
+                state: row.get(5),
+                gsn_flag: row.get(6),
+                hcn_crn_flag: row.get(7),
+                wmo_id: row.get(8),
+                elements: serde_json::from_str(&elements).unwrap_or_default(),
+            }
+        })
+        .collect())
+}
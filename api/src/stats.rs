@@ -0,0 +1,149 @@
+//! Descriptive statistics over per-year samples.
+
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use std::collections::BTreeMap;
+
+/// Value observed in a given year.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, ToSchema)]
+pub struct YearValue {
+    pub year: i32,
+    pub value: f64,
+}
+
+/// Summary statistics of a sample of yearly values.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct Summary {
+    pub count: u32,
+    pub mean: f64,
+    pub min: f64,
+    /// Year of the lowest value (the most recent one when tied)
+    pub min_year: i32,
+    pub max: f64,
+    /// Year of the highest value (the most recent one when tied)
+    pub max_year: i32,
+    pub median: f64,
+    /// Sample standard deviation; absent with fewer than two values
+    pub std_dev: Option<f64>,
+    /// Requested percentiles keyed as `p10`, `p90`, ...
+    pub percentiles: BTreeMap<String, f64>,
+    pub values: Vec<YearValue>,
+}
+
+impl Summary {
+    /// Summarise `values`, or return `None` for an empty sample.
+    ///
+    /// `percentiles` are in the range 0-100.
+    pub fn from_values(values: Vec<YearValue>, percentiles: &[f64]) -> Option<Summary> {
+        let first = *values.first()?;
+        let count = values.len();
+        let mean = values.iter().map(|v| v.value).sum::<f64>() / count as f64;
+
+        let mut min = first;
+        let mut max = first;
+        for v in &values[1..] {
+            if v.value < min.value || (v.value == min.value && v.year > min.year) {
+                min = *v;
+            }
+            if v.value > max.value || (v.value == max.value && v.year > max.year) {
+                max = *v;
+            }
+        }
+
+        let std_dev = (count > 1).then(|| {
+            let variance = values.iter().map(|v| (v.value - mean).powi(2)).sum::<f64>() / (count - 1) as f64;
+            variance.sqrt()
+        });
+
+        let mut sorted: Vec<f64> = values.iter().map(|v| v.value).collect();
+        sorted.sort_by(f64::total_cmp);
+
+        Some(Summary {
+            count: count as u32,
+            mean,
+            min: min.value,
+            min_year: min.year,
+            max: max.value,
+            max_year: max.year,
+            median: percentile(&sorted, 50.0)?,
+            std_dev,
+            percentiles: percentiles
+                .iter()
+                .map(|p| Some((percentile_key(*p), percentile(&sorted, *p)?)))
+                .collect::<Option<_>>()?,
+            values,
+        })
+    }
+}
+
+/// Key for a percentile in [`Summary::percentiles`], e.g. `p90` or `p2.5`.
+pub fn percentile_key(p: f64) -> String {
+    format!("p{}", p)
+}
+
+/// Percentile of an ascending slice using linear interpolation between
+/// closest ranks, or `None` when it is empty.
+pub fn percentile(sorted: &[f64], p: f64) -> Option<f64> {
+    let rank = p / 100.0 * sorted.len().checked_sub(1)? as f64;
+    let lower = rank.floor() as usize;
+    let upper = rank.ceil() as usize;
+    Some(sorted[lower] + (sorted[upper] - sorted[lower]) * (rank - lower as f64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn years(values: &[f64]) -> Vec<YearValue> {
+        values
+            .iter()
+            .enumerate()
+            .map(|(i, &value)| YearValue { year: 2000 + i as i32, value })
+            .collect()
+    }
+
+    #[test]
+    fn interpolates_percentiles_between_ranks() {
+        let sorted = [1.0, 2.0, 3.0, 4.0];
+        assert_eq!(percentile(&sorted, 0.0), Some(1.0));
+        assert_eq!(percentile(&sorted, 25.0), Some(1.75));
+        assert_eq!(percentile(&sorted, 50.0), Some(2.5));
+        assert_eq!(percentile(&sorted, 100.0), Some(4.0));
+        assert_eq!(percentile(&[10.0, 20.0, 30.0, 40.0, 50.0], 90.0), Some(46.0));
+    }
+
+    #[test]
+    fn takes_every_percentile_of_a_single_value_as_that_value() {
+        for p in [0.0, 10.0, 50.0, 99.9, 100.0] {
+            assert_eq!(percentile(&[7.5], p), Some(7.5));
+        }
+        assert_eq!(percentile(&[], 50.0), None);
+    }
+
+    #[test]
+    fn summarises_a_sample() {
+        let summary = Summary::from_values(years(&[2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0]), &[2.5, 90.0]).unwrap();
+        assert_eq!((summary.count, summary.mean, summary.median), (8, 5.0, 4.5));
+        assert_eq!((summary.min, summary.min_year), (2.0, 2000));
+        assert_eq!((summary.max, summary.max_year), (9.0, 2007));
+        // Sample (n - 1) standard deviation: sqrt(32 / 7)
+        assert!((summary.std_dev.unwrap() - (32.0f64 / 7.0).sqrt()).abs() < 1e-12);
+        assert_eq!(summary.percentiles.keys().collect::<Vec<_>>(), ["p2.5", "p90"]);
+        assert!((summary.percentiles["p2.5"] - 2.35).abs() < 1e-12);
+        assert!((summary.percentiles["p90"] - 7.6).abs() < 1e-12);
+    }
+
+    #[test]
+    fn reports_the_most_recent_year_of_tied_extremes() {
+        let summary = Summary::from_values(years(&[3.0, 1.0, 3.0, 1.0]), &[]).unwrap();
+        assert_eq!((summary.min_year, summary.max_year), (2003, 2002));
+    }
+
+    #[test]
+    fn summarises_a_single_value_without_a_standard_deviation() {
+        let summary = Summary::from_values(years(&[12.0]), &[10.0]).unwrap();
+        assert_eq!((summary.mean, summary.median, summary.percentiles["p10"]), (12.0, 12.0, 12.0));
+        assert_eq!(summary.std_dev, None);
+        assert!(Summary::from_values(Vec::new(), &[10.0]).is_none());
+    }
+}
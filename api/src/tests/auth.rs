@@ -0,0 +1,133 @@
+use axum::body::Body;
+use axum::http::{header, Method, Request, StatusCode};
+use serde_json::{json, Value};
+
+use super::{TestApp, TestResponse};
+use crate::auth::Role;
+use crate::models::{ApiKey, CreatedApiKey};
+use crate::repository::ClimateRepository;
+
+async fn send(app: &TestApp, method: Method, uri: &str, key: &str, body: Option<Value>) -> TestResponse {
+    let request = Request::builder()
+        .method(method)
+        .uri(uri)
+        .header(header::AUTHORIZATION, format!("Bearer {}", key));
+    let request = match body {
+        Some(body) => request
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string())),
+        None => request.body(Body::empty()),
+    };
+    app.request(request.unwrap()).await
+}
+
+#[tokio::test]
+async fn requires_a_valid_key() {
+    let app = TestApp::with_auth(600, 60);
+
+    let response = app.get("/get_locations").await;
+    response.assert_status(StatusCode::UNAUTHORIZED);
+    assert_eq!(response.headers[header::WWW_AUTHENTICATE], "Bearer");
+    assert_eq!(response.json::<Value>()["code"], "missing_api_key");
+
+    let response = send(&app, Method::GET, "/get_locations", "ck_0123456789abcdef", None).await;
+    response.assert_status(StatusCode::UNAUTHORIZED);
+    assert_eq!(response.json::<Value>()["code"], "invalid_api_key");
+
+    // Either header works
+    let key = app.api_key("weather", Role::Read).await;
+    assert!(key.starts_with("ck_"));
+    send(&app, Method::GET, "/get_locations", &key, None).await.assert_status(StatusCode::OK);
+    let request = Request::get("/get_locations").header("x-api-key", &key).body(Body::empty()).unwrap();
+    app.request(request).await.assert_status(StatusCode::OK);
+
+    // The documentation stays open
+    app.get("/openapi.json").await.assert_status(StatusCode::OK);
+}
+
+#[tokio::test]
+async fn keeps_admin_endpoints_to_admin_keys() {
+    let app = TestApp::with_auth(600, 60);
+    let read = app.api_key("weather", Role::Read).await;
+    let admin = app.api_key("ops", Role::Admin).await;
+
+    for (method, uri) in [
+        (Method::POST, "/compute_normals?location=Oakland"),
+        (Method::GET, "/admin/locations/Oakland"),
+        (Method::GET, "/admin/keys"),
+    ] {
+        let response = send(&app, method.clone(), uri, &read, None).await;
+        response.assert_status(StatusCode::FORBIDDEN);
+        assert_eq!(response.json::<Value>()["code"], "forbidden", "{} {}", method, uri);
+    }
+
+    // Admin keys may also read
+    send(&app, Method::GET, "/get_locations", &admin, None).await.assert_status(StatusCode::OK);
+    send(&app, Method::GET, "/admin/locations/Oakland", &admin, None)
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn manages_keys() {
+    let app = TestApp::with_auth(600, 60);
+    let admin = app.api_key("ops", Role::Admin).await;
+
+    let response = send(&app, Method::POST, "/admin/keys", &admin, Some(json!({"name": "weather", "role": "read", "rate_limit": 120}))).await;
+    response.assert_status(StatusCode::CREATED);
+    let created: CreatedApiKey = response.json();
+    assert_eq!((created.details.role, created.details.rate_limit), (Role::Read, Some(120)));
+    assert!(created.key.starts_with(&created.details.prefix));
+    send(&app, Method::GET, "/get_locations", &created.key, None).await.assert_status(StatusCode::OK);
+
+    let response = send(&app, Method::POST, "/admin/keys", &admin, Some(json!({"name": "weather", "role": "admin"}))).await;
+    response.assert_status(StatusCode::CONFLICT);
+    let response = send(&app, Method::POST, "/admin/keys", &admin, Some(json!({"name": "zero", "role": "read", "rate_limit": 0}))).await;
+    assert_eq!(response.json::<Value>()["parameter"], "rate_limit");
+
+    // Listings never include the key
+    let response = send(&app, Method::GET, "/admin/keys", &admin, None).await;
+    let keys: Vec<ApiKey> = response.json();
+    assert_eq!(keys.iter().map(|k| k.name.as_str()).collect::<Vec<_>>(), ["ops", "weather"]);
+    assert!(!response.body.contains(&created.key));
+
+    send(&app, Method::DELETE, "/admin/keys/weather", &admin, None).await.assert_status(StatusCode::NO_CONTENT);
+    send(&app, Method::GET, "/get_locations", &created.key, None).await.assert_status(StatusCode::UNAUTHORIZED);
+    send(&app, Method::DELETE, "/admin/keys/weather", &admin, None).await.assert_status(StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn limits_the_request_rate_of_each_key() {
+    let app = TestApp::with_auth(60, 2);
+    let first = app.api_key("first", Role::Read).await;
+    let second = app.api_key("second", Role::Read).await;
+
+    for _ in 0..2 {
+        send(&app, Method::GET, "/get_locations", &first, None).await.assert_status(StatusCode::OK);
+    }
+    let response = send(&app, Method::GET, "/get_locations", &first, None).await;
+    response.assert_status(StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(response.json::<Value>()["code"], "rate_limited");
+    assert_eq!(response.headers[header::RETRY_AFTER], "1");
+
+    // Buckets are per key
+    send(&app, Method::GET, "/get_locations", &second, None).await.assert_status(StatusCode::OK);
+}
+
+#[tokio::test]
+async fn remembers_keys_until_revoked_over_http() {
+    let app = TestApp::with_auth(600, 60);
+    let admin = app.api_key("ops", Role::Admin).await;
+    let read = app.api_key("weather", Role::Read).await;
+    send(&app, Method::GET, "/get_locations", &read, None).await.assert_status(StatusCode::OK);
+
+    // Deleted behind the server's back, as `api keys revoke` does, the key is not looked up again
+    assert!(app.repo.delete_api_key("weather").await.unwrap());
+    send(&app, Method::GET, "/get_locations", &read, None).await.assert_status(StatusCode::OK);
+
+    // Revoking it over HTTP forgets it at once
+    let read = app.api_key("weather", Role::Read).await;
+    send(&app, Method::GET, "/get_locations", &read, None).await.assert_status(StatusCode::OK);
+    send(&app, Method::DELETE, "/admin/keys/weather", &admin, None).await.assert_status(StatusCode::NO_CONTENT);
+    send(&app, Method::GET, "/get_locations", &read, None).await.assert_status(StatusCode::UNAUTHORIZED);
+}
@@ -0,0 +1,112 @@
+use axum::body::Body;
+use axum::http::{header, Request, StatusCode};
+use serde_json::json;
+use std::time::Duration;
+
+use super::{date, TestApp, STATION_ID};
+use crate::cache::ResponseCache;
+use crate::models::YearlyPrecipitationResponse;
+
+const YEARLY: &str = "/get_yearly_precipitation?location=Oakland&start_year=2023&end_year=2024&units=raw";
+
+fn app_with(cache: ResponseCache) -> TestApp {
+    let app = TestApp::with_cache(cache);
+    app.day("Oakland", date(2023, 1, 1), json!([{"PRCP": 100}]));
+    app
+}
+
+/// Precipitation of 2023, in raw units.
+async fn total_2023(app: &TestApp, uri: &str) -> f64 {
+    let response: YearlyPrecipitationResponse = app.get(uri).await.json();
+    response.yearly_precipitation[&2023]
+}
+
+async fn get_with(app: &TestApp, uri: &str, name: header::HeaderName, value: &str) -> super::TestResponse {
+    app.request(Request::get(uri).header(name, value).body(Body::empty()).unwrap()).await
+}
+
+#[tokio::test]
+async fn serves_equivalent_requests_from_the_cache() {
+    let app = app_with(ResponseCache::new(1024 * 1024, Duration::from_secs(3600)));
+    assert_eq!(total_2023(&app, YEARLY).await, 100.0);
+
+    // Written behind the cache's back: not seen until the location is invalidated
+    app.repo.insert_day("Oakland", STATION_ID, date(2023, 1, 2), json!([{"PRCP": 50}]));
+    assert_eq!(total_2023(&app, YEARLY).await, 100.0);
+    let reordered = "/get_yearly_precipitation?units=raw&end_year=2024&qc=strict&start_year=2023&location=Oakland";
+    assert_eq!(total_2023(&app, reordered).await, 100.0);
+
+    // Other locations keep their entries
+    app.day("Berkeley", date(2022, 1, 1), json!([{"PRCP": 10}]));
+    assert_eq!(total_2023(&app, YEARLY).await, 100.0);
+    app.cache.invalidate("Oakland");
+    assert_eq!(total_2023(&app, YEARLY).await, 150.0);
+
+    // Formats are cached separately
+    let csv = app.get(&format!("{}&format=csv", YEARLY)).await;
+    assert_eq!(csv.body, "year,precipitation\n2023,150\n");
+}
+
+#[tokio::test]
+async fn expires_and_evicts_entries() {
+    let app = app_with(ResponseCache::new(1024 * 1024, Duration::ZERO));
+    assert_eq!(total_2023(&app, YEARLY).await, 100.0);
+    app.repo.insert_day("Oakland", STATION_ID, date(2023, 1, 2), json!([{"PRCP": 50}]));
+    assert_eq!(total_2023(&app, YEARLY).await, 150.0);
+
+    // A small cache: the least recently used entries make room for new ones
+    let app = app_with(ResponseCache::new(8 * 1024, Duration::from_secs(3600)));
+    assert_eq!(total_2023(&app, YEARLY).await, 100.0);
+    for start_year in 1960..2020 {
+        let uri = format!("/get_yearly_precipitation?location=Oakland&start_year={}&end_year=2024", start_year);
+        assert!(app.get(&uri).await.headers.contains_key(header::ETAG));
+    }
+    app.repo.insert_day("Oakland", STATION_ID, date(2023, 1, 2), json!([{"PRCP": 50}]));
+    assert_eq!(total_2023(&app, YEARLY).await, 150.0);
+}
+
+#[tokio::test]
+async fn clears_the_cache_on_writes_through_the_api() {
+    let app = app_with(ResponseCache::new(1024 * 1024, Duration::from_secs(3600)));
+    let names = |body: &serde_json::Value| body.as_array().unwrap().iter().map(|l| l["registered"].clone()).collect::<Vec<_>>();
+    assert_eq!(names(&app.get("/get_locations").await.json()), [json!(false)]);
+
+    let location = json!({
+        "location": "Oakland",
+        "display_name": "Oakland, CA",
+        "latitude": 37.8,
+        "longitude": -122.27,
+        "timezone": "America/Los_Angeles",
+    });
+    app.post("/admin/locations", Some(location)).await.assert_status(StatusCode::CREATED);
+    assert_eq!(names(&app.get("/get_locations").await.json()), [json!(true)]);
+}
+
+#[tokio::test]
+async fn answers_conditional_requests_with_not_modified() {
+    let app = app_with(ResponseCache::new(1024 * 1024, Duration::from_secs(3600)));
+    let response = app.get(YEARLY).await;
+    response.assert_status(StatusCode::OK);
+    let etag = response.headers[header::ETAG].to_str().unwrap().to_string();
+    let last_modified = response.headers[header::LAST_MODIFIED].to_str().unwrap().to_string();
+    assert_eq!(response.headers[header::CACHE_CONTROL], "no-cache");
+
+    let response = get_with(&app, YEARLY, header::IF_NONE_MATCH, &format!("\"other\", W/{}", etag)).await;
+    response.assert_status(StatusCode::NOT_MODIFIED);
+    assert_eq!(response.headers[header::ETAG], etag.as_str());
+    assert!(response.body.is_empty());
+
+    let response = get_with(&app, YEARLY, header::IF_MODIFIED_SINCE, &last_modified).await;
+    response.assert_status(StatusCode::NOT_MODIFIED);
+
+    // New data, new tag
+    app.day("Oakland", date(2023, 1, 2), json!([{"PRCP": 50}]));
+    let response = get_with(&app, YEARLY, header::IF_NONE_MATCH, &etag).await;
+    response.assert_status(StatusCode::OK);
+    assert_ne!(response.headers[header::ETAG], etag.as_str());
+
+    // Errors are neither cached nor tagged
+    let response = app.get("/get_yearly_precipitation?location=Nowhere&samples=5").await;
+    response.assert_status(StatusCode::NOT_FOUND);
+    assert!(!response.headers.contains_key(header::ETAG));
+}
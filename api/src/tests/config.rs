@@ -0,0 +1,93 @@
+use clap::Parser;
+use std::path::PathBuf;
+
+use crate::config::{Config, ConfigArgs, LogFormat, SslMode};
+
+#[derive(Parser)]
+struct Cli {
+    #[command(flatten)]
+    config: ConfigArgs,
+}
+
+fn load(args: &[&str]) -> anyhow::Result<Config> {
+    let cli = Cli::try_parse_from(std::iter::once("api").chain(args.iter().copied()))?;
+    Config::load(&cli.config)
+}
+
+/// Write a configuration file named after the test, so tests running at once do not share one.
+fn file(name: &str, contents: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("api-{}-{}.toml", std::process::id(), name));
+    std::fs::write(&path, contents).unwrap();
+    path
+}
+
+#[test]
+fn layers_flags_over_the_file_over_defaults() {
+    let path = file(
+        "layers",
+        r#"
+        [database]
+        host = "db.internal"
+        [database.pool]
+        max_size = 4
+        [server]
+        port = 8080
+        cors_origins = []
+        [log]
+        format = "json"
+        "#,
+    );
+    let config = load(&["--config", path.to_str().unwrap(), "--db-host", "override", "--port", "9090"]).unwrap();
+
+    assert_eq!(config.database.host.as_deref(), Some("override"));
+    assert_eq!((config.database.pool.max_size, config.database.pool.wait_timeout_secs), (4, 5));
+    assert_eq!(config.server.port, 9090);
+    assert!(config.server.cors_origins.is_empty());
+    assert_eq!(config.log.format, LogFormat::Json);
+    assert_eq!(config.database.tls.mode, SslMode::Disable);
+}
+
+#[test]
+fn reports_every_problem_at_once() {
+    let path = file("problems", "[database]\nhost = \"db\"\n[database.pool]\nmax_size = 0\n");
+    let flags = ["--cors-origin", "example.com/", "--rate-limit", "0", "--request-timeout", "0"];
+    let error = load(&[&["--config", path.to_str().unwrap()][..], &flags].concat()).unwrap_err().to_string();
+    assert!(error.contains("database.pool.max_size: must be at least 1"), "{}", error);
+    assert!(error.contains(r#"server.cors_origins: "example.com/" is not an origin"#), "{}", error);
+    assert!(error.contains("auth.rate_limit: must be at least 1"), "{}", error);
+    assert!(error.contains("server.request_timeout_secs: must be at least 1"), "{}", error);
+
+    // Misspelt settings are not ignored
+    let path = file("unknown", "[server]\nprot = 80\n");
+    let error = format!("{:#}", load(&["--config", path.to_str().unwrap()]).unwrap_err());
+    assert!(error.contains("unknown field `prot`"), "{}", error);
+}
+
+#[test]
+fn accepts_the_example_configuration() {
+    let path = concat!(env!("CARGO_MANIFEST_DIR"), "/api.example.toml");
+    let config = load(&["--config", path]).unwrap();
+    let defaults = Config::default();
+    assert_eq!(config.server.cors_origins, defaults.server.cors_origins);
+    assert_eq!(config.cache.size_mb, defaults.cache.size_mb);
+}
+
+#[test]
+fn reports_unusable_certificates() {
+    let certs = concat!(env!("CARGO_MANIFEST_DIR"), "/src/tests/certs");
+    let not_pem = concat!(env!("CARGO_MANIFEST_DIR"), "/api.example.toml");
+    let error = load(&["--db-host", "db", "--db-sslmode", "verify-full", "--db-ssl-root-cert", not_pem])
+        .unwrap_err()
+        .to_string();
+    assert!(error.contains("database.tls.ca_file: ") && error.contains("holds no PEM certificates"), "{}", error);
+
+    let (cert, key) = (format!("{}/client.pem", certs), format!("{}/server.key", certs));
+    let error = load(&["--db-host", "db", "--db-sslmode", "require", "--db-ssl-cert", &cert, "--db-ssl-key", &key])
+        .unwrap_err()
+        .to_string();
+    assert!(error.contains("cert_file and key_file do not match"), "{}", error);
+
+    let ca = format!("{}/ca.pem", certs);
+    let config = load(&["--db-host", "db", "--db-sslmode", "verify-full", "--db-ssl-root-cert", &ca]).unwrap();
+    assert_eq!(config.database.tls.mode, SslMode::VerifyFull);
+}
@@ -0,0 +1,138 @@
+use axum::body::Body;
+use axum::http::{header, Method, Request, StatusCode};
+use serde_json::{json, Value};
+use std::time::Duration;
+
+use super::{date, TestApp};
+use crate::models::DailyObservationsResponse;
+
+/// Assert an RFC 7807 body and return it.
+#[track_caller]
+fn problem(response: &super::TestResponse, status: StatusCode, code: &str) -> Value {
+    response.assert_status(status);
+    assert_eq!(response.headers[header::CONTENT_TYPE], "application/problem+json");
+    let body: Value = response.json();
+    assert_eq!(body["type"], "about:blank");
+    assert_eq!(body["title"], status.canonical_reason().unwrap());
+    assert_eq!(body["status"], status.as_u16());
+    assert_eq!(body["code"], code, "{}", body);
+    assert_eq!(body["request_id"], response.headers["x-request-id"].to_str().unwrap());
+    body
+}
+
+#[tokio::test]
+async fn reports_validation_errors_with_the_parameter() {
+    let app = TestApp::new();
+    let response = app
+        .get("/get_average_temp_by_date?location=Oakland&month=13&day=4&samples=5")
+        .await;
+    let body = problem(&response, StatusCode::BAD_REQUEST, "invalid_parameter");
+    assert_eq!(body["detail"], "Month must be between 1 and 12");
+    assert_eq!(body["parameter"], "month");
+
+    let response = app.get("/get_yearly_precipitation?location=Oakland").await;
+    let body = problem(&response, StatusCode::BAD_REQUEST, "missing_parameter");
+    assert_eq!(body["parameter"], "samples");
+
+    let response = app.get("/get_locations?latitude=37.8").await;
+    let body = problem(&response, StatusCode::BAD_REQUEST, "missing_parameter");
+    assert_eq!(body["parameter"], "longitude");
+}
+
+#[tokio::test]
+async fn names_parameters_the_query_string_cannot_hold() {
+    let app = TestApp::new();
+    for (query, code, parameter) in [
+        ("month=7&day=4&samples=5", "missing_parameter", "location"),
+        ("location=Oakland&month=July&day=4&samples=5", "invalid_parameter", "month"),
+        ("location=Oakland&month=7&day=4&samples=5&units=kelvin", "invalid_parameter", "units"),
+        ("location=Oakland&month=7&day=4&samples=5&as_of=yesterday", "invalid_parameter", "as_of"),
+    ] {
+        let response = app.get(&format!("/get_average_temp_by_date?{}", query)).await;
+        let body = problem(&response, StatusCode::BAD_REQUEST, code);
+        assert_eq!(body["parameter"], parameter, "{}", query);
+    }
+}
+
+#[tokio::test]
+async fn reports_body_errors() {
+    let app = TestApp::new();
+    let response = app
+        .post("/admin/locations", Some(json!({"location": "Oakland", "display_name": "Oakland"})))
+        .await;
+    let body = problem(&response, StatusCode::UNPROCESSABLE_ENTITY, "missing_parameter");
+    assert!(body["parameter"].is_string());
+
+    let response = app
+        .send(Method::PUT, "/admin/locations/Oakland", Some(json!({"display_name": "Oakland", "latitude": "north"})))
+        .await;
+    let body = problem(&response, StatusCode::UNPROCESSABLE_ENTITY, "invalid_parameter");
+    assert_eq!(body["parameter"], "latitude");
+
+    let request = Request::post("/admin/locations")
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from("{\"location\":"))
+        .unwrap();
+    problem(&app.request(request).await, StatusCode::BAD_REQUEST, "invalid_body");
+
+    let request = Request::post("/admin/locations").body(Body::from("location=Oakland")).unwrap();
+    problem(&app.request(request).await, StatusCode::UNSUPPORTED_MEDIA_TYPE, "unsupported_media_type");
+}
+
+#[tokio::test]
+async fn reports_unknown_routes_and_methods() {
+    let app = TestApp::new();
+    let body = problem(&app.get("/get_weather").await, StatusCode::NOT_FOUND, "route_not_found");
+    assert_eq!(body["detail"], "No route for /get_weather");
+
+    let response = app.get("/compute_normals?location=Oakland").await;
+    problem(&response, StatusCode::METHOD_NOT_ALLOWED, "method_not_allowed");
+    assert_eq!(response.headers[header::ALLOW], "POST");
+}
+
+#[tokio::test]
+async fn echoes_the_client_request_id() {
+    let app = TestApp::new();
+    let request = Request::get("/admin/locations/Nowhere")
+        .header("x-request-id", "client-chosen-id")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.request(request).await;
+    let body = problem(&response, StatusCode::NOT_FOUND, "location_not_found");
+    assert_eq!(body["request_id"], "client-chosen-id");
+
+    // Successful responses carry an ID too
+    let response = app.get("/get_locations").await;
+    response.assert_status(StatusCode::OK);
+    assert!(!response.headers["x-request-id"].is_empty());
+}
+
+#[tokio::test]
+async fn counts_corrupt_rows() {
+    let app = TestApp::new();
+    app.day("Oakland", date(2024, 3, 1), json!([{"TMAX": 200}]))
+        .day("Oakland", date(2024, 3, 2), json!("TMAX=210"))
+        .day("Oakland", date(2024, 3, 3), json!([{"TMAX": 220}, 42]))
+        .day("Oakland", date(2024, 3, 4), json!({"TMAX": 230}));
+
+    let response: DailyObservationsResponse = app
+        .get("/get_daily_observations?location=Oakland&start_date=2024-03-01&end_date=2024-03-31")
+        .await
+        .json();
+    assert_eq!(response.total, 4);
+    assert_eq!(response.rows_corrupt, 2);
+    let dates: Vec<String> = response.observations.iter().map(|o| o.date.to_string()).collect();
+    assert_eq!(dates, ["2024-03-01", "2024-03-04"]);
+}
+
+#[tokio::test]
+async fn times_out_slow_requests() {
+    let app = TestApp::with_request_timeout(1);
+    app.repo.delay_ping(Duration::from_secs(5));
+    let response = app.get("/readyz").await;
+    let body = problem(&response, StatusCode::GATEWAY_TIMEOUT, "timeout");
+    assert_eq!(body["detail"], "The request did not complete within 1 s");
+
+    app.repo.delay_ping(Duration::ZERO);
+    app.get("/readyz").await.assert_status(StatusCode::OK);
+}
@@ -0,0 +1,141 @@
+use arrow_array::cast::AsArray;
+use arrow_array::types::{Date32Type, Float64Type};
+use arrow_schema::DataType;
+use axum::body::Body;
+use axum::http::{header, Request, StatusCode};
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use serde_json::{json, Value};
+
+use super::{date, TestApp};
+
+const OBSERVATIONS: &str = "/get_daily_observations?location=Oakland&start_date=2023-01-01&end_date=2024-12-31";
+
+/// 400 days of TMAX and PRCP, more than a page of JSON.
+fn app() -> TestApp {
+    let app = TestApp::new();
+    for (i, day) in date(2023, 6, 1).iter_days().take(400).enumerate() {
+        app.day("Oakland", day, json!([{"TMAX": 200 + i as i64 % 50}, {"PRCP": 3, "mflag": "T", "sflag": "7"}]));
+    }
+    app
+}
+
+async fn get_with_accept(app: &TestApp, uri: &str, accept: &str) -> super::TestResponse {
+    let request = Request::get(uri).header(header::ACCEPT, accept).body(Body::empty()).unwrap();
+    app.request(request).await
+}
+
+#[tokio::test]
+async fn exports_every_observation_as_csv() {
+    let app = app();
+    let response = app.get(&format!("{}&format=csv", OBSERVATIONS)).await;
+    response.assert_status(StatusCode::OK);
+    assert_eq!(response.headers[header::CONTENT_TYPE], "text/csv; charset=utf-8");
+    assert_eq!(
+        response.headers[header::CONTENT_DISPOSITION],
+        "attachment; filename=\"daily_observations.csv\""
+    );
+
+    // One row per observation, not limited to a page
+    let lines: Vec<&str> = response.body.lines().collect();
+    assert_eq!(lines[0], "date,station_id,element,value,unit,mflag,qflag,sflag");
+    assert_eq!(lines.len(), 1 + 800);
+    assert_eq!(lines[1], "2023-06-01,USW00023230,TMAX,20,°C,,,");
+    assert_eq!(lines[2], "2023-06-01,USW00023230,PRCP,0.3,mm,T,,7");
+}
+
+#[tokio::test]
+async fn pages_exports_when_asked_to() {
+    let app = app();
+    let response = app
+        .get(&format!("{}&format=csv&elements=PRCP&units=imperial&limit=2&offset=1", OBSERVATIONS))
+        .await;
+    response.assert_status(StatusCode::OK);
+    let lines: Vec<&str> = response.body.lines().skip(1).collect();
+    assert_eq!(lines.len(), 2);
+    assert!(lines[0].starts_with("2023-06-02,USW00023230,PRCP,0.0118"), "{}", lines[0]);
+    assert!(lines[0].contains(",in,T,,7"));
+
+    let error: Value = app.get(&format!("{}&format=csv&limit=0", OBSERVATIONS)).await.json();
+    assert_eq!(error["parameter"], "limit");
+}
+
+#[tokio::test]
+async fn negotiates_ndjson_from_the_accept_header() {
+    let app = app();
+    let uri = "/get_average_temp_by_date?location=Oakland&month=6&day=1&start_year=2023&end_year=2024";
+    let response = get_with_accept(&app, uri, "application/x-ndjson").await;
+    response.assert_status(StatusCode::OK);
+    assert_eq!(response.headers[header::CONTENT_TYPE], "application/x-ndjson");
+    assert_eq!(response.headers[header::VARY], "accept");
+
+    let rows: Vec<Value> = response.body.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+    assert_eq!(
+        rows,
+        [
+            json!({"element": "TMAX", "year": 2023, "value": 20.0}),
+            json!({"element": "TMAX", "year": 2024, "value": 21.6}),
+        ]
+    );
+    // Keys in column order
+    assert!(response.body.starts_with(r#"{"element":"TMAX","year":2023,"value":20.0}"#));
+
+    // The query parameter wins over the header
+    let response = get_with_accept(&app, &format!("{}&format=json", uri), "application/x-ndjson").await;
+    assert_eq!(response.headers[header::CONTENT_TYPE], "application/json");
+}
+
+#[tokio::test]
+async fn exports_parquet_with_typed_columns() {
+    let app = app();
+    let response = get_with_accept(&app, &format!("{}&elements=TMAX", OBSERVATIONS), "application/vnd.apache.parquet").await;
+    response.assert_status(StatusCode::OK);
+    assert_eq!(response.headers[header::CONTENT_TYPE], "application/vnd.apache.parquet");
+
+    let reader = ParquetRecordBatchReaderBuilder::try_new(response.bytes.clone()).unwrap();
+    let schema = reader.schema().clone();
+    assert_eq!(schema.field_with_name("date").unwrap().data_type(), &DataType::Date32);
+    assert_eq!(schema.field_with_name("value").unwrap().data_type(), &DataType::Float64);
+
+    let batches: Vec<_> = reader.build().unwrap().collect::<Result<_, _>>().unwrap();
+    assert_eq!(batches.iter().map(|b| b.num_rows()).sum::<usize>(), 400);
+    let first = &batches[0];
+    let dates = first.column_by_name("date").unwrap().as_primitive::<Date32Type>();
+    assert_eq!(dates.value_as_date(0), Some(date(2023, 6, 1)));
+    let values = first.column_by_name("value").unwrap().as_primitive::<Float64Type>();
+    assert_eq!(values.value(1), 20.1);
+    assert!(first.column_by_name("qflag").unwrap().is_null(0));
+}
+
+#[tokio::test]
+async fn exports_aggregates_as_tables() {
+    let app = app();
+    let response = app
+        .get("/get_yearly_precipitation?location=Oakland&start_year=2023&end_year=2024&format=csv")
+        .await;
+    response.assert_status(StatusCode::OK);
+    assert_eq!(response.body, "year,precipitation\n2023,64.2\n2024,55.8\n");
+
+    let response = app.get("/get_locations?format=csv").await;
+    assert_eq!(response.body.lines().next(), Some("location,registered,display_name,latitude,longitude,elevation,country,timezone,station_ids,distance_km"));
+    assert_eq!(response.body.lines().nth(1), Some("Oakland,false,,,,,,,,"));
+}
+
+#[tokio::test]
+async fn rejects_formats_it_cannot_produce() {
+    let app = app();
+    let response = app.get(&format!("{}&format=xml", OBSERVATIONS)).await;
+    response.assert_status(StatusCode::BAD_REQUEST);
+    let error: Value = response.json();
+    assert_eq!(error["code"], "invalid_parameter");
+    assert_eq!(error["parameter"], "format");
+
+    let response = get_with_accept(&app, OBSERVATIONS, "application/xml").await;
+    response.assert_status(StatusCode::NOT_ACCEPTABLE);
+    assert_eq!(response.json::<Value>()["code"], "not_acceptable");
+
+    // Browsers get JSON; q=0 rules a type out
+    let response = get_with_accept(&app, OBSERVATIONS, "text/html,application/xhtml+xml,*/*;q=0.8").await;
+    assert_eq!(response.headers[header::CONTENT_TYPE], "application/json");
+    let response = get_with_accept(&app, OBSERVATIONS, "text/csv;q=0, application/x-ndjson;q=0.5").await;
+    assert_eq!(response.headers[header::CONTENT_TYPE], "application/x-ndjson");
+}
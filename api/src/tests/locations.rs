@@ -0,0 +1,157 @@
+use axum::http::{Method, StatusCode};
+use serde_json::{json, Value};
+
+use super::{date, TestApp};
+use crate::models::Location;
+
+fn oakland() -> Value {
+    json!({
+        "location": "Oakland",
+        "display_name": "Oakland, California",
+        "latitude": 37.8044,
+        "longitude": -122.2712,
+        "elevation": 13.0,
+        "country": "us",
+        "timezone": "America/Los_Angeles",
+        "station_ids": ["USW00023230"],
+    })
+}
+
+#[tokio::test]
+async fn lists_registered_then_unregistered_locations() {
+    let app = TestApp::new();
+    let empty: Vec<Location> = app.get("/get_locations").await.json();
+    assert!(empty.is_empty());
+
+    app.day("Berkeley", date(2024, 1, 1), json!([{"TMAX": 150}]))
+        .day("Oakland", date(2024, 1, 2), json!([{"TMAX": 160}]))
+        .day("Albany", date(2024, 1, 3), json!([{"TMAX": 170}]));
+    app.post("/admin/locations", Some(oakland()))
+        .await
+        .assert_status(StatusCode::CREATED);
+
+    let locations: Vec<Location> = app.get("/get_locations").await.json();
+    let names: Vec<_> = locations.iter().map(|l| (l.location.as_str(), l.registered)).collect();
+    assert_eq!(names, [("Oakland", true), ("Albany", false), ("Berkeley", false)]);
+    assert_eq!(locations[0].timezone.as_deref(), Some("America/Los_Angeles"));
+    assert!(locations[1].latitude.is_none());
+}
+
+#[tokio::test]
+async fn sorts_registered_locations_by_distance() {
+    let app = TestApp::new();
+    app.post("/admin/locations", Some(oakland())).await;
+    let mut sacramento = oakland();
+    sacramento["location"] = json!("Sacramento");
+    sacramento["latitude"] = json!(38.58);
+    sacramento["longitude"] = json!(-121.49);
+    app.post("/admin/locations", Some(sacramento)).await;
+    app.day("Unplaced", date(2024, 1, 1), json!([{"TMAX": 150}]));
+
+    let body: Vec<Value> = app.get("/get_locations?latitude=38.5&longitude=-121.5").await.json();
+    let names: Vec<_> = body.iter().map(|l| l["location"].as_str().unwrap()).collect();
+    assert_eq!(names, ["Sacramento", "Oakland", "Unplaced"]);
+    assert!(body[0]["distance_km"].as_f64().unwrap() < 10.0);
+    assert!(body[2].get("distance_km").is_none());
+
+    // Without a point no distance is reported
+    let body: Vec<Value> = app.get("/get_locations").await.json();
+    assert!(body[0].get("distance_km").is_none());
+}
+
+#[tokio::test]
+async fn rejects_invalid_points() {
+    let app = TestApp::new();
+    for (query, message) in [
+        ("latitude=37.8", "latitude and longitude must be given together"),
+        ("longitude=-122.3", "latitude and longitude must be given together"),
+        ("latitude=91&longitude=0", "Latitude must be between -90 and 90"),
+        ("latitude=0&longitude=-180.5", "Longitude must be between -180 and 180"),
+    ] {
+        let response = app.get(&format!("/get_locations?{}", query)).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        assert!(response.body.contains(message), "{}: {}", query, response.body);
+    }
+}
+
+#[tokio::test]
+async fn creates_reads_updates_and_deletes_locations() {
+    let app = TestApp::new();
+    let response = app.post("/admin/locations", Some(oakland())).await;
+    response.assert_status(StatusCode::CREATED);
+    let created: Location = response.json();
+    assert!(created.registered);
+    assert_eq!(created.country.as_deref(), Some("US"));
+
+    let response = app.post("/admin/locations", Some(oakland())).await;
+    response.assert_status(StatusCode::CONFLICT);
+    assert!(response.body.contains("Location Oakland already exists"));
+
+    let fetched: Location = app.get("/admin/locations/Oakland").await.json();
+    assert_eq!(fetched.station_ids, ["USW00023230"]);
+
+    let mut details = oakland();
+    details.as_object_mut().unwrap().remove("location");
+    details["display_name"] = json!("Oakland, CA");
+    details.as_object_mut().unwrap().remove("station_ids");
+    let response = app.send(Method::PUT, "/admin/locations/Oakland", Some(details.clone())).await;
+    response.assert_status(StatusCode::OK);
+    let updated: Location = response.json();
+    assert_eq!(updated.display_name.as_deref(), Some("Oakland, CA"));
+    assert!(updated.station_ids.is_empty());
+
+    app.send(Method::DELETE, "/admin/locations/Oakland", None)
+        .await
+        .assert_status(StatusCode::NO_CONTENT);
+    for response in [
+        app.get("/admin/locations/Oakland").await,
+        app.send(Method::PUT, "/admin/locations/Oakland", Some(details)).await,
+        app.send(Method::DELETE, "/admin/locations/Oakland", None).await,
+    ] {
+        response.assert_status(StatusCode::NOT_FOUND);
+        assert!(response.body.contains("Location Oakland is not registered"));
+    }
+}
+
+#[tokio::test]
+async fn serves_names_with_spaces() {
+    let app = TestApp::new();
+    let mut newport = oakland();
+    newport["location"] = json!("Newport Beach");
+    app.post("/admin/locations", Some(newport))
+        .await
+        .assert_status(StatusCode::CREATED);
+    let fetched: Location = app.get("/admin/locations/Newport%20Beach").await.json();
+    assert_eq!(fetched.location, "Newport Beach");
+}
+
+#[tokio::test]
+async fn rejects_invalid_location_details() {
+    let app = TestApp::new();
+    for (field, value, message) in [
+        ("location", json!(" "), "location must not be empty"),
+        ("display_name", json!(""), "display_name must not be empty"),
+        ("latitude", json!(-91.0), "Latitude must be between -90 and 90"),
+        ("longitude", json!(181.0), "Longitude must be between -180 and 180"),
+        ("timezone", json!("Pacific Time"), "Unknown IANA time zone: Pacific Time"),
+        ("country", json!("USA"), "Invalid country code: USA"),
+        ("station_ids", json!(["USW0002323"]), "Invalid GHCN station ID: USW0002323"),
+    ] {
+        let mut body = oakland();
+        body[field] = value;
+        let response = app.post("/admin/locations", Some(body)).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        assert!(response.body.contains(message), "{}: {}", field, response.body);
+    }
+
+    // Missing fields and malformed JSON are rejected by the JSON extractor
+    let mut body = oakland();
+    body.as_object_mut().unwrap().remove("timezone");
+    app.post("/admin/locations", Some(body))
+        .await
+        .assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+    app.send(Method::PUT, "/admin/locations/Oakland", Some(json!("Oakland")))
+        .await
+        .assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+    app.get("/admin/locations/Oakland").await.assert_status(StatusCode::NOT_FOUND);
+}
@@ -0,0 +1,37 @@
+use axum::http::{header, StatusCode};
+
+use super::TestApp;
+
+#[tokio::test]
+async fn serves_probes_and_metrics_without_a_key() {
+    let app = TestApp::with_auth(600, 60);
+    for uri in ["/healthz", "/readyz"] {
+        let response = app.get(uri).await;
+        response.assert_status(StatusCode::OK);
+        assert_eq!(response.body, "ok");
+    }
+
+    let response = app.get("/metrics").await;
+    response.assert_status(StatusCode::OK);
+    assert!(response.headers[header::CONTENT_TYPE].to_str().unwrap().starts_with("text/plain"));
+}
+
+#[tokio::test]
+async fn records_request_latency_by_route() {
+    let app = TestApp::new();
+    app.get("/admin/locations/Oakland").await.assert_status(StatusCode::NOT_FOUND);
+    app.get("/admin/locations/Berkeley").await.assert_status(StatusCode::NOT_FOUND);
+    app.get("/nowhere").await.assert_status(StatusCode::NOT_FOUND);
+
+    // Path parameters and unknown paths do not make series of their own
+    let body = app.get("/metrics").await.body;
+    let series = |labels: &str| {
+        body.lines()
+            .find(|line| line.starts_with(&format!("http_request_duration_seconds_count{{{}}}", labels)))
+            .and_then(|line| line.rsplit(' ').next())
+            .and_then(|count| count.parse::<u64>().ok())
+    };
+    assert!(series(r#"method="GET",route="/admin/locations/:location",status="404""#) >= Some(2));
+    assert!(series(r#"method="GET",route="unmatched",status="404""#) >= Some(1));
+    assert!(!body.contains("Oakland"));
+}
@@ -0,0 +1,159 @@
+//! Request-level tests of every route, served by [`crate::router`] from a
+//! [`MemoryRepository`] seeded per test.
+
+mod auth;
+mod cache;
+mod config;
+mod errors;
+mod exports;
+mod locations;
+mod metrics;
+mod normals;
+mod observations;
+mod openapi;
+mod precipitation;
+mod stations;
+mod temperature;
+mod tls;
+
+use axum::body::{to_bytes, Body, Bytes};
+use axum::http::{header, HeaderMap, Method, Request, StatusCode};
+use axum::Router;
+use chrono::NaiveDate;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::time::Duration;
+use tower::ServiceExt;
+
+use crate::auth::{create_key, Role};
+use crate::cache::ResponseCache;
+use crate::config::{AuthConfig, Config, ServerConfig};
+use crate::models::NewApiKey;
+use crate::repository::MemoryRepository;
+
+/// Largest response body read by the tests.
+const MAX_BODY_BYTES: usize = 16 * 1024 * 1024;
+
+pub struct TestApp {
+    pub repo: MemoryRepository,
+    pub cache: ResponseCache,
+    router: Router,
+}
+
+pub struct TestResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    /// The body as text, with invalid UTF-8 replaced
+    pub body: String,
+    pub bytes: Bytes,
+}
+
+impl TestResponse {
+    /// Parse the body, failing the test with the body when it is not the expected JSON.
+    pub fn json<T: DeserializeOwned>(&self) -> T {
+        serde_json::from_str(&self.body)
+            .unwrap_or_else(|e| panic!("unexpected body ({}, {}): {}", self.status, e, self.body))
+    }
+
+    /// Assert the status, showing the body when it differs.
+    #[track_caller]
+    pub fn assert_status(&self, status: StatusCode) -> &Self {
+        assert_eq!(self.status, status, "body: {}", self.body);
+        self
+    }
+}
+
+impl TestApp {
+    /// An app with the default cache and without authentication.
+    pub fn new() -> Self {
+        Self::with_cache(Self::default_cache())
+    }
+
+    pub fn with_cache(cache: ResponseCache) -> Self {
+        Self::build(cache, Config { auth: Self::no_auth(), ..Config::default() })
+    }
+
+    /// An app requiring API keys, see [`TestApp::api_key`].
+    pub fn with_auth(rate_limit: u32, rate_limit_burst: u32) -> Self {
+        let auth = AuthConfig { disabled: false, rate_limit, rate_limit_burst };
+        Self::build(Self::default_cache(), Config { auth, ..Config::default() })
+    }
+
+    /// An app failing requests that take longer than `secs` seconds.
+    pub fn with_request_timeout(secs: u64) -> Self {
+        let server = ServerConfig { request_timeout_secs: secs, ..ServerConfig::default() };
+        Self::build(Self::default_cache(), Config { server, auth: Self::no_auth(), ..Config::default() })
+    }
+
+    /// Authentication of the apps that do not test it.
+    fn no_auth() -> AuthConfig {
+        AuthConfig { disabled: true, ..AuthConfig::default() }
+    }
+
+    fn default_cache() -> ResponseCache {
+        ResponseCache::new(16 * 1024 * 1024, Duration::from_secs(3600))
+    }
+
+    fn build(cache: ResponseCache, config: Config) -> Self {
+        let repo = MemoryRepository::new();
+        TestApp {
+            router: crate::router(repo.clone(), cache.clone(), &config),
+            repo,
+            cache,
+        }
+    }
+
+    /// Create a key with the default rate limit and return it.
+    pub async fn api_key(&self, name: &str, role: Role) -> String {
+        let new_key = NewApiKey { name: name.to_string(), role, rate_limit: None };
+        create_key(&self.repo, &new_key).await.unwrap().unwrap().key
+    }
+
+    /// Store a `daily` row under `location` for the default test station, and
+    /// drop the cached responses of the location as `api ingest` would.
+    pub fn day(&self, location: &str, date: NaiveDate, data: Value) -> &Self {
+        self.repo.insert_day(location, STATION_ID, date, data);
+        self.cache.invalidate(location);
+        self
+    }
+
+    pub async fn get(&self, uri: &str) -> TestResponse {
+        self.send(Method::GET, uri, None).await
+    }
+
+    pub async fn post(&self, uri: &str, body: Option<Value>) -> TestResponse {
+        self.send(Method::POST, uri, body).await
+    }
+
+    pub async fn send(&self, method: Method, uri: &str, body: Option<Value>) -> TestResponse {
+        let request = Request::builder().method(method).uri(uri);
+        let request = match body {
+            Some(body) => request
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string())),
+            None => request.body(Body::empty()),
+        }
+        .unwrap();
+        self.request(request).await
+    }
+
+    pub async fn request(&self, request: Request<Body>) -> TestResponse {
+        let response = self.router.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let headers = response.headers().clone();
+        let bytes = to_bytes(response.into_body(), MAX_BODY_BYTES).await.unwrap();
+        TestResponse {
+            status,
+            headers,
+            body: String::from_utf8_lossy(&bytes).into_owned(),
+            bytes,
+        }
+    }
+}
+
+/// Station the rows of [`TestApp::day`] are stored under.
+pub const STATION_ID: &str = "USW00023230";
+
+pub fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+    NaiveDate::from_ymd_opt(year, month, day).unwrap()
+}
@@ -0,0 +1,304 @@
+use axum::http::StatusCode;
+use chrono::Datelike;
+use serde_json::json;
+
+use super::{date, TestApp};
+use crate::models::{AnomalyResponse, ComputeNormalsResponse, NormalsResponse};
+use crate::normals::Smoothing;
+use crate::observations::Qc;
+
+const BASE: &str = "base_start_year=2001&base_end_year=2010";
+
+/// Ten years of constant TMAX 20 °C, TMIN 10 °C and 1 mm of rain a day, in
+/// alternating storage shapes.
+fn app() -> TestApp {
+    let app = TestApp::new();
+    for year in 2001..=2010 {
+        for day in date(year, 1, 1).iter_days().take_while(|d| d.year() == year) {
+            let data = if day.day() % 2 == 0 {
+                json!([{"TMAX": 200}, {"TMIN": "100"}, {"PRCP": 10}])
+            } else {
+                json!({"TMAX": "200", "TMIN": 100, "PRCP": "10"})
+            };
+            app.day("Oakland", day, data);
+        }
+    }
+    app
+}
+
+#[tokio::test]
+async fn computes_stores_and_serves_normals() {
+    let app = app();
+    let response = app.post(&format!("/compute_normals?location=Oakland&{}&method=window", BASE), None).await;
+    response.assert_status(StatusCode::OK);
+    let computed: ComputeNormalsResponse = response.json();
+    assert_eq!(computed.method, Smoothing::Window);
+    let elements: Vec<_> = computed
+        .elements
+        .iter()
+        .map(|e| (e.element.as_str(), e.years, e.days_with_data))
+        .collect();
+    assert_eq!(elements, [("TMAX", 10, 366), ("TMIN", 10, 366), ("PRCP", 10, 366)]);
+
+    let normals: NormalsResponse = app
+        .get(&format!("/get_normals?location=Oakland&{}&year=2024", BASE))
+        .await
+        .json();
+    assert_eq!(normals.method, Smoothing::Window);
+    assert_eq!(normals.normals.len(), 366);
+    for normal in &normals.normals {
+        assert!((normal.tmax.unwrap() - 20.0).abs() < 1e-9, "{:?}", normal);
+        assert!((normal.tmin.unwrap() - 10.0).abs() < 1e-9, "{:?}", normal);
+        assert!((normal.prcp.unwrap() - 1.0).abs() < 1e-9, "{:?}", normal);
+    }
+    assert_eq!(normals.element_units["PRCP"], "mm");
+
+    let normals: NormalsResponse = app
+        .get(&format!("/get_normals?location=Oakland&{}&date=2023-03-01&units=imperial", BASE))
+        .await
+        .json();
+    assert_eq!(normals.normals.len(), 1);
+    assert_eq!(normals.normals[0].day_of_year, 61);
+    assert!((normals.normals[0].tmax.unwrap() - 68.0).abs() < 1e-9);
+}
+
+#[tokio::test]
+async fn leaves_out_elements_with_too_few_years() {
+    // TMAX for 10 years, TMIN for 9 and PRCP only as a string that is not a number
+    let app = TestApp::new();
+    for year in 2001..=2010 {
+        for day in date(year, 1, 1).iter_days().take_while(|d| d.year() == year) {
+            let data = if year == 2001 {
+                json!([{"TMAX": 200}])
+            } else {
+                json!([{"TMIN": 100}, {"TMAX": 200}, {"PRCP": "T"}])
+            };
+            app.day("Oakland", day, data);
+        }
+    }
+
+    let computed: ComputeNormalsResponse = app
+        .post(&format!("/compute_normals?location=Oakland&{}", BASE), None)
+        .await
+        .json();
+    let elements: Vec<_> = computed.elements.iter().map(|e| e.element.as_str()).collect();
+    assert_eq!(elements, ["TMAX"]);
+
+    let normals: NormalsResponse = app
+        .get(&format!("/get_normals?location=Oakland&{}&date=2020-01-01", BASE))
+        .await
+        .json();
+    assert!(normals.normals[0].tmax.is_some());
+    assert_eq!(normals.normals[0].tmin, None);
+}
+
+#[tokio::test]
+async fn counts_values_rejected_by_quality_control() {
+    let app = app();
+    app.day("Oakland", date(2005, 7, 4), json!([{"TMAX": 450, "qflag": "X"}, {"TMIN": 100}]));
+
+    let computed: ComputeNormalsResponse = app
+        .post(&format!("/compute_normals?location=Oakland&{}", BASE), None)
+        .await
+        .json();
+    assert_eq!(computed.values_dropped, 1);
+
+    let computed: ComputeNormalsResponse = app
+        .post(&format!("/compute_normals?location=Oakland&{}&qc=none", BASE), None)
+        .await
+        .json();
+    assert_eq!(computed.values_dropped, 0);
+}
+
+#[tokio::test]
+async fn stores_normals_per_quality_control_policy() {
+    let app = app();
+    app.day("Oakland", date(2005, 7, 4), json!([{"TMAX": 450, "qflag": "X"}]));
+    for qc in ["strict", "none"] {
+        app.post(&format!("/compute_normals?location=Oakland&{}&qc={}", BASE, qc), None)
+            .await
+            .assert_status(StatusCode::OK);
+    }
+
+    let uri = format!("/get_normals?location=Oakland&{}&date=2024-07-04", BASE);
+    let strict: NormalsResponse = app.get(&uri).await.json();
+    assert_eq!(strict.qc, Qc::Strict);
+    assert!((strict.normals[0].tmax.unwrap() - 20.0).abs() < 1e-9);
+
+    // The flagged value counts without QC, and did not replace the strict normals
+    let none: NormalsResponse = app.get(&format!("{}&qc=none", uri)).await.json();
+    assert_eq!(none.qc, Qc::None);
+    assert!(none.normals[0].tmax.unwrap() > 20.0);
+
+    let response = app.get(&format!("{}&qc=lenient", uri)).await;
+    response.assert_status(StatusCode::NOT_FOUND);
+    assert!(response.body.contains("qc=lenient"));
+}
+
+#[tokio::test]
+async fn reports_missing_normals() {
+    let app = app();
+
+    // Not enough years in the base period
+    let response = app
+        .post("/compute_normals?location=Oakland&base_start_year=2001&base_end_year=2005", None)
+        .await;
+    response.assert_status(StatusCode::NOT_FOUND);
+    assert!(response.body.contains("Not enough data to compute normals"));
+
+    let response = app.post("/compute_normals?location=Nowhere", None).await;
+    response.assert_status(StatusCode::NOT_FOUND);
+
+    // Not computed yet
+    let response = app.get(&format!("/get_normals?location=Oakland&{}&date=2020-01-01", BASE)).await;
+    response.assert_status(StatusCode::NOT_FOUND);
+    assert!(response.body.contains("POST /compute_normals"));
+}
+
+#[tokio::test]
+async fn rejects_invalid_normals_parameters() {
+    let app = app();
+    for (response, message) in [
+        (
+            app.post("/compute_normals?location=Oakland&base_start_year=2010&base_end_year=2001", None).await,
+            "base_start_year must not be after base_end_year",
+        ),
+        (
+            app.get("/get_normals?location=Oakland&base_start_year=2010&base_end_year=2001&year=2020").await,
+            "base_start_year must not be after base_end_year",
+        ),
+        (
+            app.get("/get_normals?location=Oakland&date=2020-01-01&year=2020").await,
+            "Exactly one of date or year must be provided",
+        ),
+        (app.get("/get_normals?location=Oakland").await, "Exactly one of date or year must be provided"),
+        (app.get("/get_normals?location=Oakland&year=300000").await, "Invalid year: 300000"),
+    ] {
+        response.assert_status(StatusCode::BAD_REQUEST);
+        assert!(response.body.contains(message), "{}", response.body);
+    }
+
+    app.post("/compute_normals?location=Oakland&method=spline", None)
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+    app.get("/compute_normals?location=Oakland")
+        .await
+        .assert_status(StatusCode::METHOD_NOT_ALLOWED);
+}
+
+#[tokio::test]
+async fn measures_departures_from_a_computed_baseline() {
+    let app = app();
+    app.day("Oakland", date(2024, 6, 1), json!([{"TMAX": 230}, {"TMIN": "90"}, {"PRCP": 30}]))
+        .day("Oakland", date(2024, 6, 2), json!({"TMAX": 170}))
+        .day("Oakland", date(2024, 6, 3), json!([{"TMAX": 990, "qflag": "X"}]));
+
+    let response: AnomalyResponse = app
+        .get(&format!("/get_anomalies?location=Oakland&start_date=2024-06-01&end_date=2024-06-30&{}", BASE))
+        .await
+        .json();
+    assert_eq!(serde_json::to_value(response.baseline).unwrap(), "computed");
+    assert_eq!(response.values_dropped, 1);
+    assert_eq!(response.days.len(), 3);
+    assert_eq!(response.summary.days_with_data, 2);
+
+    let first = response.days[0].tmax.as_ref().unwrap();
+    assert!((first.departure - 3.0).abs() < 1e-9);
+    assert_eq!(first.percentile_rank, Some(100.0));
+    let second = response.days[1].tmax.as_ref().unwrap();
+    assert!((second.cumulative_departure - 0.0).abs() < 1e-9);
+    assert!(response.days[2].tmax.is_none());
+
+    assert!((response.summary.tmax_departure.unwrap() - 0.0).abs() < 1e-9);
+    assert!((response.summary.tmin_departure.unwrap() + 1.0).abs() < 1e-9);
+    assert!((response.summary.prcp_percent_of_normal.unwrap() - 300.0).abs() < 1e-9);
+}
+
+#[tokio::test]
+async fn averages_the_stations_of_a_location() {
+    let app = app();
+    // A second station, 10 °C warmer throughout
+    for year in 2001..=2010 {
+        for day in date(year, 1, 1).iter_days().take_while(|d| d.year() == year) {
+            app.repo.insert_day("Oakland", "USC00046336", day, json!([{"TMAX": 300}]));
+        }
+    }
+    app.day("Oakland", date(2024, 6, 1), json!([{"TMAX": 230}]));
+    app.repo.insert_day("Oakland", "USC00046336", date(2024, 6, 1), json!([{"TMAX": 330}]));
+    let uri = format!("/get_anomalies?location=Oakland&start_date=2024-06-01&end_date=2024-06-01&{}", BASE);
+
+    let computed: AnomalyResponse = app.get(&uri).await.json();
+    let tmax = computed.days[0].tmax.as_ref().unwrap();
+    assert!((tmax.observed - 28.0).abs() < 1e-9);
+    assert!((tmax.normal - 25.0).abs() < 1e-9);
+    assert!((tmax.departure - 3.0).abs() < 1e-9);
+
+    // Stored normals average the same values
+    app.post(&format!("/compute_normals?location=Oakland&{}", BASE), None)
+        .await
+        .assert_status(StatusCode::OK);
+    let stored: AnomalyResponse = app.get(&uri).await.json();
+    assert_eq!(serde_json::to_value(stored.baseline).unwrap(), "stored");
+    assert!((stored.days[0].tmax.as_ref().unwrap().departure - 3.0).abs() < 1e-9);
+}
+
+#[tokio::test]
+async fn prefers_stored_normals() {
+    let app = app();
+    app.day("Oakland", date(2024, 6, 1), json!([{"TMAX": 230}]));
+    app.post(&format!("/compute_normals?location=Oakland&{}&method=window", BASE), None)
+        .await
+        .assert_status(StatusCode::OK);
+
+    let response: AnomalyResponse = app
+        .get(&format!("/get_anomalies?location=Oakland&start_date=2024-06-01&end_date=2024-06-01&{}", BASE))
+        .await
+        .json();
+    assert_eq!(serde_json::to_value(response.baseline).unwrap(), "stored");
+    assert_eq!(response.method, Smoothing::Window);
+
+    // Only normals of the request's QC policy
+    let response: AnomalyResponse = app
+        .get(&format!("/get_anomalies?location=Oakland&start_date=2024-06-01&end_date=2024-06-01&{}&qc=none", BASE))
+        .await
+        .json();
+    assert_eq!(serde_json::to_value(response.baseline).unwrap(), "computed");
+    assert_eq!(response.method, Smoothing::Harmonic);
+}
+
+#[tokio::test]
+async fn reports_missing_anomaly_data() {
+    let app = app();
+    let response = app
+        .get(&format!("/get_anomalies?location=Oakland&start_date=2024-06-01&end_date=2024-06-30&{}", BASE))
+        .await;
+    response.assert_status(StatusCode::NOT_FOUND);
+    assert!(response.body.contains("No observations found"));
+
+    app.day("Oakland", date(2024, 6, 1), json!([{"TMAX": 230}]));
+    let response = app
+        .get("/get_anomalies?location=Oakland&start_date=2024-06-01&end_date=2024-06-30&base_start_year=1991&base_end_year=2000")
+        .await;
+    response.assert_status(StatusCode::NOT_FOUND);
+    assert!(response.body.contains("Not enough data in 1991-2000"));
+}
+
+#[tokio::test]
+async fn rejects_invalid_anomaly_parameters() {
+    let app = app();
+    for (query, message) in [
+        ("start_date=2024-06-30&end_date=2024-06-01", "start_date must not be after end_date"),
+        ("start_date=2000-01-01&end_date=2024-06-01", "Date range must not exceed 3660 days"),
+        (
+            "start_date=2024-06-01&end_date=2024-06-30&base_start_year=2010&base_end_year=2001",
+            "base_start_year must not be after base_end_year",
+        ),
+    ] {
+        let response = app.get(&format!("/get_anomalies?location=Oakland&{}", query)).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        assert!(response.body.contains(message), "{}: {}", query, response.body);
+    }
+    app.get("/get_anomalies?location=Oakland&start_date=2024-06-01")
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+}
@@ -0,0 +1,114 @@
+use axum::http::StatusCode;
+use serde_json::{json, Value};
+
+use super::{date, TestApp};
+use crate::models::DailyObservationsResponse;
+
+fn app() -> TestApp {
+    let app = TestApp::new();
+    app.day("Oakland", date(2024, 3, 1), json!([{"TMAX": 200, "sflag": "W"}, {"PRCP": 5}]))
+        .day("Oakland", date(2024, 3, 2), json!({"TMAX": "210", "SNOW": 0}))
+        .day("Oakland", date(2024, 3, 3), json!([{"SNOW": 10}]))
+        .day("Oakland", date(2024, 3, 4), json!([{"TMAX": 990, "qflag": "X"}, {"AWND": 31}]))
+        .day("Oakland", date(2024, 4, 1), json!([{"TMAX": 250}]));
+    app.repo.insert_day("Oakland", "USC00046336", date(2024, 3, 1), json!([{"TMAX": 190}]));
+    app
+}
+
+#[tokio::test]
+async fn lists_rows_in_date_and_station_order() {
+    let app = app();
+    let response: DailyObservationsResponse = app
+        .get("/get_daily_observations?location=Oakland&start_date=2024-03-01&end_date=2024-03-31")
+        .await
+        .json();
+
+    assert_eq!(response.total, 5);
+    let rows: Vec<_> = response
+        .observations
+        .iter()
+        .map(|o| (o.date.to_string(), o.station_id.clone().unwrap()))
+        .collect();
+    assert_eq!(rows[0], ("2024-03-01".to_string(), "USC00046336".to_string()));
+    assert_eq!(rows[1], ("2024-03-01".to_string(), "USW00023230".to_string()));
+
+    // String values are converted, flags reported per element
+    let second = &response.observations[1];
+    assert_eq!(second.values["TMAX"], 20.0);
+    assert_eq!(second.flags["TMAX"].sflag, Some('W'));
+    assert!(!second.flags.contains_key("PRCP"));
+    assert_eq!(response.observations[2].values["TMAX"], 21.0);
+
+    // The QC-rejected TMAX is dropped, the rest of the day kept
+    let fourth = &response.observations[4];
+    assert!(!fourth.values.contains_key("TMAX"));
+    assert_eq!(fourth.values["AWND"], 3.1);
+    assert_eq!(response.values_dropped, 1);
+    assert_eq!(response.element_units["AWND"], "m/s");
+}
+
+#[tokio::test]
+async fn filters_elements_and_pages() {
+    let app = app();
+    let response: DailyObservationsResponse = app
+        .get("/get_daily_observations?location=Oakland&start_date=2024-03-01&end_date=2024-03-31&elements=tmax,TMAX&limit=2&offset=1")
+        .await
+        .json();
+
+    // Rows holding TMAX, whether or not it passes QC
+    assert_eq!(response.total, 4);
+    assert_eq!(response.elements.as_deref(), Some(&["TMAX".to_string()][..]));
+    assert_eq!((response.limit, response.offset), (2, 1));
+    let dates: Vec<String> = response.observations.iter().map(|o| o.date.to_string()).collect();
+    assert_eq!(dates, ["2024-03-01", "2024-03-02"]);
+    assert!(response.observations.iter().all(|o| o.values.keys().all(|k| k == "TMAX")));
+
+    let response: DailyObservationsResponse = app
+        .get("/get_daily_observations?location=Oakland&start_date=2024-03-01&end_date=2024-03-31&elements=SNOW&units=raw")
+        .await
+        .json();
+    assert_eq!(response.total, 2);
+    assert_eq!(response.observations[1].values["SNOW"], 10.0);
+}
+
+#[tokio::test]
+async fn returns_an_empty_page_without_data() {
+    let app = app();
+    let response = app
+        .get("/get_daily_observations?location=Oakland&start_date=2023-01-01&end_date=2023-12-31")
+        .await;
+    response.assert_status(StatusCode::OK);
+    let body: Value = response.json();
+    assert_eq!(body["total"], 0);
+    assert_eq!(body["observations"], json!([]));
+
+    let response: DailyObservationsResponse = app
+        .get("/get_daily_observations?location=Oakland&start_date=2024-03-01&end_date=2024-03-31&offset=100")
+        .await
+        .json();
+    assert_eq!(response.total, 5);
+    assert!(response.observations.is_empty());
+}
+
+#[tokio::test]
+async fn rejects_invalid_parameters() {
+    let app = app();
+    let base = "/get_daily_observations?location=Oakland";
+    for (query, message) in [
+        ("start_date=2024-03-31&end_date=2024-03-01", "start_date must not be after end_date"),
+        ("start_date=2024-03-01&end_date=2024-03-31&limit=0", "Limit must be between 1 and 10000"),
+        ("start_date=2024-03-01&end_date=2024-03-31&limit=10001", "Limit must be between 1 and 10000"),
+        ("start_date=2024-03-01&end_date=2024-03-31&elements=TMAX;DROP", "Invalid element code: TMAX;DROP"),
+        ("start_date=2024-03-01&end_date=2024-03-31&elements=,", "at least one element code"),
+    ] {
+        let response = app.get(&format!("{}&{}", base, query)).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        assert!(response.body.contains(message), "{}: {}", query, response.body);
+    }
+
+    for query in ["start_date=2024-03-01", "start_date=2024-02-30&end_date=2024-03-01"] {
+        app.get(&format!("{}&{}", base, query))
+            .await
+            .assert_status(StatusCode::BAD_REQUEST);
+    }
+}
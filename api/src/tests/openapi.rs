@@ -0,0 +1,105 @@
+use axum::http::{header, Method, StatusCode};
+use serde_json::Value;
+use utoipa::OpenApi;
+
+use super::TestApp;
+use crate::openapi::{mcp_tools, ApiDoc};
+
+#[tokio::test]
+async fn serves_a_document_covering_every_route() {
+    let app = TestApp::new();
+    let response = app.get("/openapi.json").await;
+    response.assert_status(StatusCode::OK);
+    let doc: Value = response.json();
+    assert_eq!(doc["openapi"], "3.1.0");
+
+    let mut operations = 0;
+    for (path, item) in doc["paths"].as_object().unwrap() {
+        for method in item.as_object().unwrap().keys() {
+            operations += 1;
+            let uri = path.replace("{location}", "Oakland").replace("{name}", "weather");
+            let method: Method = method.to_uppercase().parse().unwrap();
+            let response = app.send(method.clone(), &uri, None).await;
+            let body: Value = serde_json::from_str(&response.body).unwrap_or_default();
+            assert!(
+                !matches!(body["code"].as_str(), Some("route_not_found" | "method_not_allowed")),
+                "{} {} is documented but not routed",
+                method,
+                path
+            );
+        }
+    }
+    assert_eq!(operations, 16);
+
+    // Errors share the problem details response
+    let bad_request = &doc["components"]["responses"]["BadRequest"]["content"]["application/problem+json"];
+    assert_eq!(bad_request["schema"]["$ref"], "#/components/schemas/Problem");
+    assert_eq!(
+        doc["paths"]["/get_locations"]["get"]["responses"]["503"]["$ref"],
+        "#/components/responses/ServiceUnavailable"
+    );
+    assert_eq!(
+        doc["paths"]["/get_locations"]["get"]["responses"]["504"]["$ref"],
+        "#/components/responses/GatewayTimeout"
+    );
+
+    // Every operation takes a key; only admin operations refuse read keys
+    assert_eq!(doc["components"]["securitySchemes"]["api_key"]["scheme"], "bearer");
+    assert_eq!(doc["security"][0]["api_key"], serde_json::json!([]));
+    assert!(doc["paths"]["/get_locations"]["get"]["responses"].get("403").is_none());
+    assert_eq!(
+        doc["paths"]["/admin/keys"]["post"]["responses"]["403"]["$ref"],
+        "#/components/responses/Forbidden"
+    );
+}
+
+#[tokio::test]
+async fn serves_swagger_ui() {
+    let app = TestApp::new();
+    let response = app.get("/docs/").await;
+    response.assert_status(StatusCode::OK);
+    assert!(response.headers[header::CONTENT_TYPE].to_str().unwrap().starts_with("text/html"));
+}
+
+#[test]
+fn derives_mcp_tools_from_the_read_only_operations() {
+    let tools = mcp_tools(&ApiDoc::openapi());
+    let names: Vec<_> = tools.iter().map(|t| t.name.as_str()).collect();
+    assert_eq!(
+        names,
+        [
+            "get_anomalies",
+            "get_average_temp_by_date",
+            "get_daily_observations",
+            "get_locations",
+            "get_nearest_stations",
+            "get_normals",
+            "get_total_precipitation_by_month",
+            "get_yearly_precipitation",
+        ]
+    );
+
+    let temperature = tools.iter().find(|t| t.name == "get_average_temp_by_date").unwrap();
+    assert!(temperature.description.starts_with("Temperature statistics for a calendar day. "));
+    let schema = &temperature.input_schema;
+    assert_eq!(schema["type"], "object");
+    assert_eq!(schema["required"], serde_json::json!(["day", "month", "location"]));
+    assert_eq!(schema["properties"]["day"]["description"], "Day of the month (1-31)");
+    assert_eq!(schema["properties"]["as_of"]["format"], "date");
+    // Component schemas are inlined
+    assert_eq!(schema["properties"]["units"]["enum"], serde_json::json!(["metric", "imperial", "raw"]));
+    assert!(!serde_json::to_string(&tools).unwrap().contains("$ref"));
+}
+
+/// The weather MCP server takes its climate tool schemas from a copy of
+/// `api openapi --mcp-tools`; regenerate it when this fails.
+#[test]
+fn matches_the_tools_of_the_weather_server() {
+    let generated = serde_json::to_value(mcp_tools(&ApiDoc::openapi())).unwrap();
+    let weather: Value =
+        serde_json::from_str(include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/../weather/climate_tools.json"))).unwrap();
+    assert!(
+        generated == weather,
+        "weather/climate_tools.json is out of date: run `cargo run -- openapi --mcp-tools > ../weather/climate_tools.json`"
+    );
+}
@@ -0,0 +1,139 @@
+use axum::http::StatusCode;
+use serde_json::json;
+
+use super::{date, TestApp};
+use crate::models::{PrecipitationResponse, YearlyPrecipitationResponse};
+
+#[tokio::test]
+async fn totals_a_month_per_year() {
+    let app = TestApp::new();
+    app.day("Oakland", date(2020, 6, 1), json!([{"PRCP": 100}]))
+        .day("Oakland", date(2020, 6, 30), json!([{"PRCP": 25}, {"TMAX": 200}]))
+        .day("Oakland", date(2020, 7, 1), json!([{"PRCP": 999}]))
+        .day("Oakland", date(2021, 6, 15), json!([{"PRCP": 0}]));
+    // Every station's precipitation counts towards the total
+    app.repo.insert_day("Oakland", "USC00046336", date(2020, 6, 30), json!([{"PRCP": 50}]));
+
+    let response: PrecipitationResponse = app
+        .get("/get_total_precipitation_by_month?location=Oakland&month=6&start_year=2019&end_year=2021")
+        .await
+        .json();
+    assert_eq!(response.precipitation_by_year.len(), 2);
+    assert_eq!(response.precipitation_by_year[&2020], 17.5);
+    assert_eq!(response.precipitation_by_year[&2021], 0.0);
+    assert_eq!((response.samples_requested, response.samples_found), (3, 2));
+    assert_eq!(response.precipitation_unit, "mm");
+}
+
+#[tokio::test]
+async fn reads_array_and_object_rows_alike() {
+    let app = TestApp::new();
+    // Array of single-element objects, as ingested
+    app.day("Oakland", date(2020, 6, 1), json!([{"PRCP": 10}, {"TMAX": 200}]))
+        // Plain object with several elements
+        .day("Oakland", date(2020, 6, 2), json!({"PRCP": 20, "TMAX": 210}))
+        // String-encoded values in both shapes
+        .day("Oakland", date(2020, 6, 3), json!([{"PRCP": "30"}]))
+        .day("Oakland", date(2020, 6, 4), json!({"PRCP": "40.5"}))
+        // Neither an array nor an object, or without a numeric value: no precipitation
+        .day("Oakland", date(2020, 6, 5), json!("PRCP"))
+        .day("Oakland", date(2020, 6, 6), json!([{"PRCP": "T"}, 7]))
+        .day("Oakland", date(2020, 6, 7), json!([]));
+
+    let monthly: PrecipitationResponse = app
+        .get("/get_total_precipitation_by_month?location=Oakland&month=6&start_year=2020&end_year=2020&units=raw")
+        .await
+        .json();
+    assert_eq!(monthly.precipitation_by_year[&2020], 100.5);
+
+    let yearly: YearlyPrecipitationResponse = app
+        .get("/get_yearly_precipitation?location=Oakland&start_year=2020&end_year=2020&units=raw")
+        .await
+        .json();
+    assert_eq!(yearly.yearly_precipitation[&2020], 100.5);
+}
+
+#[tokio::test]
+async fn counts_values_rejected_by_quality_control() {
+    let app = TestApp::new();
+    app.day("Oakland", date(2020, 6, 1), json!([{"PRCP": 100, "qflag": "D"}]))
+        .day("Oakland", date(2020, 6, 2), json!({"PRCP": "50", "qflag": "S", "sflag": "7"}))
+        .day("Oakland", date(2020, 6, 3), json!([{"PRCP": 10, "mflag": "T"}]));
+    let uri = "/get_total_precipitation_by_month?location=Oakland&month=6&start_year=2020&end_year=2020&units=raw";
+
+    let strict: PrecipitationResponse = app.get(uri).await.json();
+    assert_eq!((strict.precipitation_by_year[&2020], strict.values_dropped), (10.0, 2));
+
+    let lenient: PrecipitationResponse = app.get(&format!("{}&qc=lenient", uri)).await.json();
+    assert_eq!((lenient.precipitation_by_year[&2020], lenient.values_dropped), (60.0, 1));
+
+    let none: YearlyPrecipitationResponse = app
+        .get("/get_yearly_precipitation?location=Oakland&start_year=2020&end_year=2020&units=raw&qc=none")
+        .await
+        .json();
+    assert_eq!((none.yearly_precipitation[&2020], none.values_dropped), (160.0, 0));
+}
+
+#[tokio::test]
+async fn totals_years_across_stations() {
+    let app = TestApp::new();
+    app.day("Oakland", date(2019, 1, 1), json!([{"PRCP": 10}]))
+        .day("Oakland", date(2019, 12, 31), json!([{"PRCP": 20}]))
+        .day("Oakland", date(2020, 2, 29), json!([{"TMAX": 150}]));
+    app.repo.insert_day("Oakland", "USC00046336", date(2019, 1, 1), json!([{"PRCP": 5}]));
+
+    let response: YearlyPrecipitationResponse = app
+        .get("/get_yearly_precipitation?location=Oakland&samples=3&as_of=2021-06-01&units=imperial")
+        .await
+        .json();
+    assert_eq!((response.start_year, response.end_year, response.samples), (2019, 2021, 3));
+    assert_eq!(response.samples_found, 2);
+    assert!((response.yearly_precipitation[&2019] - 3.5 / 25.4).abs() < 1e-9);
+    assert_eq!(response.yearly_precipitation[&2020], 0.0);
+    assert_eq!(response.precipitation_unit, "in");
+}
+
+#[tokio::test]
+async fn handles_empty_results() {
+    let app = TestApp::new();
+    app.day("Oakland", date(2020, 6, 1), json!([{"PRCP": 100}]));
+
+    // A month without rows is an empty, successful answer
+    let response = app
+        .get("/get_total_precipitation_by_month?location=Oakland&month=1&start_year=2020&end_year=2020")
+        .await;
+    response.assert_status(StatusCode::OK);
+    let monthly: PrecipitationResponse = response.json();
+    assert!(monthly.precipitation_by_year.is_empty());
+    assert_eq!(monthly.samples_found, 0);
+
+    // Years without rows are not
+    let response = app
+        .get("/get_yearly_precipitation?location=Oakland&start_year=2000&end_year=2010")
+        .await;
+    response.assert_status(StatusCode::NOT_FOUND);
+    assert!(response.body.contains("No precipitation data"));
+}
+
+#[tokio::test]
+async fn rejects_invalid_parameters() {
+    let app = TestApp::new();
+    for (uri, message) in [
+        ("/get_total_precipitation_by_month?location=Oakland&month=0&samples=5", "Month must be between 1 and 12"),
+        ("/get_total_precipitation_by_month?location=Oakland&month=13&samples=5", "Month must be between 1 and 12"),
+        ("/get_total_precipitation_by_month?location=Oakland&month=6", "Either samples or start_year"),
+        ("/get_yearly_precipitation?location=Oakland&samples=0", "Samples must be greater than 0"),
+        ("/get_yearly_precipitation?location=Oakland&start_year=2020&end_year=2019", "start_year must not be after end_year"),
+    ] {
+        let response = app.get(uri).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        assert!(response.body.contains(message), "{}: {}", uri, response.body);
+    }
+
+    for uri in [
+        "/get_total_precipitation_by_month?month=6&samples=5",
+        "/get_yearly_precipitation?location=Oakland&samples=-1",
+    ] {
+        app.get(uri).await.assert_status(StatusCode::BAD_REQUEST);
+    }
+}
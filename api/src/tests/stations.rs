@@ -0,0 +1,149 @@
+use axum::http::StatusCode;
+
+use super::TestApp;
+use crate::models::NearestStationsResponse;
+use crate::observations::Element;
+use crate::stations::{ElementPeriod, Station};
+
+fn station(station_id: &str, latitude: f64, longitude: f64, periods: &[(Element, i32, i32)]) -> Station {
+    Station {
+        station_id: station_id.to_string(),
+        name: station_id.to_string(),
+        latitude,
+        longitude,
+        elevation: Some(10.0),
+        state: Some("CA".to_string()),
+        gsn_flag: None,
+        hcn_crn_flag: None,
+        wmo_id: None,
+        elements: periods
+            .iter()
+            .map(|(element, first_year, last_year)| ElementPeriod {
+                element: element.clone(),
+                first_year: *first_year,
+                last_year: *last_year,
+            })
+            .collect(),
+    }
+}
+
+/// Stations around Oakland (37.80, -122.27).
+fn app() -> TestApp {
+    let app = TestApp::new();
+    let full = [(Element::Tmax, 1950, 2024), (Element::Tmin, 1950, 2024), (Element::Prcp, 1950, 2024)];
+    // ~3 km away, recording since 2016 only
+    app.repo.insert_station(station("USC00000001", 37.82, -122.29, &[
+        (Element::Tmax, 2016, 2024),
+        (Element::Tmin, 2016, 2024),
+        (Element::Prcp, 2016, 2024),
+    ]));
+    // ~12 km away, complete
+    app.repo.insert_station(station("USW00000002", 37.72, -122.36, &full));
+    // ~25 km away, precipitation only
+    app.repo.insert_station(station("USC00000003", 37.60, -122.20, &[(Element::Prcp, 1950, 2024)]));
+    // ~5 km away, nothing in the coverage period
+    app.repo.insert_station(station("USC00000004", 37.84, -122.25, &[(Element::Tmax, 1900, 1950)]));
+    // ~130 km away
+    app.repo.insert_station(station("USW00000005", 38.70, -121.30, &full));
+    app
+}
+
+#[tokio::test]
+async fn ranks_stations_by_coverage_weighted_distance() {
+    let app = app();
+    let response: NearestStationsResponse = app
+        .get("/get_nearest_stations?latitude=37.80&longitude=-122.27&start_year=1991&end_year=2020")
+        .await
+        .json();
+
+    let ids: Vec<_> = response.stations.iter().map(|s| s.station_id.as_str()).collect();
+    assert_eq!(ids, ["USW00000002", "USC00000001", "USC00000003", "USC00000004"]);
+    assert_eq!(response.elements, [Element::Tmax, Element::Tmin, Element::Prcp]);
+    assert_eq!((response.start_year, response.end_year), (1991, 2020));
+    assert_eq!(response.max_distance_km, 100.0);
+
+    let complete = &response.stations[0];
+    assert_eq!(complete.coverage, 1.0);
+    assert_eq!(complete.effective_distance_km, Some(complete.distance_km));
+
+    let partial = &response.stations[1];
+    assert!((partial.coverage - 5.0 / 30.0).abs() < 1e-9);
+
+    let precipitation_only = &response.stations[2];
+    assert!((precipitation_only.coverage - 1.0 / 3.0).abs() < 1e-9);
+
+    // Without coverage the station comes last and has no effective distance
+    let uncovered = &response.stations[3];
+    assert_eq!(uncovered.coverage, 0.0);
+    assert_eq!(uncovered.effective_distance_km, None);
+    assert_eq!(uncovered.elements.len(), 1);
+}
+
+#[tokio::test]
+async fn limits_by_elements_radius_and_count() {
+    let app = app();
+    let response: NearestStationsResponse = app
+        .get("/get_nearest_stations?latitude=37.80&longitude=-122.27&elements=prcp&start_year=1991&end_year=2020&limit=3")
+        .await
+        .json();
+    // The TMAX-only station has no PRCP coverage and falls off the list
+    let ids: Vec<_> = response.stations.iter().map(|s| s.station_id.as_str()).collect();
+    assert_eq!(ids, ["USW00000002", "USC00000001", "USC00000003"]);
+    assert_eq!(response.elements, [Element::Prcp]);
+
+    let response: NearestStationsResponse = app
+        .get("/get_nearest_stations?latitude=37.80&longitude=-122.27&max_distance_km=200&limit=100")
+        .await
+        .json();
+    assert_eq!(response.stations.len(), 5);
+
+    let response: NearestStationsResponse = app
+        .get("/get_nearest_stations?latitude=37.80&longitude=-122.27&max_distance_km=4")
+        .await
+        .json();
+    let ids: Vec<_> = response.stations.iter().map(|s| s.station_id.as_str()).collect();
+    assert_eq!(ids, ["USC00000001"]);
+}
+
+#[tokio::test]
+async fn returns_no_stations_far_from_any() {
+    let app = app();
+    let response = app.get("/get_nearest_stations?latitude=0&longitude=0").await;
+    response.assert_status(StatusCode::OK);
+    assert!(response.json::<NearestStationsResponse>().stations.is_empty());
+}
+
+#[tokio::test]
+async fn searches_across_the_antimeridian() {
+    let app = TestApp::new();
+    app.repo.insert_station(station("FJ000000001", -17.0, 179.9, &[(Element::Tmax, 2000, 2020)]));
+    app.repo.insert_station(station("FJ000000002", -17.0, -179.9, &[(Element::Tmax, 2000, 2020)]));
+    let response: NearestStationsResponse = app
+        .get("/get_nearest_stations?latitude=-17&longitude=180&elements=TMAX")
+        .await
+        .json();
+    assert_eq!(response.stations.len(), 2);
+}
+
+#[tokio::test]
+async fn rejects_invalid_parameters() {
+    let app = app();
+    for (query, message) in [
+        ("latitude=90.5&longitude=0", "Latitude must be between -90 and 90"),
+        ("latitude=0&longitude=200", "Longitude must be between -180 and 180"),
+        ("latitude=0&longitude=0&limit=0", "Limit must be between 1 and 100"),
+        ("latitude=0&longitude=0&limit=101", "Limit must be between 1 and 100"),
+        ("latitude=0&longitude=0&max_distance_km=0", "max_distance_km must be greater than 0 and at most 1000"),
+        ("latitude=0&longitude=0&max_distance_km=1001", "max_distance_km must be greater than 0 and at most 1000"),
+        ("latitude=0&longitude=0&max_distance_km=NaN", "max_distance_km must be greater than 0 and at most 1000"),
+        ("latitude=0&longitude=0&elements=", "Elements must list at least one element code"),
+        ("latitude=0&longitude=0&start_year=2020&end_year=2000", "start_year must not be after end_year"),
+    ] {
+        let response = app.get(&format!("/get_nearest_stations?{}", query)).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        assert!(response.body.contains(message), "{}: {}", query, response.body);
+    }
+    app.get("/get_nearest_stations?latitude=0")
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+}
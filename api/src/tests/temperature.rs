@@ -0,0 +1,171 @@
+use axum::http::StatusCode;
+use serde_json::json;
+
+use super::{date, TestApp};
+use crate::models::TemperatureResponse;
+
+const RANGE: &str = "location=Oakland&month=7&day=4&start_year=2018&end_year=2022";
+
+/// July 4th of 2018-2022 with every mix of TMAX, TMIN and TAVG.
+fn app() -> TestApp {
+    let app = TestApp::new();
+    app.day("Oakland", date(2018, 7, 4), json!([{"TMAX": 250}, {"TMIN": 120}, {"TAVG": 185}]))
+        .day("Oakland", date(2019, 7, 4), json!([{"TMAX": 270}, {"TMIN": 140}]))
+        .day("Oakland", date(2020, 7, 4), json!([{"TAVG": 200}]))
+        .day("Oakland", date(2021, 7, 4), json!([{"TMIN": 100}, {"PRCP": 0}]))
+        .day("Oakland", date(2022, 7, 4), json!([{"PRCP": 30}]))
+        // Other days and locations stay out
+        .day("Oakland", date(2020, 7, 5), json!([{"TMAX": 999}]));
+    app.repo.insert_day("Seasonal", "USC00046336", date(2020, 7, 4), json!([{"TMAX": 999}]));
+    app
+}
+
+#[tokio::test]
+async fn summarises_mixed_elements_independently() {
+    let app = app();
+    let response: TemperatureResponse = app.get(&format!("/get_average_temp_by_date?{}", RANGE)).await.json();
+
+    assert_eq!((response.start_year, response.end_year), (2018, 2022));
+    assert_eq!(response.samples_requested, 5);
+    // 2022 only has PRCP
+    assert_eq!(response.samples_found, 4);
+    assert_eq!(response.temperature_unit, "°C");
+
+    let tmax = response.tmax.unwrap();
+    assert_eq!(tmax.count, 2);
+    assert_eq!((tmax.min, tmax.min_year, tmax.max, tmax.max_year), (25.0, 2018, 27.0, 2019));
+    assert_eq!(tmax.mean, 26.0);
+
+    let tmin = response.tmin.unwrap();
+    assert_eq!(tmin.count, 3);
+    assert_eq!(tmin.min, 10.0);
+
+    // TAVG is what the stations reported, never derived from TMAX/TMIN
+    let tavg = response.tavg.unwrap();
+    let years: Vec<i32> = tavg.values.iter().map(|v| v.year).collect();
+    assert_eq!(years, [2018, 2020]);
+    assert_eq!(tavg.mean, 19.25);
+}
+
+#[tokio::test]
+async fn omits_elements_without_values() {
+    let app = TestApp::new();
+    app.day("Oakland", date(2020, 1, 1), json!([{"TMAX": 150}]));
+    let response = app
+        .get("/get_average_temp_by_date?location=Oakland&month=1&day=1&samples=1&end_year=2020")
+        .await;
+    response.assert_status(StatusCode::OK);
+    let body: serde_json::Value = response.json();
+    assert_eq!(body["tmax"]["count"], 1);
+    assert!(body["tmin"].is_null());
+    assert!(body["tavg"].is_null());
+}
+
+#[tokio::test]
+async fn reads_string_encoded_values_and_object_rows() {
+    let app = TestApp::new();
+    app.day("Oakland", date(2020, 3, 1), json!([{"TMAX": "212"}, {"TMIN": " 85 "}]))
+        .day("Oakland", date(2021, 3, 1), json!({"TMAX": 230, "TMIN": "90"}))
+        .day("Oakland", date(2022, 3, 1), json!([{"TMAX": "n/a"}]));
+
+    let response: TemperatureResponse = app
+        .get("/get_average_temp_by_date?location=Oakland&month=3&day=1&start_year=2020&end_year=2022&units=raw")
+        .await
+        .json();
+    let tmax: Vec<f64> = response.tmax.unwrap().values.iter().map(|v| v.value).collect();
+    assert_eq!(tmax, [212.0, 230.0]);
+    assert_eq!(response.tmin.unwrap().mean, 87.5);
+    assert_eq!(response.samples_found, 2);
+}
+
+#[tokio::test]
+async fn converts_units_and_reports_percentiles() {
+    let app = app();
+    let response: TemperatureResponse = app
+        .get(&format!("/get_average_temp_by_date?{}&units=imperial&percentiles=50,0", RANGE))
+        .await
+        .json();
+    assert_eq!(response.temperature_unit, "°F");
+    assert_eq!(response.percentiles, [0.0, 50.0]);
+    let tmax = response.tmax.unwrap();
+    assert!((tmax.max - 80.6).abs() < 1e-9);
+    assert_eq!(tmax.percentiles.keys().collect::<Vec<_>>(), ["p0", "p50"]);
+}
+
+#[tokio::test]
+async fn applies_quality_control() {
+    let app = TestApp::new();
+    app.day("Oakland", date(2020, 7, 4), json!([{"TMAX": 250}]))
+        .day("Oakland", date(2021, 7, 4), json!([{"TMAX": 450, "qflag": "O"}]))
+        .day("Oakland", date(2022, 7, 4), json!([{"TMAX": 900, "qflag": "X"}]));
+    let uri = "/get_average_temp_by_date?location=Oakland&month=7&day=4&start_year=2020&end_year=2022";
+
+    let strict: TemperatureResponse = app.get(uri).await.json();
+    assert_eq!((strict.tmax.unwrap().count, strict.values_dropped), (1, 2));
+
+    let lenient: TemperatureResponse = app.get(&format!("{}&qc=lenient", uri)).await.json();
+    assert_eq!((lenient.tmax.unwrap().count, lenient.values_dropped), (2, 1));
+
+    let none: TemperatureResponse = app.get(&format!("{}&qc=none", uri)).await.json();
+    assert_eq!((none.tmax.unwrap().count, none.values_dropped), (3, 0));
+
+    // Nothing left after quality control
+    let app = TestApp::new();
+    app.day("Oakland", date(2022, 7, 4), json!([{"TMAX": 900, "qflag": "X"}]));
+    app.get(uri).await.assert_status(StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn resolves_year_ranges_from_as_of() {
+    let app = app();
+    let response: TemperatureResponse = app
+        .get("/get_average_temp_by_date?location=Oakland&month=7&day=4&samples=3&as_of=2020-01-15")
+        .await
+        .json();
+    assert_eq!((response.start_year, response.end_year), (2018, 2020));
+    assert_eq!(response.as_of, date(2020, 1, 15));
+    assert_eq!(response.samples_found, 3);
+}
+
+#[tokio::test]
+async fn returns_not_found_without_data() {
+    let app = app();
+    let response = app
+        .get("/get_average_temp_by_date?location=Nowhere&month=7&day=4&start_year=2018&end_year=2022")
+        .await;
+    response.assert_status(StatusCode::NOT_FOUND);
+    assert!(response.body.contains("No temperature data"));
+}
+
+#[tokio::test]
+async fn rejects_invalid_parameters() {
+    let app = app();
+    for (query, message) in [
+        ("location=Oakland&month=0&day=4&samples=5", "Month must be between 1 and 12"),
+        ("location=Oakland&month=13&day=4&samples=5", "Month must be between 1 and 12"),
+        ("location=Oakland&month=7&day=0&samples=5", "Day must be between 1 and 31"),
+        ("location=Oakland&month=7&day=32&samples=5", "Day must be between 1 and 31"),
+        ("location=Oakland&month=7&day=4&samples=0", "Samples must be greater than 0"),
+        ("location=Oakland&month=7&day=4", "Either samples or start_year must be provided"),
+        ("location=Oakland&month=7&day=4&start_year=2022&end_year=2018", "start_year must not be after end_year"),
+        ("location=Oakland&month=7&day=4&samples=5&percentiles=10,101", "Invalid percentile: 101"),
+        ("location=Oakland&month=7&day=4&samples=5&percentiles=ten", "Invalid percentile: ten"),
+    ] {
+        let response = app.get(&format!("/get_average_temp_by_date?{}", query)).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        assert!(response.body.contains(message), "{}: {}", query, response.body);
+    }
+
+    // Missing or malformed parameters are rejected by the query extractor
+    for query in [
+        "month=7&day=4&samples=5",
+        "location=Oakland&month=July&day=4&samples=5",
+        "location=Oakland&month=7&day=4&samples=5&units=kelvin",
+        "location=Oakland&month=7&day=4&samples=5&qc=loose",
+        "location=Oakland&month=7&day=4&samples=5&as_of=yesterday",
+    ] {
+        app.get(&format!("/get_average_temp_by_date?{}", query))
+            .await
+            .assert_status(StatusCode::BAD_REQUEST);
+    }
+}
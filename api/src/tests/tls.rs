@@ -0,0 +1,104 @@
+//! Handshakes of [`db::tls::connector`] with a local TLS server presenting the
+//! certificates of `certs/`: `server.pem` (for `localhost` and `127.0.0.1`)
+//! and `client.pem` are issued by `ca.pem`; `other-ca.pem` issued neither.
+
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{RootCertStore, ServerConfig};
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::net::{TcpListener, TcpStream};
+use tokio_postgres::tls::{MakeTlsConnect, TlsConnect};
+use tokio_rustls::TlsAcceptor;
+
+use crate::config::{DatabaseConfig, SslMode, TlsConfig};
+use crate::db;
+
+fn cert(name: &str) -> PathBuf {
+    PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/src/tests/certs")).join(name)
+}
+
+fn tls(mode: SslMode, ca_file: Option<&str>) -> TlsConfig {
+    TlsConfig {
+        mode,
+        ca_file: ca_file.map(cert),
+        ..TlsConfig::default()
+    }
+}
+
+/// Connect to a server presenting `server.pem` as `host`, returning whether
+/// the client and the server completed the handshake.
+async fn handshake(config: &TlsConfig, host: &str, require_client_cert: bool) -> (bool, bool) {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let builder = ServerConfig::builder_with_provider(provider.clone()).with_safe_default_protocol_versions().unwrap();
+    let builder = if require_client_cert {
+        let mut roots = RootCertStore::empty();
+        roots.add(CertificateDer::from_pem_file(cert("ca.pem")).unwrap()).unwrap();
+        builder.with_client_cert_verifier(
+            WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider).build().unwrap(),
+        )
+    } else {
+        builder.with_no_client_auth()
+    };
+    let server_config = builder
+        .with_single_cert(
+            vec![CertificateDer::from_pem_file(cert("server.pem")).unwrap()],
+            PrivateKeyDer::from_pem_file(cert("server.key")).unwrap(),
+        )
+        .unwrap();
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        TlsAcceptor::from(Arc::new(server_config)).accept(stream).await.is_ok()
+    });
+
+    let mut connector = db::tls::connector(config).unwrap();
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let connect = MakeTlsConnect::<TcpStream>::make_tls_connect(&mut connector, host).unwrap();
+    let client = connect.connect(stream).await.is_ok();
+    (client, server.await.unwrap())
+}
+
+#[tokio::test]
+async fn checks_the_server_certificate_as_sslmode_does() {
+    // Any certificate, without a CA bundle
+    assert!(handshake(&tls(SslMode::Require, None), "db.example", false).await.0);
+    // With one, the chain but not the name
+    assert!(handshake(&tls(SslMode::Require, Some("ca.pem")), "db.example", false).await.0);
+    assert!(!handshake(&tls(SslMode::Prefer, Some("other-ca.pem")), "localhost", false).await.0);
+    // Both
+    assert!(handshake(&tls(SslMode::VerifyFull, Some("ca.pem")), "localhost", false).await.0);
+    assert!(handshake(&tls(SslMode::VerifyFull, Some("ca.pem")), "127.0.0.1", false).await.0);
+    assert!(!handshake(&tls(SslMode::VerifyFull, Some("ca.pem")), "db.example", false).await.0);
+    assert!(!handshake(&tls(SslMode::VerifyFull, Some("other-ca.pem")), "localhost", false).await.0);
+}
+
+#[tokio::test]
+async fn presents_the_client_certificate() {
+    let mut config = tls(SslMode::VerifyFull, Some("ca.pem"));
+    assert!(!handshake(&config, "localhost", true).await.1);
+
+    config.cert_file = Some(cert("client.pem"));
+    config.key_file = Some(cert("client.key"));
+    assert_eq!(handshake(&config, "localhost", true).await, (true, true));
+}
+
+/// Needs a Postgres at `DB_HOST` (default `localhost`) serving `server.pem`,
+/// see "Encrypting connections to Postgres" in the README.
+#[tokio::test]
+#[ignore = "needs Postgres with TLS"]
+async fn connects_to_postgres_over_tls() {
+    let config = DatabaseConfig {
+        host: Some(std::env::var("DB_HOST").unwrap_or_else(|_| "localhost".to_string())),
+        user: Some(std::env::var("DB_USER").unwrap_or_else(|_| "postgres".to_string())),
+        password: std::env::var("DB_PASSWORD").ok(),
+        tls: tls(SslMode::VerifyFull, Some("ca.pem")),
+        ..DatabaseConfig::default()
+    };
+    let client = db::create_pool(&config).unwrap().get().await.unwrap();
+    let row = client.query_one("SELECT ssl FROM pg_stat_ssl WHERE pid = pg_backend_pid()", &[]).await.unwrap();
+    assert!(row.get::<_, bool>(0));
+}
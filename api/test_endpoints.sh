@@ -1,7 +1,8 @@
 #!/bin/bash
 
 # Test script for API endpoints using HTTPie
-# Make sure the API server is running on localhost:3000 before running these commands
+# Make sure the API server is running on localhost:3000 before running these commands,
+# with DISABLE_AUTH=true as they send no API key
 
 echo "=== Testing API Endpoints ==="
 echo
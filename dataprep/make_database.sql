@@ -0,0 +1,4 @@
+CREATE DATABASE mcpdb;
+
+-- The tables are created and upgraded by the api's embedded migrations:
+--   cd ../api && cargo run -- migrate
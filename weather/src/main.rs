@@ -5,8 +5,11 @@ use rmcp::{
     model::*,
     schemars, tool, tool_handler, tool_router,
 };
-use serde::{Deserialize};
+use serde::{Deserialize, Serialize};
 use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Arc;
 
 #[derive(serde::Deserialize, schemars::JsonSchema)]
 pub struct MCPForecastRequest {
@@ -19,8 +22,43 @@ pub struct MCPAlertRequest {
     state: String,
 }
 
+/// MCP tool definitions of the climate API's read-only endpoints, printed by
+/// `api openapi --mcp-tools`. The climate tools take their input schemas from
+/// here; regenerate it from `api/` when the endpoints' parameters change:
+/// `cargo run -- openapi --mcp-tools > ../weather/climate_tools.json`.
+const CLIMATE_TOOLS: &str = include_str!("../climate_tools.json");
+
+#[derive(Debug, Deserialize)]
+struct ClimateTool {
+    name: String,
+    #[serde(rename = "inputSchema")]
+    input_schema: JsonObject,
+}
+
 const NWS_API_BASE: &str = "https://api.weather.gov";
 const USER_AGENT: &str = "weather-app/1.0";
+const DEFAULT_CLIMATE_API_BASE: &str = "http://localhost:3000";
+
+/// Error response of the climate API (RFC 7807 problem details).
+#[derive(Debug, Deserialize)]
+struct Problem {
+    status: u16,
+    detail: String,
+    code: String,
+    parameter: Option<String>,
+}
+
+impl std::fmt::Display for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Climate API error {} ({}): {}", self.status, self.code, self.detail)?;
+        if let Some(parameter) = &self.parameter {
+            write!(f, " [parameter: {parameter}]")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Problem {}
 
 #[derive(Debug, Deserialize)]
 struct AlertsResponse {
@@ -76,6 +114,89 @@ struct ForecastPeriod {
     detailed_forecast: String,
 }
 
+#[derive(Debug, Deserialize)]
+struct LocationResponse {
+    location: String,
+    display_name: Option<String>,
+    latitude: Option<f64>,
+    longitude: Option<f64>,
+    elevation: Option<f64>,
+    country: Option<String>,
+    timezone: Option<String>,
+    #[serde(default)]
+    station_ids: Vec<String>,
+    distance_km: Option<f64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TemperatureResponse {
+    day: u32,
+    month: u32,
+    start_year: i32,
+    end_year: i32,
+    samples_requested: u32,
+    samples_found: u32,
+    temperature_unit: String,
+    tmax: Option<TemperatureSummary>,
+    tmin: Option<TemperatureSummary>,
+    tavg: Option<TemperatureSummary>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TemperatureSummary {
+    count: u32,
+    mean: f64,
+    min: f64,
+    min_year: i32,
+    max: f64,
+    max_year: i32,
+    median: f64,
+    std_dev: Option<f64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PrecipitationResponse {
+    month: u32,
+    start_year: i32,
+    end_year: i32,
+    samples_requested: u32,
+    samples_found: u32,
+    precipitation_unit: String,
+    precipitation_by_year: HashMap<i32, f64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct YearlyPrecipitationResponse {
+    start_year: i32,
+    end_year: i32,
+    samples: u32,
+    samples_found: u32,
+    precipitation_unit: String,
+    yearly_precipitation: HashMap<i32, f64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct AnomalyResponse {
+    start_date: String,
+    end_date: String,
+    base_start_year: i32,
+    base_end_year: i32,
+    temperature_unit: String,
+    precipitation_unit: String,
+    summary: AnomalySummary,
+}
+
+#[derive(Debug, Deserialize)]
+struct AnomalySummary {
+    days_with_data: u32,
+    tmax_departure: Option<f64>,
+    tmin_departure: Option<f64>,
+    mean_temperature_departure: Option<f64>,
+    prcp_observed: Option<f64>,
+    prcp_normal: Option<f64>,
+    prcp_percent_of_normal: Option<f64>,
+}
+
 async fn make_nws_request<T: DeserializeOwned>(url: &str) -> Result<T> {
     let client = reqwest::Client::new();
     let rsp = client
@@ -88,6 +209,72 @@ async fn make_nws_request<T: DeserializeOwned>(url: &str) -> Result<T> {
     Ok(rsp.json::<T>().await?)
 }
 
+async fn make_climate_request<T: DeserializeOwned, Q: Serialize>(
+    url: &str,
+    query: &Q,
+    api_key: Option<&str>,
+) -> Result<T> {
+    let client = reqwest::Client::new();
+    let mut request = client
+        .get(url)
+        .query(query)
+        .header(reqwest::header::USER_AGENT, USER_AGENT)
+        .header(reqwest::header::ACCEPT, "application/json");
+    if let Some(key) = api_key {
+        request = request.bearer_auth(key);
+    }
+    let rsp = request.send().await?;
+    let status = rsp.status();
+    if !status.is_success() {
+        // Error bodies are RFC 7807 problem details; keep them for the agent
+        let body = rsp.text().await.unwrap_or_default();
+        return Err(match serde_json::from_str::<Problem>(&body) {
+            Ok(problem) => problem.into(),
+            Err(_) => anyhow::anyhow!("HTTP {status}"),
+        });
+    }
+    Ok(rsp.json::<T>().await?)
+}
+
+/// Tool text for a failed climate API call: the API's explanation when it
+/// gave one, otherwise `fallback` with the cause.
+fn climate_error(fallback: &str, error: &anyhow::Error) -> String {
+    match error.downcast_ref::<Problem>() {
+        Some(problem) => problem.to_string(),
+        None => format!("{fallback} ({error})"),
+    }
+}
+
+/// Give the climate tools of `router` the input schemas of the endpoints they call.
+fn use_climate_schemas(router: &mut ToolRouter<Weather>) {
+    let tools: Vec<ClimateTool> =
+        serde_json::from_str(CLIMATE_TOOLS).expect("climate_tools.json holds MCP tool definitions");
+    for tool in tools {
+        if let Some(route) = router.map.get_mut(tool.name.as_str()) {
+            route.attr.input_schema = Arc::new(tool.input_schema);
+        }
+    }
+}
+
+/// Query parameters of a climate tool call, passed on as the API's text values.
+fn query_pairs(arguments: &JsonObject) -> Vec<(&str, String)> {
+    arguments
+        .iter()
+        .filter_map(|(name, value)| match value {
+            Value::Null => None,
+            Value::String(text) => Some((name.as_str(), text.clone())),
+            other => Some((name.as_str(), other.to_string())),
+        })
+        .collect()
+}
+
+fn location(arguments: &JsonObject) -> &str {
+    arguments
+        .get("location")
+        .and_then(Value::as_str)
+        .unwrap_or_default()
+}
+
 fn format_alert(feature: &AlertFeature) -> String {
     let props = &feature.properties;
     format!(
@@ -118,18 +305,173 @@ fn format_period(period: &ForecastPeriod) -> String {
     )
 }
 
+fn format_by_year(values: &HashMap<i32, f64>) -> String {
+    let mut years: Vec<_> = values.iter().collect();
+    years.sort_by_key(|(year, _)| **year);
+    years
+        .iter()
+        .map(|(year, value)| format!("{year}: {value:.2}"))
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+fn format_temperature_summary(label: &str, summary: Option<&TemperatureSummary>, unit: &str) -> String {
+    let Some(summary) = summary else {
+        return format!("{label}: no data");
+    };
+    let std_dev = summary
+        .std_dev
+        .map(|sd| format!("{sd:.1} {unit}"))
+        .unwrap_or_else(|| "n/a".to_string());
+    format!(
+        "{label} ({} years): mean {:.1} {unit}, median {:.1} {unit}, std dev {std_dev}\n  Record high: {:.1} {unit} in {}\n  Record low: {:.1} {unit} in {}",
+        summary.count,
+        summary.mean,
+        summary.median,
+        summary.max,
+        summary.max_year,
+        summary.min,
+        summary.min_year,
+    )
+}
+
+fn format_temperature(location: &str, data: &TemperatureResponse) -> String {
+    let unit = &data.temperature_unit;
+    format!(
+        "Location: {}\nDate: {}/{}\nYears: {}-{}\nYears found: {} of {}\n{}\n{}\n{}",
+        location,
+        data.month,
+        data.day,
+        data.start_year,
+        data.end_year,
+        data.samples_found,
+        data.samples_requested,
+        format_temperature_summary("Maximum temperature", data.tmax.as_ref(), unit),
+        format_temperature_summary("Minimum temperature", data.tmin.as_ref(), unit),
+        format_temperature_summary("Average temperature", data.tavg.as_ref(), unit),
+    )
+}
+
+fn format_monthly_precipitation(location: &str, data: &PrecipitationResponse) -> String {
+    format!(
+        "Location: {}\nMonth: {}\nYears: {}-{}\nYears found: {} of {}\nTotal precipitation by year ({}):\n{}",
+        location,
+        data.month,
+        data.start_year,
+        data.end_year,
+        data.samples_found,
+        data.samples_requested,
+        data.precipitation_unit,
+        format_by_year(&data.precipitation_by_year)
+    )
+}
+
+fn format_yearly_precipitation(location: &str, data: &YearlyPrecipitationResponse) -> String {
+    format!(
+        "Location: {}\nYears: {}-{}\nYears found: {} of {}\nTotal precipitation by year ({}):\n{}",
+        location,
+        data.start_year,
+        data.end_year,
+        data.samples_found,
+        data.samples,
+        data.precipitation_unit,
+        format_by_year(&data.yearly_precipitation)
+    )
+}
+
+fn format_location(location: &LocationResponse) -> String {
+    let mut lines = vec![format!("Location: {}", location.location)];
+    if let Some(name) = &location.display_name {
+        lines.push(format!("Name: {}", name));
+    }
+    if let (Some(latitude), Some(longitude)) = (location.latitude, location.longitude) {
+        let elevation = location
+            .elevation
+            .map(|e| format!(", {e:.0} m"))
+            .unwrap_or_default();
+        lines.push(format!("Coordinates: {latitude:.4}, {longitude:.4}{elevation}"));
+    }
+    if let Some(country) = &location.country {
+        lines.push(format!("Country: {}", country));
+    }
+    if let Some(timezone) = &location.timezone {
+        lines.push(format!("Time zone: {}", timezone));
+    }
+    if !location.station_ids.is_empty() {
+        lines.push(format!("Stations: {}", location.station_ids.join(", ")));
+    }
+    if let Some(distance) = location.distance_km {
+        lines.push(format!("Distance: {distance:.1} km"));
+    }
+    lines.join("\n")
+}
+
+fn format_departure(departure: Option<f64>, unit: &str) -> String {
+    match departure {
+        Some(d) if d >= 0.0 => format!("{d:.1} {unit} warmer than normal"),
+        Some(d) => format!("{:.1} {unit} colder than normal", -d),
+        None => "no data".to_string(),
+    }
+}
+
+fn format_anomalies(location: &str, data: &AnomalyResponse) -> String {
+    let summary = &data.summary;
+    let temp_unit = &data.temperature_unit;
+    let prcp_unit = &data.precipitation_unit;
+    let precipitation = match (summary.prcp_observed, summary.prcp_normal) {
+        (Some(observed), Some(normal)) => {
+            let percent = summary
+                .prcp_percent_of_normal
+                .map(|p| format!(" ({p:.0}% of normal)"))
+                .unwrap_or_default();
+            format!("{observed:.2} {prcp_unit} vs normal {normal:.2} {prcp_unit}{percent}")
+        }
+        _ => "no data".to_string(),
+    };
+    format!(
+        "Location: {}\nPeriod: {} to {} ({} days with data)\nBaseline: {}-{} normals\nMean temperature: {}\nMaximum temperature: {}\nMinimum temperature: {}\nPrecipitation: {}",
+        location,
+        data.start_date,
+        data.end_date,
+        summary.days_with_data,
+        data.base_start_year,
+        data.base_end_year,
+        format_departure(summary.mean_temperature_departure, temp_unit),
+        format_departure(summary.tmax_departure, temp_unit),
+        format_departure(summary.tmin_departure, temp_unit),
+        precipitation
+    )
+}
+
 pub struct Weather {
     tool_router: ToolRouter<Weather>,
+    climate_api_base: String,
+    climate_api_key: Option<String>,
 }
 
 #[tool_router]
 impl Weather {
     fn new() -> Self {
+        // Base URL of the historical climate REST API (the `api` crate)
+        let climate_api_base = std::env::var("CLIMATE_API_BASE")
+            .unwrap_or_else(|_| DEFAULT_CLIMATE_API_BASE.to_string());
+        // Read key for the climate API, unless it runs without authentication
+        let climate_api_key = std::env::var("CLIMATE_API_KEY")
+            .ok()
+            .filter(|key| !key.is_empty());
+        let mut tool_router = Self::tool_router();
+        use_climate_schemas(&mut tool_router);
         Self {
-            tool_router: Self::tool_router(),
+            tool_router,
+            climate_api_base: climate_api_base.trim_end_matches('/').to_string(),
+            climate_api_key,
         }
     }
 
+    fn climate_url(&self, endpoint: &str) -> String {
+        format!("{}/{}", self.climate_api_base, endpoint)
+    }
+
     #[tool(description = "Get weather alerts for a US state.")]
     async fn get_alerts(
         &self,
@@ -185,6 +527,71 @@ impl Weather {
             .join("\n---\n");
         forecast_summary
     }
+
+    #[tool(description = "List the locations that have historical daily climate records, with their coordinates and time zone. Give the latitude/longitude of a forecast point to list the closest locations first.")]
+    async fn get_locations(
+        &self,
+        Parameters(arguments): Parameters<JsonObject>,
+    ) -> String {
+        let url = self.climate_url("get_locations");
+        match make_climate_request::<Vec<LocationResponse>, _>(&url, &query_pairs(&arguments), self.climate_api_key.as_deref()).await {
+            Ok(locations) if locations.is_empty() => "No historical locations available.".to_string(),
+            Ok(locations) => locations
+                .iter()
+                .map(format_location)
+                .collect::<Vec<String>>()
+                .join("\n---\n"),
+            Err(e) => climate_error("Unable to fetch historical locations.", &e),
+        }
+    }
+
+    #[tool(description = "Get historical temperature statistics (mean, median, record high/low and the year they occurred) for a calendar day at a location over past years.")]
+    async fn get_average_temp_by_date(
+        &self,
+        Parameters(arguments): Parameters<JsonObject>,
+    ) -> String {
+        let url = self.climate_url("get_average_temp_by_date");
+        match make_climate_request::<TemperatureResponse, _>(&url, &query_pairs(&arguments), self.climate_api_key.as_deref()).await {
+            Ok(data) => format_temperature(location(&arguments), &data),
+            Err(e) => climate_error("Unable to fetch historical temperature data for this date.", &e),
+        }
+    }
+
+    #[tool(description = "Get historical total precipitation for a month at a location, per year.")]
+    async fn get_total_precipitation_by_month(
+        &self,
+        Parameters(arguments): Parameters<JsonObject>,
+    ) -> String {
+        let url = self.climate_url("get_total_precipitation_by_month");
+        match make_climate_request::<PrecipitationResponse, _>(&url, &query_pairs(&arguments), self.climate_api_key.as_deref()).await {
+            Ok(data) => format_monthly_precipitation(location(&arguments), &data),
+            Err(e) => climate_error("Unable to fetch historical precipitation data for this month.", &e),
+        }
+    }
+
+    #[tool(description = "Get historical total precipitation per year at a location.")]
+    async fn get_yearly_precipitation(
+        &self,
+        Parameters(arguments): Parameters<JsonObject>,
+    ) -> String {
+        let url = self.climate_url("get_yearly_precipitation");
+        match make_climate_request::<YearlyPrecipitationResponse, _>(&url, &query_pairs(&arguments), self.climate_api_key.as_deref()).await {
+            Ok(data) => format_yearly_precipitation(location(&arguments), &data),
+            Err(e) => climate_error("Unable to fetch historical yearly precipitation data.", &e),
+        }
+    }
+
+    #[tool(description = "Compare observed temperature and precipitation at a location over a date range with the 1991-2020 climate normals.")]
+    async fn get_anomalies(
+        &self,
+        Parameters(arguments): Parameters<JsonObject>,
+    ) -> String {
+        let url = self.climate_url("get_anomalies");
+        match make_climate_request::<AnomalyResponse, _>(&url, &query_pairs(&arguments), self.climate_api_key.as_deref()).await {
+            Ok(data) => format_anomalies(location(&arguments), &data),
+            Err(e) => climate_error("Unable to fetch climate anomalies for this period.", &e),
+        }
+    }
 }
 
 #[tool_handler]
@@ -203,4 +610,96 @@ async fn main() -> Result<()> {
     let service = Weather::new().serve(transport).await?;
     service.waiting().await?;
     Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn climate_tools_take_the_schemas_of_their_endpoints() {
+        let tools: Vec<ClimateTool> = serde_json::from_str(CLIMATE_TOOLS).unwrap();
+        let schemas: HashMap<_, _> = tools.iter().map(|t| (t.name.as_str(), &t.input_schema)).collect();
+
+        let weather = Weather::new();
+        let mut climate_tools = 0;
+        for tool in weather.tool_router.list_all() {
+            if matches!(tool.name.as_ref(), "get_alerts" | "get_forecast") {
+                continue;
+            }
+            let schema = schemas
+                .get(tool.name.as_ref())
+                .unwrap_or_else(|| panic!("{} is not an endpoint of climate_tools.json", tool.name));
+            assert_eq!(tool.input_schema.as_ref(), *schema, "{}", tool.name);
+            climate_tools += 1;
+        }
+        assert_eq!(climate_tools, 5);
+    }
+
+    /// Answer one request on a local port with `status` and `body`.
+    async fn serve_once(status: &'static str, body: &'static str) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/get_average_temp_by_date", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            use tokio::io::{AsyncReadExt, AsyncWriteExt};
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = [0; 4096];
+            let _ = stream.read(&mut request).await.unwrap();
+            let response = format!(
+                "HTTP/1.1 {status}\r\ncontent-type: application/problem+json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{body}",
+                body.len()
+            );
+            stream.write_all(response.as_bytes()).await.unwrap();
+        });
+        url
+    }
+
+    async fn tool_error(status: &'static str, body: &'static str) -> String {
+        let url = serve_once(status, body).await;
+        let error = make_climate_request::<TemperatureResponse, _>(&url, &[("month", "13")], None)
+            .await
+            .unwrap_err();
+        climate_error("Unable to fetch historical temperature data for this date.", &error)
+    }
+
+    #[tokio::test]
+    async fn reports_the_problems_of_the_climate_api() {
+        assert_eq!(
+            tool_error(
+                "400 Bad Request",
+                r#"{"type":"about:blank","title":"Bad Request","status":400,"detail":"Month must be between 1 and 12","code":"invalid_parameter","parameter":"month"}"#,
+            )
+            .await,
+            "Climate API error 400 (invalid_parameter): Month must be between 1 and 12 [parameter: month]"
+        );
+        assert_eq!(
+            tool_error(
+                "429 Too Many Requests",
+                r#"{"type":"about:blank","title":"Too Many Requests","status":429,"detail":"Rate limit of 120 requests per minute exceeded","code":"rate_limited"}"#,
+            )
+            .await,
+            "Climate API error 429 (rate_limited): Rate limit of 120 requests per minute exceeded"
+        );
+        // Not problem details, e.g. from a proxy
+        assert_eq!(
+            tool_error("502 Bad Gateway", "<html>Bad Gateway</html>").await,
+            "Unable to fetch historical temperature data for this date. (HTTP 502 Bad Gateway)"
+        );
+    }
+
+    #[test]
+    fn passes_arguments_on_as_query_parameters() {
+        let arguments = serde_json::json!({
+            "location": "Oakland",
+            "day": 4,
+            "percentiles": "10,90",
+            "as_of": null,
+        });
+        let arguments = arguments.as_object().unwrap();
+        assert_eq!(
+            query_pairs(arguments),
+            [("day", "4".to_string()), ("location", "Oakland".to_string()), ("percentiles", "10,90".to_string())]
+        );
+        assert_eq!(location(arguments), "Oakland");
+    }
+}